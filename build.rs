@@ -0,0 +1,4 @@
+// Пересборка при изменении миграций, которые встраиваются макросом sqlx::migrate!
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+}
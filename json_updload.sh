@@ -1,21 +1,31 @@
 #!/bin/bash
 
-# Проверяем, что передан путь к файлу
-if [ "$#" -ne 1 ]; then
-    echo "Usage: $0 <path_to_file>"
+# Проверяем, что передан хотя бы один путь к файлу
+if [ "$#" -lt 1 ]; then
+    echo "Usage: $0 <path_to_file> [path_to_file...]"
     exit 1
 fi
 
-# Сохраняем аргумент в переменную
-filename=$1
+# Проверяем, существуют ли файлы
+for filename in "$@"; do
+    if [ ! -f "$filename" ]; then
+        echo "File not found: $filename"
+        exit 1
+    fi
+done
 
-# Проверяем, существует ли файл
-if [ ! -f "$filename" ]; then
-    echo "File not found: $filename"
-    exit 1
+# Один файл отправляем как есть
+if [ "$#" -eq 1 ]; then
+    curl -X POST http://localhost:8081/order \
+         -H 'Content-Type: application/json' \
+         -d @"$1"
+    exit
 fi
 
-# Выполняем запрос curl с файлом
-curl -X POST http://localhost:8081/order \
-     -H 'Content-Type: application/json' \
-     -d @"$filename"
+# Несколько файлов отправляем одним запросом в формате NDJSON: по заказу в строке
+for filename in "$@"; do
+    tr -d '\n' < "$filename"
+    echo
+done | curl -X POST http://localhost:8081/orders/bulk \
+     -H 'Content-Type: application/x-ndjson' \
+     --data-binary @-
@@ -0,0 +1,58 @@
+-- Исходная схема; IF NOT EXISTS позволяет применить миграцию к базам,
+-- таблицы в которых были созданы до появления миграций
+CREATE TABLE IF NOT EXISTS delivery (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    phone VARCHAR(50) NOT NULL,
+    zip VARCHAR(20) NOT NULL,
+    city VARCHAR(100) NOT NULL,
+    address VARCHAR(255) NOT NULL,
+    region VARCHAR(100) NOT NULL,
+    email VARCHAR(100) NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS payment (
+    id SERIAL PRIMARY KEY,
+    transaction VARCHAR(255) NOT NULL,
+    request_id VARCHAR(255) NOT NULL,
+    currency VARCHAR(10) NOT NULL,
+    provider VARCHAR(100) NOT NULL,
+    amount INTEGER NOT NULL,
+    payment_dt BIGINT NOT NULL,
+    bank VARCHAR(100) NOT NULL,
+    delivery_cost INTEGER NOT NULL,
+    goods_total INTEGER NOT NULL,
+    custom_fee INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS orders (
+    order_uid VARCHAR(255) PRIMARY KEY,
+    track_number VARCHAR(255) NOT NULL,
+    entry VARCHAR(255) NOT NULL,
+    delivery_id INTEGER REFERENCES delivery(id) ON DELETE CASCADE,
+    payment_id INTEGER REFERENCES payment(id) ON DELETE CASCADE,
+    locale VARCHAR(10) NOT NULL,
+    internal_signature VARCHAR(255) NOT NULL,
+    customer_id VARCHAR(255) NOT NULL,
+    delivery_service VARCHAR(100) NOT NULL,
+    shardkey VARCHAR(50) NOT NULL,
+    sm_id INTEGER NOT NULL,
+    date_created VARCHAR(50) NOT NULL,
+    oof_shard VARCHAR(50) NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS item (
+    id SERIAL PRIMARY KEY,
+    chrt_id INTEGER NOT NULL,
+    track_number VARCHAR(255) NOT NULL,
+    price INTEGER NOT NULL,
+    rid VARCHAR(255) NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    sale INTEGER NOT NULL,
+    size VARCHAR(50) NOT NULL,
+    total_price INTEGER NOT NULL,
+    nm_id INTEGER NOT NULL,
+    brand VARCHAR(100) NOT NULL,
+    status INTEGER NOT NULL,
+    order_uid VARCHAR(255) REFERENCES orders(order_uid) ON DELETE CASCADE
+);
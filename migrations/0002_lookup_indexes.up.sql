@@ -0,0 +1,4 @@
+-- Индексы для загрузки товаров заказа и выборок по покупателю и трек-номеру
+CREATE INDEX IF NOT EXISTS item_order_uid_idx ON item (order_uid);
+CREATE INDEX IF NOT EXISTS orders_customer_id_idx ON orders (customer_id);
+CREATE INDEX IF NOT EXISTS orders_track_number_idx ON orders (track_number);
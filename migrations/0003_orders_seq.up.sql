@@ -0,0 +1,4 @@
+-- Порядковый номер заказа: сохраняет порядок поступления для загрузки кэша.
+-- Существующим заказам номера выдаются в порядке их физического расположения
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS seq BIGSERIAL;
+CREATE UNIQUE INDEX IF NOT EXISTS orders_seq_idx ON orders (seq);
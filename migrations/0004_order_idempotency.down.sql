@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS idempotency_keys;
+ALTER TABLE orders DROP COLUMN IF EXISTS content_hash;
@@ -0,0 +1,12 @@
+-- Хэш содержимого заказа (sha256 от json): отличает повторную отправку того же заказа
+-- от другого заказа с тем же order_uid. У заказов, записанных раньше, хэш вычисляется
+-- при первом сравнении
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS content_hash TEXT;
+
+-- Значения заголовка Idempotency-Key: повторный запрос с тем же ключом получает исходный ответ
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    key TEXT PRIMARY KEY,
+    order_uid TEXT NOT NULL,
+    content_hash TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
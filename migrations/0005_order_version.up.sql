@@ -0,0 +1,3 @@
+-- Версия заказа для оптимистичной блокировки: увеличивается при каждом изменении,
+-- клиент передает ее в If-Match (ETag ответа)
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS version BIGINT NOT NULL DEFAULT 1;
@@ -0,0 +1,3 @@
+ALTER TABLE orders DROP COLUMN IF EXISTS extras;
+ALTER TABLE orders DROP COLUMN IF EXISTS document;
+ALTER TABLE orders DROP COLUMN IF EXISTS raw_document;
@@ -0,0 +1,6 @@
+-- Исходный документ заказа байт в байт, как он был получен (тело запроса или сообщения),
+-- и он же в JSONB для запросов по полям. Поля верхнего уровня, неизвестные схеме,
+-- хранятся в extras и возвращаются вместе с заказом
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS raw_document TEXT;
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS document JSONB GENERATED ALWAYS AS (raw_document::jsonb) STORED;
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS extras JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,77 @@
+-- Схема SQLite, соответствующая миграциям PostgreSQL 0001-0006. Порядок поступления
+-- заказов задает seq (псевдоним rowid), extras и исходный документ хранятся текстом,
+-- к полям документа обращаются через json_extract. Длина строк ограничена CHECK
+-- так же, как VARCHAR(n) в PostgreSQL
+CREATE TABLE delivery (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL CHECK (length(name) <= 255),
+    phone TEXT NOT NULL CHECK (length(phone) <= 50),
+    zip TEXT NOT NULL CHECK (length(zip) <= 20),
+    city TEXT NOT NULL CHECK (length(city) <= 100),
+    address TEXT NOT NULL CHECK (length(address) <= 255),
+    region TEXT NOT NULL CHECK (length(region) <= 100),
+    email TEXT NOT NULL CHECK (length(email) <= 100)
+);
+
+-- transaction — ключевое слово SQLite, имя колонки берется в кавычки
+CREATE TABLE payment (
+    id INTEGER PRIMARY KEY,
+    "transaction" TEXT NOT NULL CHECK (length("transaction") <= 255),
+    request_id TEXT NOT NULL CHECK (length(request_id) <= 255),
+    currency TEXT NOT NULL CHECK (length(currency) <= 10),
+    provider TEXT NOT NULL CHECK (length(provider) <= 100),
+    amount INTEGER NOT NULL,
+    payment_dt INTEGER NOT NULL,
+    bank TEXT NOT NULL CHECK (length(bank) <= 100),
+    delivery_cost INTEGER NOT NULL,
+    goods_total INTEGER NOT NULL,
+    custom_fee INTEGER NOT NULL
+);
+
+CREATE TABLE orders (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    order_uid TEXT NOT NULL UNIQUE CHECK (length(order_uid) <= 255),
+    track_number TEXT NOT NULL CHECK (length(track_number) <= 255),
+    entry TEXT NOT NULL CHECK (length(entry) <= 255),
+    delivery_id INTEGER REFERENCES delivery(id) ON DELETE CASCADE,
+    payment_id INTEGER REFERENCES payment(id) ON DELETE CASCADE,
+    locale TEXT NOT NULL CHECK (length(locale) <= 10),
+    internal_signature TEXT NOT NULL CHECK (length(internal_signature) <= 255),
+    customer_id TEXT NOT NULL CHECK (length(customer_id) <= 255),
+    delivery_service TEXT NOT NULL CHECK (length(delivery_service) <= 100),
+    shardkey TEXT NOT NULL CHECK (length(shardkey) <= 50),
+    sm_id INTEGER NOT NULL,
+    date_created TEXT NOT NULL CHECK (length(date_created) <= 50),
+    oof_shard TEXT NOT NULL CHECK (length(oof_shard) <= 50),
+    content_hash TEXT,
+    version INTEGER NOT NULL DEFAULT 1,
+    raw_document TEXT,
+    extras TEXT NOT NULL DEFAULT '{}'
+);
+
+CREATE TABLE item (
+    id INTEGER PRIMARY KEY,
+    chrt_id INTEGER NOT NULL,
+    track_number TEXT NOT NULL CHECK (length(track_number) <= 255),
+    price INTEGER NOT NULL,
+    rid TEXT NOT NULL CHECK (length(rid) <= 255),
+    name TEXT NOT NULL CHECK (length(name) <= 255),
+    sale INTEGER NOT NULL,
+    size TEXT NOT NULL CHECK (length(size) <= 50),
+    total_price INTEGER NOT NULL,
+    nm_id INTEGER NOT NULL,
+    brand TEXT NOT NULL CHECK (length(brand) <= 100),
+    status INTEGER NOT NULL,
+    order_uid TEXT REFERENCES orders(order_uid) ON DELETE CASCADE
+);
+
+CREATE TABLE idempotency_keys (
+    key TEXT PRIMARY KEY,
+    order_uid TEXT NOT NULL,
+    content_hash TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX item_order_uid_idx ON item (order_uid);
+CREATE INDEX orders_customer_id_idx ON orders (customer_id);
+CREATE INDEX orders_track_number_idx ON orders (track_number);
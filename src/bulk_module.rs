@@ -0,0 +1,652 @@
+use crate::db_module::{Order, OrderCache};
+use crate::error_module::AppError;
+use crate::idempotency_module::FieldDiff;
+use crate::metrics_module::{METRICS, SOURCE_BULK};
+use crate::repository_module::Repository;
+use crate::shutdown_module::Shutdown;
+use crate::shutdown_module::WriteGuard;
+use crate::validation_module::{self, Violation};
+use crate::Ingestion;
+use axum::body::StreamBody;
+use axum::extract::BodyStream;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use futures::channel::mpsc;
+use futures::{SinkExt, StreamExt};
+use serde_json::value::RawValue;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+
+// Количество заказов, записываемых одной транзакцией
+const BATCH_SIZE: usize = 500;
+// Ограничение тела в формате json-массива: он разбирается целиком в памяти,
+// большие объемы передаются построчно в NDJSON
+const MAX_ARRAY_BYTES: usize = 64 * 1024 * 1024;
+// Ограничение строки NDJSON; более длинная строка пропускается с итогом invalid
+const MAX_LINE_BYTES: usize = 1024 * 1024;
+// Количество пачек результатов, ожидающих отправки клиенту
+const OUTPUT_BATCHES: usize = 4;
+
+pub const NDJSON: &str = "application/x-ndjson";
+
+// Результат обработки одной записи
+enum Outcome {
+    Accepted,                   // заказ записан
+    Duplicate,                  // такой же заказ уже сохранен
+    Conflict(Vec<FieldDiff>),   // с тем же order_uid сохранен другой заказ
+    Malformed(String),          // запись не является заказом
+    Violations(Vec<Violation>), // нарушены бизнес-правила
+    Rejected,                   // заказ отклонен БД (ограничения, некорректные данные)
+    Failed,                     // БД недоступна
+}
+
+// Запись пачки: номер в теле запроса, order_uid (если удалось прочитать) и заказ,
+// прошедший проверки, вместе с исходным текстом записи, либо итог обработки
+struct Record {
+    index: usize,
+    order_uid: Option<String>,
+    order: Result<(Order, String), Outcome>,
+}
+
+// Тело запроса: поток строк NDJSON или разобранный json-массив
+enum Input {
+    Lines(BodyStream),
+    Array(Vec<Box<RawValue>>),
+}
+
+// Итог записи пачки одной транзакцией
+enum BatchWrite {
+    Stored(HashMap<String, i64>), // записанные заказы: order_uid и seq
+    Rejected,                     // БД отклонила пачку, заказы записываются по одному
+    Unavailable,                  // БД недоступна
+}
+
+// Накопление записей и запись их в хранилище пачками
+struct Batches {
+    repo: Repository,
+    state: Arc<OrderCache>,
+    records: Vec<Record>,
+    next_index: usize,
+    output: mpsc::Sender<Result<Vec<u8>, Infallible>>, // результаты пачек в формате NDJSON
+}
+
+// POST /orders/bulk — прием множества заказов json-массивом или потоком NDJSON
+// (один заказ на строку). Заказы записываются пачками, на каждую запись в ответе
+// возвращается строка NDJSON с ее итогом. Ответ отправляется по мере записи пачек
+pub async fn bulk_orders(
+    state: Arc<OrderCache>,
+    headers: HeaderMap,
+    mut body: BodyStream,
+    repo: Repository,
+    shutdown: Shutdown,
+) -> Result<Response, AppError> {
+    let write = shutdown.track();
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+
+    let input = match content_type.as_str() {
+        NDJSON => Input::Lines(body),
+        "application/json" => {
+            let mut buffer = Vec::new();
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk.map_err(|err| AppError::MalformedBody(err.to_string()))?;
+                if buffer.len() + chunk.len() > MAX_ARRAY_BYTES {
+                    return Err(AppError::MalformedBody(format!(
+                        "json array body exceeds {} bytes, send {} instead",
+                        MAX_ARRAY_BYTES, NDJSON
+                    )));
+                }
+                buffer.extend_from_slice(&chunk);
+            }
+            // элементы массива сохраняются как исходный текст каждого заказа
+            let values = serde_json::from_slice(&buffer).map_err(|err| {
+                AppError::MalformedBody(format!("expected a json array of orders: {}", err))
+            })?;
+            Input::Array(values)
+        }
+        _ => {
+            return Err(AppError::MalformedBody(format!(
+                "Content-Type must be application/json (array) or {}",
+                NDJSON
+            )));
+        }
+    };
+
+    let (output, results) = mpsc::channel(OUTPUT_BATCHES);
+    let batches = Batches {
+        repo,
+        state,
+        records: Vec::new(),
+        next_index: 0,
+        output,
+    };
+    tokio::spawn(batches.run(input, write));
+
+    Ok((
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, NDJSON)],
+        StreamBody::new(results),
+    )
+        .into_response())
+}
+
+impl Batches {
+    // Обработка тела запроса; запись завершается, даже если клиент перестал читать ответ
+    async fn run(mut self, input: Input, _write: WriteGuard) {
+        match input {
+            Input::Lines(body) => self.push_lines(body).await,
+            Input::Array(values) => {
+                // элементы массива сохраняются как исходный текст каждого заказа
+                for raw in values {
+                    let value =
+                        serde_json::from_str(raw.get()).expect("array element is valid json");
+                    self.push(value, raw.get().to_string()).await;
+                }
+            }
+        }
+        self.flush().await;
+    }
+
+    // Строки обрабатываются по мере получения, тело целиком в памяти не хранится.
+    // Ошибка чтения тела завершает ответ строкой со статусом error
+    async fn push_lines(&mut self, mut body: BodyStream) {
+        let mut buffer = Vec::new();
+        let mut scanned = 0; // начало буфера, в котором уже нет перевода строки
+        let mut skipping = false; // остаток слишком длинной строки
+        while let Some(chunk) = body.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    self.flush().await;
+                    let result = json!({
+                        "index": self.next_index,
+                        "order_uid": null,
+                        "status": "error",
+                        "reason": format!("failed to read request body: {}", err),
+                    });
+                    let mut output = serde_json::to_vec(&result).expect("result is serializable");
+                    output.push(b'\n');
+                    self.send(output).await;
+                    return;
+                }
+            };
+            buffer.extend_from_slice(&chunk);
+            let mut start = 0;
+            while let Some(offset) = buffer[scanned..].iter().position(|byte| *byte == b'\n') {
+                let end = scanned + offset;
+                if !std::mem::take(&mut skipping) {
+                    self.push_line(&buffer[start..end]).await;
+                }
+                start = end + 1;
+                scanned = start;
+            }
+            buffer.drain(..start);
+            scanned = buffer.len();
+            if buffer.len() > MAX_LINE_BYTES {
+                if !skipping {
+                    self.push_line(&buffer).await;
+                    skipping = true;
+                }
+                buffer.clear();
+                scanned = 0;
+            }
+        }
+        if !skipping {
+            self.push_line(&buffer).await;
+        }
+    }
+
+    // Строка NDJSON без перевода строки; пустые строки пропускаются
+    async fn push_line(&mut self, line: &[u8]) {
+        if line.len() > MAX_LINE_BYTES {
+            let reason = format!("line exceeds {} bytes", MAX_LINE_BYTES);
+            return self
+                .push_record(None, Err(Outcome::Malformed(reason)))
+                .await;
+        }
+        if line.iter().all(u8::is_ascii_whitespace) {
+            return;
+        }
+        match serde_json::from_slice(line) {
+            Ok(value) => {
+                let raw = String::from_utf8_lossy(line).trim().to_string();
+                self.push(value, raw).await
+            }
+            Err(err) => {
+                self.push_record(None, Err(Outcome::Malformed(err.to_string())))
+                    .await
+            }
+        }
+    }
+
+    // Разбор и проверка записи
+    async fn push(&mut self, value: Value, raw: String) {
+        let order_uid = value
+            .get("order_uid")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        let order = match serde_json::from_value::<Order>(value) {
+            Ok(order) => match validation_module::validate_order(&order) {
+                Ok(()) => Ok((order, raw)),
+                Err(violations) => Err(Outcome::Violations(violations)),
+            },
+            Err(err) => Err(Outcome::Malformed(err.to_string())),
+        };
+        self.push_record(order_uid, order).await;
+    }
+
+    async fn push_record(
+        &mut self,
+        order_uid: Option<String>,
+        order: Result<(Order, String), Outcome>,
+    ) {
+        self.records.push(Record {
+            index: self.next_index,
+            order_uid,
+            order,
+        });
+        self.next_index += 1;
+        if self.records.len() >= BATCH_SIZE {
+            self.flush().await;
+        }
+    }
+
+    // Запись накопленной пачки и вывод результатов в порядке записей
+    async fn flush(&mut self) {
+        let records = std::mem::take(&mut self.records);
+        let orders: Vec<(&Order, &str)> = records
+            .iter()
+            .filter_map(|record| record.order.as_ref().ok())
+            .map(|(order, raw)| (order, raw.as_str()))
+            .collect();
+        let mut written = match self.repo.insert_batch(&orders).await {
+            Ok(inserted) => BatchWrite::Stored(inserted),
+            Err(err) => {
+                tracing::error!(error = %err, orders = orders.len(), "failed to store order batch");
+                match AppError::from(err) {
+                    AppError::Unavailable(_) => BatchWrite::Unavailable,
+                    _ => BatchWrite::Rejected,
+                }
+            }
+        };
+
+        let mut stored = Vec::new();
+        let mut output = Vec::new();
+        for record in records {
+            let outcome = match record.order {
+                Err(outcome) => outcome,
+                Ok((mut order, raw)) => {
+                    let insert = match &mut written {
+                        // order_uid удаляется из записанных, чтобы повтор в пачке сравнивался
+                        // с записанным
+                        BatchWrite::Stored(inserted) => Ok(inserted.remove(&order.order_uid)),
+                        BatchWrite::Rejected => {
+                            self.repo.insert(&order, &raw).await.map_err(|err| {
+                                tracing::warn!(
+                                    error = %err,
+                                    order_uid = %order.order_uid,
+                                    "failed to store order"
+                                );
+                                match AppError::from(err) {
+                                    AppError::Unavailable(_) => Outcome::Failed,
+                                    _ => Outcome::Rejected,
+                                }
+                            })
+                        }
+                        BatchWrite::Unavailable => Err(Outcome::Failed),
+                    };
+                    match insert {
+                        Err(outcome) => outcome,
+                        Ok(Some(seq)) => {
+                            order.seq = seq;
+                            stored.push(order);
+                            Outcome::Accepted
+                        }
+                        // заказ уже был в БД или повторяется в пачке
+                        Ok(None) => {
+                            match crate::compare_with_stored(&*self.repo, &self.state, &order).await
+                            {
+                                Ok(Ingestion::Replayed) => Outcome::Duplicate,
+                                Ok(Ingestion::Conflict(conflicts)) => Outcome::Conflict(conflicts),
+                                Ok(Ingestion::Stored) => Outcome::Accepted,
+                                Err(err) => {
+                                    tracing::error!(error = %err, "failed to compare order");
+                                    Outcome::Failed
+                                }
+                            }
+                        }
+                    }
+                }
+            };
+            record_metrics(&outcome);
+            let result = describe(record.index, record.order_uid, outcome);
+            serde_json::to_writer(&mut output, &result).expect("result is serializable");
+            output.push(b'\n');
+        }
+
+        if !stored.is_empty() {
+            tracing::info!(orders = stored.len(), "order batch stored");
+            self.state.write(|cache| {
+                for order in stored {
+                    cache.add_order(order);
+                }
+            });
+            self.state.record_ingestion();
+        }
+        if !output.is_empty() {
+            self.send(output).await;
+        }
+    }
+
+    // Отправка результатов клиенту; если клиент отключился, они отбрасываются
+    async fn send(&mut self, results: Vec<u8>) {
+        let _ = self.output.send(Ok(results)).await;
+    }
+}
+
+fn record_metrics(outcome: &Outcome) {
+    match outcome {
+        Outcome::Accepted => METRICS.order_accepted(SOURCE_BULK),
+        Outcome::Duplicate => METRICS.order_rejected(SOURCE_BULK, "duplicate"),
+        Outcome::Conflict(_) => METRICS.order_rejected(SOURCE_BULK, "conflict"),
+        Outcome::Malformed(_) => METRICS.order_rejected(SOURCE_BULK, "malformed"),
+        Outcome::Violations(_) => METRICS.order_rejected(SOURCE_BULK, "validation"),
+        Outcome::Rejected | Outcome::Failed => METRICS.order_rejected(SOURCE_BULK, "db_error"),
+    }
+}
+
+// Строка результата: {"index", "order_uid", "status", ...подробности}
+fn describe(index: usize, order_uid: Option<String>, outcome: Outcome) -> Value {
+    let mut result = json!({ "index": index, "order_uid": order_uid });
+    let status = match outcome {
+        Outcome::Accepted => "accepted",
+        Outcome::Duplicate => "duplicate",
+        Outcome::Conflict(conflicts) => {
+            result["conflicts"] = json!(conflicts);
+            "conflict"
+        }
+        Outcome::Malformed(reason) => {
+            result["reason"] = json!(reason);
+            "invalid"
+        }
+        Outcome::Violations(violations) => {
+            result["reason"] = json!(format!(
+                "order violates {} business rule(s)",
+                violations.len()
+            ));
+            result["errors"] = json!(violations);
+            "invalid"
+        }
+        Outcome::Rejected => {
+            result["reason"] = json!("order rejected by the database");
+            "invalid"
+        }
+        Outcome::Failed => {
+            result["reason"] = json!("database error");
+            "error"
+        }
+    };
+    result["status"] = json!(status);
+    result
+}
+
+// Тесты
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_module::AppState;
+    use crate::postgres_module::PgRepository;
+    use crate::tests::setup_database;
+    use axum::body::Body;
+    use axum::http::Request;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    fn app(state: &Arc<OrderCache>, repo: &Repository) -> Router {
+        crate::app(state.clone(), repo.clone(), Shutdown::new())
+    }
+
+    async fn send(app: Router, content_type: &str, body: Body) -> (StatusCode, Vec<Value>) {
+        let request = Request::post("/orders/bulk")
+            .header(header::CONTENT_TYPE, content_type)
+            .body(body)
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let results = if status == StatusCode::OK {
+            body.split(|byte| *byte == b'\n')
+                .filter(|line| !line.is_empty())
+                .map(|line| serde_json::from_slice(line).unwrap())
+                .collect()
+        } else {
+            vec![serde_json::from_slice(&body).unwrap()]
+        };
+        (status, results)
+    }
+
+    fn model(path: &str) -> Value {
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    async fn count(pool: &sqlx::PgPool, table: &str) -> i64 {
+        let count: (i64,) = sqlx::query_as(&format!("SELECT COUNT(*) FROM {}", table))
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        count.0
+    }
+
+    #[tokio::test]
+    async fn test_bulk_array() {
+        let (pool, _schema) = setup_database().await;
+        let repo: Repository = Arc::new(PgRepository::new(pool.clone()));
+        let state = Arc::new(OrderCache::new(AppState::new()));
+
+        let mut changed = model("models/model1.json");
+        changed["delivery"]["city"] = "Kazan".into();
+        let mut violating = model("models/model3.json");
+        violating["payment"]["currency"] = "XYZ".into();
+        let body = json!([
+            model("models/model1.json"),
+            model("models/model2.json"),
+            {"order_uid": "broken"},
+            model("models/model1.json"),
+            changed,
+            violating,
+            42,
+        ]);
+        let (status, results) = send(
+            app(&state, &repo),
+            "application/json",
+            Body::from(body.to_string()),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let statuses: Vec<&str> = results
+            .iter()
+            .map(|result| result["status"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                "accepted",
+                "accepted",
+                "invalid",
+                "duplicate",
+                "conflict",
+                "invalid",
+                "invalid"
+            ]
+        );
+        assert_eq!(results[2]["order_uid"], "broken");
+        assert!(results[2]["reason"].is_string());
+        assert_eq!(results[4]["conflicts"][0]["field"], "delivery.city");
+        assert_eq!(results[5]["errors"][0]["field"], "payment.currency");
+        assert_eq!(results[6]["index"], 6);
+        assert_eq!(results[6]["order_uid"], Value::Null);
+
+        assert_eq!(count(&pool, "orders").await, 2);
+        assert_eq!(state.read().len(), 2);
+
+        // неподдерживаемый формат и некорректный массив
+        let (status, results) = send(app(&state, &repo), "text/plain", Body::from("[]")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(results[0]["code"], "malformed_body");
+        let (status, _) = send(app(&state, &repo), "application/json", Body::from("{}")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    // Поток NDJSON из нескольких пачек, строки разрезаны между фрагментами тела
+    #[tokio::test]
+    async fn test_bulk_ndjson_batches() {
+        let (pool, _schema) = setup_database().await;
+        let repo: Repository = Arc::new(PgRepository::new(pool.clone()));
+        let state = Arc::new(OrderCache::new(AppState::new()));
+
+        let total = BATCH_SIZE * 2 + 100;
+        let template = model("models/model2.json");
+        let items_per_order = template["items"].as_array().unwrap().len() as i64;
+        let mut body = String::new();
+        for index in 0..total {
+            let mut order = template.clone();
+            order["order_uid"] = format!("bulk-{}", index).into();
+            body.push_str(&order.to_string());
+            body.push('\n');
+            if index == 10 {
+                body.push_str("\n{not json\n");
+            }
+        }
+        let chunks: Vec<Result<Vec<u8>, std::io::Error>> = body
+            .into_bytes()
+            .chunks(1000)
+            .map(|chunk| Ok(chunk.to_vec()))
+            .collect();
+        let (status, results) = send(
+            app(&state, &repo),
+            NDJSON,
+            Body::wrap_stream(futures::stream::iter(chunks)),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        assert_eq!(results.len(), total + 1);
+        assert_eq!(results[11]["status"], "invalid");
+        let accepted = results
+            .iter()
+            .filter(|result| result["status"] == "accepted")
+            .count();
+        assert_eq!(accepted, total);
+        assert_eq!(results[total]["order_uid"], format!("bulk-{}", total - 1));
+
+        assert_eq!(count(&pool, "orders").await, total as i64);
+        assert_eq!(count(&pool, "delivery").await, total as i64);
+        assert_eq!(count(&pool, "payment").await, total as i64);
+        assert_eq!(count(&pool, "item").await, total as i64 * items_per_order);
+        assert_eq!(state.read().len(), total);
+        let stored = repo.get("bulk-700").await.unwrap().unwrap();
+        let mut expected = template.clone();
+        expected["order_uid"] = "bulk-700".into();
+        assert_eq!(serde_json::to_value(&stored).unwrap(), expected);
+        let (raw, _) = repo.raw_document("bulk-700").await.unwrap().unwrap();
+        assert_eq!(raw, Some(expected.to_string()));
+
+        // повторная отправка распознается как повтор
+        let mut order = template.clone();
+        order["order_uid"] = "bulk-0".into();
+        let (_, results) = send(
+            app(&state, &repo),
+            "application/x-ndjson; charset=utf-8",
+            Body::from(order.to_string()),
+        )
+        .await;
+        assert_eq!(results[0]["status"], "duplicate");
+    }
+
+    // Заказ, отклоненный БД, не отклоняет остальные заказы своей пачки
+    #[tokio::test]
+    async fn test_bulk_rejected_record() {
+        let (pool, _schema) = setup_database().await;
+        let repo: Repository = Arc::new(PgRepository::new(pool.clone()));
+        let state = Arc::new(OrderCache::new(AppState::new()));
+
+        // заказ проходит проверки и отклоняется только ограничением, добавленным в схему теста
+        sqlx::query("ALTER TABLE item ADD CONSTRAINT item_size_test CHECK (size <> 'rejected')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let mut rejected = model("models/model2.json");
+        rejected["items"][0]["size"] = "rejected".into();
+        let body = [
+            model("models/model1.json"),
+            rejected,
+            model("models/model3.json"),
+            model("models/model1.json"),
+        ]
+        .iter()
+        .map(|order| format!("{}\n", order))
+        .collect::<String>();
+        let (status, results) = send(app(&state, &repo), NDJSON, Body::from(body)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let statuses: Vec<&str> = results
+            .iter()
+            .map(|result| result["status"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            statuses,
+            vec!["accepted", "invalid", "accepted", "duplicate"]
+        );
+        assert_eq!(results[1]["reason"], "order rejected by the database");
+        assert_eq!(count(&pool, "orders").await, 2);
+        assert_eq!(count(&pool, "delivery").await, 2);
+        assert_eq!(state.read().len(), 2);
+    }
+
+    // Слишком длинная строка пропускается, следующие строки обрабатываются
+    #[tokio::test]
+    async fn test_bulk_ndjson_long_line() {
+        let (pool, _schema) = setup_database().await;
+        let repo: Repository = Arc::new(PgRepository::new(pool.clone()));
+        let state = Arc::new(OrderCache::new(AppState::new()));
+
+        let long_line = format!("{{\"order_uid\": \"{}\"}}", "x".repeat(MAX_LINE_BYTES));
+        let body = format!(
+            "{}\n{}\n{}",
+            long_line,
+            model("models/model1.json"),
+            long_line
+        );
+        let chunks: Vec<Result<Vec<u8>, std::io::Error>> = body
+            .into_bytes()
+            .chunks(64 * 1024)
+            .map(|chunk| Ok(chunk.to_vec()))
+            .collect();
+        let (status, results) = send(
+            app(&state, &repo),
+            NDJSON,
+            Body::wrap_stream(futures::stream::iter(chunks)),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let statuses: Vec<&str> = results
+            .iter()
+            .map(|result| result["status"].as_str().unwrap())
+            .collect();
+        assert_eq!(statuses, vec!["invalid", "accepted", "invalid"]);
+        assert_eq!(
+            results[0]["reason"],
+            format!("line exceeds {} bytes", MAX_LINE_BYTES)
+        );
+        assert_eq!(results[1]["index"], 1);
+        assert_eq!(count(&pool, "orders").await, 1);
+    }
+}
@@ -0,0 +1,650 @@
+use crate::db_module::CacheLimits;
+use crate::logging_module::{self, LogFormat};
+use crate::nats_module::NatsConfig;
+use clap::{Parser, Subcommand};
+use sqlx::postgres::{PgConnectOptions, PgPool, PgPoolOptions};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+// Файл настроек, который читается, если путь не указан явно
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+// Все параметры: ключ в TOML файле (секция.имя), переменная окружения и флаг командной строки.
+// Источники применяются по порядку: значения по умолчанию, файл, окружение, флаги командной строки
+type Setting = (&'static str, &'static str, fn(&Cli) -> &Option<String>);
+
+static SETTINGS: &[Setting] = &[
+    ("server.bind_address", "BIND_ADDRESS", |cli| {
+        &cli.bind_address
+    }),
+    ("server.port", "PORT", |cli| &cli.port),
+    (
+        "server.shutdown_timeout_secs",
+        "SHUTDOWN_TIMEOUT_SECS",
+        |cli| &cli.shutdown_timeout_secs,
+    ),
+    ("database.url", "DATABASE_URL", |cli| &cli.database_url),
+    ("database.max_connections", "DB_MAX_CONNECTIONS", |cli| {
+        &cli.db_max_connections
+    }),
+    ("database.min_connections", "DB_MIN_CONNECTIONS", |cli| {
+        &cli.db_min_connections
+    }),
+    (
+        "database.acquire_timeout_secs",
+        "DB_ACQUIRE_TIMEOUT_SECS",
+        |cli| &cli.db_acquire_timeout_secs,
+    ),
+    (
+        "database.statement_timeout_ms",
+        "DB_STATEMENT_TIMEOUT_MS",
+        |cli| &cli.db_statement_timeout_ms,
+    ),
+    ("cache.max_entries", "CACHE_MAX_ENTRIES", |cli| {
+        &cli.cache_max_entries
+    }),
+    ("cache.max_bytes", "CACHE_MAX_BYTES", |cli| {
+        &cli.cache_max_bytes
+    }),
+    ("nats.url", "NATS_URL", |cli| &cli.nats_url),
+    ("nats.subject", "NATS_SUBJECT", |cli| &cli.nats_subject),
+    ("nats.queue_group", "NATS_QUEUE_GROUP", |cli| {
+        &cli.nats_queue_group
+    }),
+    ("log.level", "LOG_LEVEL", |cli| &cli.log_level),
+    ("log.format", "LOG_FORMAT", |cli| &cli.log_format),
+];
+
+// Аргументы командной строки; флаги переопределяют файл и окружение
+#[derive(Parser, Debug, Default)]
+#[command(version, about = "Order service: HTTP API and NATS subscriber")]
+pub struct Cli {
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "TOML config file [env: CONFIG_FILE]"
+    )]
+    pub config: Option<String>,
+    #[arg(long, value_name = "IP")]
+    pub bind_address: Option<String>,
+    #[arg(long)]
+    pub port: Option<String>,
+    #[arg(long, value_name = "SECS")]
+    pub shutdown_timeout_secs: Option<String>,
+    #[arg(long, value_name = "URL")]
+    pub database_url: Option<String>,
+    #[arg(long, value_name = "N")]
+    pub db_max_connections: Option<String>,
+    #[arg(long, value_name = "N")]
+    pub db_min_connections: Option<String>,
+    #[arg(long, value_name = "SECS")]
+    pub db_acquire_timeout_secs: Option<String>,
+    #[arg(long, value_name = "MS", help = "0 disables the timeout")]
+    pub db_statement_timeout_ms: Option<String>,
+    #[arg(long, value_name = "N")]
+    pub cache_max_entries: Option<String>,
+    #[arg(long, value_name = "BYTES")]
+    pub cache_max_bytes: Option<String>,
+    #[arg(long, value_name = "URL")]
+    pub nats_url: Option<String>,
+    #[arg(long)]
+    pub nats_subject: Option<String>,
+    #[arg(long, value_name = "GROUP")]
+    pub nats_queue_group: Option<String>,
+    #[arg(
+        long,
+        value_name = "FILTER",
+        help = "Log filter, e.g. info or info,sqlx=warn"
+    )]
+    pub log_level: Option<String>,
+    #[arg(long, value_name = "text|json")]
+    pub log_format: Option<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    #[command(about = "Manage the database schema without starting the server")]
+    Migrate {
+        #[command(subcommand)]
+        action: Option<MigrateAction>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone, Copy, PartialEq)]
+pub enum MigrateAction {
+    #[command(about = "Apply all pending migrations (default)")]
+    Up,
+    #[command(about = "Revert migrations newer than VERSION (by default the latest one)")]
+    Down { version: Option<i64> },
+    #[command(about = "List migrations and their state")]
+    Status,
+}
+
+// Итоговые настройки сервиса
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    pub shutdown_timeout: Duration, // срок ожидания незавершенных запросов при остановке
+    pub database: DatabaseConfig,
+    pub cache: CacheLimits,
+    pub nats: Option<NatsConfig>, // None, если адрес NATS не задан
+    pub log_level: String,        // фильтр в формате RUST_LOG
+    pub log_format: LogFormat,
+}
+
+// Настройки подключения к БД
+#[derive(Debug, Clone)]
+pub struct DatabaseConfig {
+    pub storage: Storage,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout: Duration,
+    pub statement_timeout: Option<Duration>,
+}
+
+// Хранилище заказов, выбирается схемой database.url
+#[derive(Debug, Clone)]
+pub enum Storage {
+    Postgres(Box<PgConnectOptions>),
+    Sqlite(Box<SqliteConnectOptions>), // sqlite:orders.db — файл БД создается, если его нет
+    Memory, // memory: — заказы хранятся только в памяти процесса и теряются при остановке
+}
+
+impl FromStr for Storage {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once(':').map(|(scheme, _)| scheme) {
+            Some("postgres" | "postgresql") => match value.parse::<PgConnectOptions>() {
+                Ok(options) => Ok(Storage::Postgres(Box::new(options))),
+                Err(_) => Err(()),
+            },
+            Some("sqlite") => match value.parse::<SqliteConnectOptions>() {
+                Ok(options) => Ok(Storage::Sqlite(Box::new(
+                    options
+                        .create_if_missing(true)
+                        .journal_mode(SqliteJournalMode::Wal),
+                ))),
+                Err(_) => Err(()),
+            },
+            Some("memory") => Ok(Storage::Memory),
+            _ => Err(()),
+        }
+    }
+}
+
+impl DatabaseConfig {
+    // Создание пула подключений; statement_timeout задается каждому соединению при подключении
+    pub async fn connect(&self, connect_options: &PgConnectOptions) -> Result<PgPool, sqlx::Error> {
+        let mut connect_options = connect_options.clone();
+        if let Some(timeout) = self.statement_timeout {
+            let timeout = timeout.as_millis().to_string();
+            connect_options = connect_options.options([("statement_timeout", timeout.as_str())]);
+        }
+        PgPoolOptions::new()
+            .max_connections(self.max_connections)
+            .min_connections(self.min_connections)
+            .acquire_timeout(self.acquire_timeout)
+            .connect_with(connect_options)
+            .await
+    }
+
+    // Пул подключений к файлу SQLite; statement_timeout к SQLite не применяется
+    pub async fn connect_sqlite(
+        &self,
+        connect_options: &SqliteConnectOptions,
+    ) -> Result<SqlitePool, sqlx::Error> {
+        SqlitePoolOptions::new()
+            .max_connections(self.max_connections)
+            .min_connections(self.min_connections)
+            .acquire_timeout(self.acquire_timeout)
+            .connect_with(connect_options.clone())
+            .await
+    }
+}
+
+// Ошибка конфигурации со списком всех некорректных параметров
+#[derive(Debug, PartialEq)]
+pub struct ConfigError {
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration:")?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// Значение параметра до разбора и его источник (для сообщений об ошибках)
+struct RawValue {
+    value: String,
+    source: String,
+}
+
+impl Config {
+    // Сборка настроек из файла, окружения процесса и флагов командной строки
+    pub fn load(cli: &Cli) -> Result<Config, ConfigError> {
+        let path = cli
+            .config
+            .clone()
+            .or_else(|| env::var("CONFIG_FILE").ok().filter(|path| !path.is_empty()));
+        let file = match path {
+            Some(path) => match std::fs::read_to_string(&path) {
+                Ok(text) => Some((path, text)),
+                Err(err) => {
+                    return Err(ConfigError {
+                        problems: vec![format!("config file {}: {}", path, err)],
+                    })
+                }
+            },
+            // файл по умолчанию необязателен
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                let text =
+                    std::fs::read_to_string(DEFAULT_CONFIG_FILE).map_err(|err| ConfigError {
+                        problems: vec![format!("config file {}: {}", DEFAULT_CONFIG_FILE, err)],
+                    })?;
+                Some((DEFAULT_CONFIG_FILE.to_string(), text))
+            }
+            None => None,
+        };
+        Config::from_sources(file, |name| env::var(name).ok(), cli)
+    }
+
+    // Сборка настроек из переданных источников: (путь, содержимое) TOML файла,
+    // функция чтения окружения и флаги. Проверяются все параметры, ошибки возвращаются списком
+    pub fn from_sources(
+        file: Option<(String, String)>,
+        env: impl Fn(&str) -> Option<String>,
+        cli: &Cli,
+    ) -> Result<Config, ConfigError> {
+        let mut problems = Vec::new();
+        let mut raw: BTreeMap<&'static str, RawValue> = BTreeMap::new();
+
+        if let Some((path, text)) = file {
+            read_file(&path, &text, &mut raw, &mut problems);
+        }
+        for (key, name, _) in SETTINGS {
+            if let Some(value) = env(name) {
+                let source = format!("env {}", name);
+                raw.insert(key, RawValue { value, source });
+            }
+        }
+        for (key, _, flag) in SETTINGS {
+            if let Some(value) = flag(cli) {
+                let source = format!("--{}", flag_name(key));
+                let value = value.clone();
+                raw.insert(key, RawValue { value, source });
+            }
+        }
+
+        let mut values = Values {
+            raw: &raw,
+            problems: &mut problems,
+        };
+        let bind_address = values.parse("server.bind_address", "an IP address");
+        let port = values.parse("server.port", "a port number");
+        let shutdown_timeout = values.parse::<u64>("server.shutdown_timeout_secs", "an integer");
+        let storage = match raw.get("database.url") {
+            Some(_) => {
+                values.parse::<Storage>("database.url", "a postgres://, sqlite: or memory: URL")
+            }
+            None => {
+                values
+                    .problems
+                    .push("database.url: must be set".to_string());
+                None
+            }
+        };
+        let max_connections = values.parse::<u32>("database.max_connections", "an integer");
+        let min_connections = values.parse::<u32>("database.min_connections", "an integer");
+        let acquire_timeout = values.parse::<u64>("database.acquire_timeout_secs", "an integer");
+        let statement_timeout = values.parse::<u64>("database.statement_timeout_ms", "an integer");
+        let max_entries = values.parse::<usize>("cache.max_entries", "an integer");
+        let max_bytes = values.parse::<usize>("cache.max_bytes", "an integer");
+        let nats_url = values.parse::<String>("nats.url", "a string");
+        let nats_subject = values.parse::<String>("nats.subject", "a string");
+        let nats_queue_group = values.parse::<String>("nats.queue_group", "a string");
+        let log_level = values.parse::<String>("log.level", "a string");
+        let log_format = values.parse::<LogFormat>("log.format", "'text' or 'json'");
+
+        // значения по умолчанию совпадают с настройками sqlx
+        let max_connections = max_connections.unwrap_or(10);
+        let min_connections = min_connections.unwrap_or(0);
+        if max_connections == 0 {
+            problems.push("database.max_connections: must be at least 1".to_string());
+        }
+        if min_connections > max_connections {
+            problems.push(format!(
+                "database.min_connections: {} is greater than database.max_connections {}",
+                min_connections, max_connections
+            ));
+        }
+        let acquire_timeout = acquire_timeout.unwrap_or(30);
+        if acquire_timeout == 0 {
+            problems.push("database.acquire_timeout_secs: must be at least 1".to_string());
+        }
+        if nats_subject
+            .as_ref()
+            .is_some_and(|subject| subject.is_empty())
+        {
+            problems.push("nats.subject: must not be empty".to_string());
+        }
+        // уведомления и запросы sqlx по умолчанию не выводятся
+        let log_level = log_level.unwrap_or_else(|| "info,sqlx=warn".to_string());
+        if let Err(err) = logging_module::parse_filter(&log_level) {
+            problems.push(format!("log.level: {}", err));
+        }
+
+        let storage = match storage {
+            Some(storage) if problems.is_empty() => storage,
+            _ => return Err(ConfigError { problems }),
+        };
+        Ok(Config {
+            bind_address: bind_address.unwrap_or(IpAddr::from([127, 0, 0, 1])),
+            port: port.unwrap_or(8081),
+            shutdown_timeout: Duration::from_secs(shutdown_timeout.unwrap_or(30)),
+            database: DatabaseConfig {
+                storage,
+                max_connections,
+                min_connections,
+                acquire_timeout: Duration::from_secs(acquire_timeout),
+                // 0, как и в postgres, отключает ограничение
+                statement_timeout: statement_timeout
+                    .filter(|ms| *ms > 0)
+                    .map(Duration::from_millis),
+            },
+            cache: CacheLimits {
+                max_entries,
+                max_bytes,
+            },
+            nats: nats_url.map(|url| NatsConfig {
+                url,
+                subject: nats_subject.unwrap_or_else(|| "orders".to_string()),
+                queue_group: nats_queue_group,
+            }),
+            log_level,
+            log_format: log_format.unwrap_or(LogFormat::Text),
+        })
+    }
+
+    // Адрес, на котором сервер принимает запросы
+    pub fn socket_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_address, self.port)
+    }
+}
+
+// Имя флага командной строки для ключа: server.port -> port, database.url -> database-url
+fn flag_name(key: &str) -> String {
+    let name = match key.split_once('.') {
+        Some(("server", name)) => name.to_string(),
+        Some(("database", "url")) => "database_url".to_string(),
+        Some(("database", name)) => format!("db_{}", name),
+        _ => key.replace('.', "_"),
+    };
+    name.replace('_', "-")
+}
+
+// Чтение параметров из TOML файла; неизвестные ключи и значения неподходящего типа
+// попадают в список ошибок
+fn read_file(
+    path: &str,
+    text: &str,
+    raw: &mut BTreeMap<&'static str, RawValue>,
+    problems: &mut Vec<String>,
+) {
+    let table = match text.parse::<toml::Table>() {
+        Ok(table) => table,
+        Err(err) => {
+            problems.push(format!("config file {}: {}", path, err.message()));
+            return;
+        }
+    };
+    for (section, entries) in table {
+        let Some(entries) = entries.as_table() else {
+            problems.push(format!("{} ({}): unknown key", section, path));
+            continue;
+        };
+        for (name, value) in entries {
+            let full = format!("{}.{}", section, name);
+            let Some((key, _, _)) = SETTINGS.iter().find(|(key, _, _)| *key == full) else {
+                problems.push(format!("{} ({}): unknown key", full, path));
+                continue;
+            };
+            let value = match value {
+                toml::Value::String(value) => value.clone(),
+                toml::Value::Integer(value) => value.to_string(),
+                other => {
+                    problems.push(format!(
+                        "{} ({}): expected a string or an integer, found {}",
+                        key,
+                        path,
+                        other.type_str()
+                    ));
+                    continue;
+                }
+            };
+            let source = path.to_string();
+            raw.insert(key, RawValue { value, source });
+        }
+    }
+}
+
+// Разбор значений параметров с накоплением ошибок
+struct Values<'a> {
+    raw: &'a BTreeMap<&'static str, RawValue>,
+    problems: &'a mut Vec<String>,
+}
+
+impl Values<'_> {
+    fn parse<T: FromStr>(&mut self, key: &str, expected: &str) -> Option<T> {
+        let raw = self.raw.get(key)?;
+        match raw.value.parse() {
+            Ok(value) => Some(value),
+            Err(_) => {
+                self.problems.push(format!(
+                    "{} ({}): expected {}, got '{}'",
+                    key, raw.source, expected, raw.value
+                ));
+                None
+            }
+        }
+    }
+}
+
+// Тесты
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    fn env_from(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: Vec<(String, String)> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        move |name| {
+            vars.iter()
+                .find(|(var, _)| var == name)
+                .map(|(_, value)| value.clone())
+        }
+    }
+
+    fn file(text: &str) -> Option<(String, String)> {
+        Some(("test.toml".to_string(), text.to_string()))
+    }
+
+    #[test]
+    fn test_defaults() {
+        let env = env_from(&[("DATABASE_URL", "postgres://localhost/orders")]);
+        let config = Config::from_sources(None, env, &Cli::default()).unwrap();
+
+        assert_eq!(
+            config.socket_addr(),
+            SocketAddr::from(([127, 0, 0, 1], 8081))
+        );
+        assert_eq!(config.shutdown_timeout, Duration::from_secs(30));
+        assert_eq!(config.database.max_connections, 10);
+        assert_eq!(config.database.acquire_timeout, Duration::from_secs(30));
+        assert_eq!(config.database.statement_timeout, None);
+        assert_eq!(config.cache, CacheLimits::default());
+        assert!(config.nats.is_none());
+        assert_eq!(config.log_level, "info,sqlx=warn");
+        assert_eq!(config.log_format, LogFormat::Text);
+        assert!(matches!(config.database.storage, Storage::Postgres(_)));
+    }
+
+    // Хранилище выбирается схемой database.url
+    #[test]
+    fn test_storage() {
+        let env = env_from(&[("DATABASE_URL", "memory:")]);
+        let config = Config::from_sources(None, env, &Cli::default()).unwrap();
+        assert!(matches!(config.database.storage, Storage::Memory));
+
+        let env = env_from(&[("DATABASE_URL", "sqlite:orders.db")]);
+        let config = Config::from_sources(None, env, &Cli::default()).unwrap();
+        let Storage::Sqlite(options) = config.database.storage else {
+            panic!("expected sqlite storage");
+        };
+        assert_eq!(options.get_filename(), std::path::Path::new("orders.db"));
+
+        let env = env_from(&[("DATABASE_URL", "mysql://localhost/orders")]);
+        let err = Config::from_sources(None, env, &Cli::default()).unwrap_err();
+        assert_eq!(
+            err.problems,
+            vec!["database.url (env DATABASE_URL): expected a postgres://, sqlite: or memory: URL, got 'mysql://localhost/orders'"]
+        );
+    }
+
+    // Окружение переопределяет файл, флаги переопределяют окружение
+    #[test]
+    fn test_layering() {
+        let text = r#"
+            [server]
+            bind_address = "0.0.0.0"
+            port = 9000
+
+            [database]
+            url = "postgres://file/orders"
+            max_connections = 20
+            statement_timeout_ms = 5000
+
+            [nats]
+            url = "nats://file:4222"
+        "#;
+        let env = env_from(&[("PORT", "9100"), ("DB_MAX_CONNECTIONS", "30")]);
+        let cli = Cli {
+            db_max_connections: Some("40".to_string()),
+            cache_max_entries: Some("1000".to_string()),
+            ..Default::default()
+        };
+        let config = Config::from_sources(file(text), env, &cli).unwrap();
+
+        assert_eq!(config.socket_addr(), SocketAddr::from(([0, 0, 0, 0], 9100)));
+        assert_eq!(config.database.max_connections, 40);
+        assert_eq!(
+            config.database.statement_timeout,
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(config.cache.max_entries, Some(1000));
+        let nats = config.nats.unwrap();
+        assert_eq!(nats.url, "nats://file:4222");
+        assert_eq!(nats.subject, "orders");
+    }
+
+    // Все ошибки перечисляются разом
+    #[test]
+    fn test_every_bad_key_is_reported() {
+        let text = r#"
+            [server]
+            port = "http"
+            host = "localhost"
+
+            [database]
+            min_connections = 5
+            max_connections = 2
+            acquire_timeout_secs = true
+        "#;
+        let env = env_from(&[
+            ("CACHE_MAX_BYTES", "-1"),
+            ("BIND_ADDRESS", "localhost"),
+            ("LOG_FORMAT", "xml"),
+        ]);
+        let cli = Cli {
+            nats_subject: Some(String::new()),
+            ..Default::default()
+        };
+        let err = Config::from_sources(file(text), env, &cli).unwrap_err();
+
+        assert_eq!(
+            err.problems,
+            vec![
+                "database.acquire_timeout_secs (test.toml): expected a string or an integer, found boolean",
+                "server.host (test.toml): unknown key",
+                "server.bind_address (env BIND_ADDRESS): expected an IP address, got 'localhost'",
+                "server.port (test.toml): expected a port number, got 'http'",
+                "database.url: must be set",
+                "cache.max_bytes (env CACHE_MAX_BYTES): expected an integer, got '-1'",
+                "log.format (env LOG_FORMAT): expected 'text' or 'json', got 'xml'",
+                "database.min_connections: 5 is greater than database.max_connections 2",
+                "nats.subject: must not be empty",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_flag_names() {
+        let cli = Cli::try_parse_from([
+            "service",
+            "--port",
+            "8082",
+            "--database-url",
+            "postgres://cli/orders",
+            "--db-statement-timeout-ms",
+            "0",
+            "migrate",
+            "down",
+            "2",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::Migrate {
+                action: Some(MigrateAction::Down { version: Some(2) })
+            })
+        ));
+        let config = Config::from_sources(None, env_from(&[]), &cli).unwrap();
+        assert_eq!(config.port, 8082);
+        assert_eq!(config.database.statement_timeout, None);
+
+        // у каждого параметра есть флаг, имя которого выводится в сообщениях об ошибках,
+        // и значение этого флага читается именно для этого параметра
+        let command = Cli::command();
+        for (key, _, value) in SETTINGS {
+            let flag = flag_name(key);
+            assert!(
+                command
+                    .get_arguments()
+                    .any(|arg| arg.get_long() == Some(flag.as_str())),
+                "{}",
+                flag
+            );
+            let cli = Cli::try_parse_from(["service", &format!("--{}", flag), "value"]).unwrap();
+            assert_eq!(value(&cli).as_deref(), Some("value"), "{}", flag);
+        }
+    }
+}
@@ -1,72 +1,37 @@
+use crate::repository_module::OrderRepository;
+use arc_swap::ArcSwap;
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
 use serde::{Deserialize, Serialize};
-use sqlx::postgres::PgPool;
-use sqlx::FromRow;
-
-// Запросы для создания таблиц в БД
-pub static CREATE_DELIVERY_TABLE: &str = r#"
-        CREATE TABLE delivery (
-            id SERIAL PRIMARY KEY,
-            name VARCHAR(255) NOT NULL,
-            phone VARCHAR(50) NOT NULL,
-            zip VARCHAR(20) NOT NULL,
-            city VARCHAR(100) NOT NULL,
-            address VARCHAR(255) NOT NULL,
-            region VARCHAR(100) NOT NULL,
-            email VARCHAR(100) NOT NULL
-        );
-    "#;
-
-pub static CREATE_PAYMENT_TABLE: &str = r#"
-        CREATE TABLE payment (
-            id SERIAL PRIMARY KEY,
-            transaction VARCHAR(255) NOT NULL,
-            request_id VARCHAR(255) NOT NULL,
-            currency VARCHAR(10) NOT NULL,
-            provider VARCHAR(100) NOT NULL,
-            amount INTEGER NOT NULL,
-            payment_dt BIGINT NOT NULL,
-            bank VARCHAR(100) NOT NULL,
-            delivery_cost INTEGER NOT NULL,
-            goods_total INTEGER NOT NULL,
-            custom_fee INTEGER NOT NULL
-        );
-    "#;
-
-pub static CREATE_ORDERS_TABLE: &str = r#"
-        CREATE TABLE orders (
-            order_uid VARCHAR(255) PRIMARY KEY,
-            track_number VARCHAR(255) NOT NULL,
-            entry VARCHAR(255) NOT NULL,
-            delivery_id INTEGER REFERENCES delivery(id) ON DELETE CASCADE,
-            payment_id INTEGER REFERENCES payment(id) ON DELETE CASCADE,
-            locale VARCHAR(10) NOT NULL,
-            internal_signature VARCHAR(255) NOT NULL,
-            customer_id VARCHAR(255) NOT NULL,
-            delivery_service VARCHAR(100) NOT NULL,
-            shardkey VARCHAR(50) NOT NULL,
-            sm_id INTEGER NOT NULL,
-            date_created VARCHAR(50) NOT NULL,
-            oof_shard VARCHAR(50) NOT NULL
-        );
-    "#;
-
-pub static CREATE_ITEM_TABLE: &str = r#"
-        CREATE TABLE item (
-            id SERIAL PRIMARY KEY,
-            chrt_id INTEGER NOT NULL,
-            track_number VARCHAR(255) NOT NULL,
-            price INTEGER NOT NULL,
-            rid VARCHAR(255) NOT NULL,
-            name VARCHAR(255) NOT NULL,
-            sale INTEGER NOT NULL,
-            size VARCHAR(50) NOT NULL,
-            total_price INTEGER NOT NULL,
-            nm_id INTEGER NOT NULL,
-            brand VARCHAR(100) NOT NULL,
-            status INTEGER NOT NULL,
-            order_uid VARCHAR(255) REFERENCES orders(order_uid) ON DELETE CASCADE
-        );
-    "#;
+use serde_json::{Map, Value};
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use sqlx::{Database, FromRow, Pool, Postgres, Sqlite};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+// Версионные миграции схемы из каталога migrations, встраиваются в бинарный файл.
+// Примененные версии хранятся в таблице _sqlx_migrations
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+// Та же схема для SQLite, миграции в каталоге migrations_sqlite
+pub static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("./migrations_sqlite");
+
+// Миграции схемы для конкретной БД
+pub trait Migrations: Database {
+    fn migrator() -> &'static Migrator;
+}
+
+impl Migrations for Postgres {
+    fn migrator() -> &'static Migrator {
+        &MIGRATOR
+    }
+}
+
+impl Migrations for Sqlite {
+    fn migrator() -> &'static Migrator {
+        &SQLITE_MIGRATOR
+    }
+}
 
 // Структуры для хранения заказов
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -85,6 +50,21 @@ pub struct Order {
     pub sm_id: i32,
     pub date_created: String,
     pub oof_shard: String,
+    // поля верхнего уровня, неизвестные схеме: сохраняются и выводятся вместе с заказом
+    #[serde(flatten)]
+    pub extras: Map<String, Value>,
+    // версия записи в БД (ETag); в json не выводится и в хэш содержимого не входит
+    #[serde(skip, default = "initial_version")]
+    pub version: i64,
+    // порядковый номер заказа в хранилище (orders.seq), задает позицию заказа в кэше;
+    // у полученного от клиента и еще не записанного заказа равен 0
+    #[serde(skip)]
+    pub seq: i64,
+}
+
+// Версия только что записанного заказа
+pub fn initial_version() -> i64 {
+    1
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, FromRow)]
@@ -127,34 +107,12 @@ pub struct Item {
     pub status: i32,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-pub struct AppState {
-    orders: Vec<Order>, // Здесь мы храним заказы
-}
-
-impl AppState {
-    // Создание нового состояния с пустым списком заказов
-    pub fn new() -> Self {
-        AppState { orders: Vec::new() }
-    }
-    // Добавление нового заказа
-    pub fn add_order(&mut self, order: Order) {
-        self.orders.push(order);
-    }
-    // Получение заказов
-    pub fn get_orders(&self) -> Vec<Order> {
-        self.orders.clone() // Клонируем заказы для возврата
-    }
-}
-
-// Структура для загрузки данных из базы в AppState
+// Строка заказа вместе с delivery и payment, общая для хранилищ на SQL
 #[derive(FromRow, Debug)]
-struct OrderRow {
-    order_uid: String,
+pub struct OrderRow {
+    pub order_uid: String,
     track_number: String,
     entry: String,
-    delivery_id: i32,
-    payment_id: i32,
     locale: String,
     internal_signature: String,
     customer_id: String,
@@ -163,122 +121,882 @@ struct OrderRow {
     sm_id: i32,
     date_created: String,
     oof_shard: String,
+    version: i64,
+    seq: i64,
+    extras: sqlx::types::Json<Map<String, Value>>,
+    #[sqlx(flatten)]
+    delivery: Delivery,
+    #[sqlx(flatten)]
+    payment: Payment,
+}
+
+impl OrderRow {
+    pub fn into_order(self, items: Vec<Item>) -> Order {
+        Order {
+            order_uid: self.order_uid,
+            track_number: self.track_number,
+            entry: self.entry,
+            delivery: self.delivery,
+            payment: self.payment,
+            items,
+            locale: self.locale,
+            internal_signature: self.internal_signature,
+            customer_id: self.customer_id,
+            delivery_service: self.delivery_service,
+            shardkey: self.shardkey,
+            sm_id: self.sm_id,
+            date_created: self.date_created,
+            oof_shard: self.oof_shard,
+            extras: self.extras.0,
+            version: self.version,
+            seq: self.seq,
+        }
+    }
+}
+
+// Товар вместе с order_uid заказа
+#[derive(FromRow, Debug)]
+pub struct ItemRow {
+    pub order_uid: String,
+    #[sqlx(flatten)]
+    pub item: Item,
+}
+
+// Количество отметок об удалении, которые хранит кэш (см. AppState::remove_order)
+const MAX_REMOVED: usize = 10_000;
+
+// Ограничения размера кэша; None — без ограничения
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheLimits {
+    pub max_entries: Option<usize>, // максимальное количество заказов
+    pub max_bytes: Option<usize>,   // примерный объем памяти под заказы
+}
+
+// Поля, по которым кэш ведет вторичные индексы
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IndexField {
+    CustomerId,
+    TrackNumber,
+    NmId,        // Item.nm_id
+    Rid,         // Item.rid
+    Transaction, // payment.transaction
+}
+
+impl IndexField {
+    const ALL: [IndexField; 5] = [
+        IndexField::CustomerId,
+        IndexField::TrackNumber,
+        IndexField::NmId,
+        IndexField::Rid,
+        IndexField::Transaction,
+    ];
+
+    // Значения поля в заказе; у полей товаров их может быть несколько, в том числе одинаковых
+    fn values(self, order: &Order) -> Vec<String> {
+        match self {
+            IndexField::CustomerId => vec![order.customer_id.clone()],
+            IndexField::TrackNumber => vec![order.track_number.clone()],
+            IndexField::NmId => order
+                .items
+                .iter()
+                .map(|item| item.nm_id.to_string())
+                .collect(),
+            IndexField::Rid => order.items.iter().map(|item| item.rid.clone()).collect(),
+            IndexField::Transaction => vec![order.payment.transaction.clone()],
+        }
+    }
+}
+
+// Кэш заказов. Коллекции персистентные: клонирование состояния стоит O(1),
+// а изменение копирует только затронутые узлы, что позволяет публиковать снимки (см. OrderCache).
+// При превышении ограничений вытесняются давно не запрошенные заказы (LRU)
+#[derive(Clone)]
+pub struct AppState {
+    orders: im::HashMap<String, CachedOrder>, // Здесь мы храним заказы, индексированные по order_uid
+    sequence: im::OrdMap<i64, String>,        // Порядок поступления заказов (по orders.seq)
+    recency: im::OrdMap<u64, String>,         // Заказы по моменту последнего обращения
+    // Вторичные индексы: поле и значение -> позиции заказов в sequence
+    index: im::HashMap<(IndexField, String), im::OrdSet<i64>>,
+    // Отметки удаленных заказов: order_uid -> порядковый номер удаленного заказа и момент
+    // удаления. Запись, зафиксированная в хранилище до удаления, не возвращает заказ в кэш
+    removed: im::HashMap<String, (i64, u64)>,
+    removals: im::OrdMap<u64, String>, // отметки по моменту удаления, для ограничения их числа
+    bytes: usize,
+    limits: CacheLimits,
+    clock: Arc<AtomicU64>, // логические часы обращений, общие для всех снимков
+}
+
+#[derive(Clone)]
+struct CachedOrder {
+    order: Arc<Order>,
+    seq: i64,                  // позиция заказа в sequence
+    bytes: usize,              // примерный объем заказа в памяти
+    recency_key: u64,          // ключ заказа в recency
+    last_used: Arc<AtomicU64>, // момент последнего чтения, обновляется и из неизменяемых снимков
 }
 
-// Метод для загрузки данных из базы в AppState
 impl AppState {
-    pub async fn load_orders(&mut self, db_pool: &PgPool) -> Result<(), sqlx::Error> {
-        // Загружаем заказы
-        let orders: Vec<OrderRow> = sqlx::query_as::<_, OrderRow>("SELECT * FROM orders")
-            .fetch_all(db_pool)
-            .await?;
-
-        for order_row in orders {
-            // Загружаем соответствующий delivery
-            let delivery_row: Delivery = sqlx::query_as("SELECT * FROM delivery WHERE id = $1")
-                .bind(order_row.delivery_id)
-                .fetch_one(db_pool)
-                .await?;
-
-            // Загружаем соответствующий payment
-            let payment_row: Payment = sqlx::query_as("SELECT * FROM payment WHERE id = $1")
-                .bind(order_row.payment_id)
-                .fetch_one(db_pool)
-                .await?;
-
-            // Загружаем соответствующие items
-            let items: Vec<Item> =
-                sqlx::query_as::<_, Item>("SELECT * FROM item WHERE order_uid = $1")
-                    .bind(order_row.order_uid.clone())
-                    .fetch_all(db_pool)
-                    .await?;
-
-            // Преобразуем загруженные данные в нужные структуры
-            let delivery = Delivery {
-                name: delivery_row.name,
-                phone: delivery_row.phone,
-                zip: delivery_row.zip,
-                city: delivery_row.city,
-                address: delivery_row.address,
-                region: delivery_row.region,
-                email: delivery_row.email,
-            };
+    // Создание нового состояния с пустым списком заказов и без ограничений (для тестов)
+    #[cfg(test)]
+    pub fn new() -> Self {
+        AppState::with_limits(CacheLimits::default())
+    }
+    // Создание состояния с ограничением размера
+    pub fn with_limits(limits: CacheLimits) -> Self {
+        AppState {
+            orders: im::HashMap::new(),
+            sequence: im::OrdMap::new(),
+            recency: im::OrdMap::new(),
+            index: im::HashMap::new(),
+            removed: im::HashMap::new(),
+            removals: im::OrdMap::new(),
+            bytes: 0,
+            limits,
+            clock: Arc::new(AtomicU64::new(0)),
+        }
+    }
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
+    // Добавление заказа на позицию его порядкового номера в хранилище; повторное добавление
+    // заменяет заказ. Обработчики обновляют кэш после коммита и могут прийти не по порядку,
+    // поэтому заказ не добавляется, если в кэше более новая версия (больше порядковый номер,
+    // а при равном — версия) или заказ удален после этой записи. Возвращает true, если добавлен
+    pub fn add_order(&mut self, order: impl Into<Arc<Order>>) -> bool {
+        let order: Arc<Order> = order.into();
+        let uid = order.order_uid.clone();
+        let seq = order.seq;
+        match self.removed.get(&uid) {
+            Some(&(removed_seq, _)) if removed_seq >= seq => return false,
+            // заказ создан заново после удаления, отметка больше не нужна
+            Some(&(_, tick)) => {
+                self.removed.remove(&uid);
+                self.removals.remove(&tick);
+            }
+            None => {}
+        }
+        if let Some(previous) = self.orders.get(&uid).cloned() {
+            if (previous.seq, previous.order.version) > (seq, order.version) {
+                return false;
+            }
+            self.detach(&uid);
+        }
+        self.sequence.insert(seq, uid.clone());
 
-            let payment = Payment {
-                transaction: payment_row.transaction,
-                request_id: payment_row.request_id,
-                currency: payment_row.currency,
-                provider: payment_row.provider,
-                amount: payment_row.amount,
-                payment_dt: payment_row.payment_dt,
-                bank: payment_row.bank,
-                delivery_cost: payment_row.delivery_cost,
-                goods_total: payment_row.goods_total,
-                custom_fee: payment_row.custom_fee,
+        let tick = self.tick();
+        let bytes = approximate_size(&order);
+        self.index(&order, seq);
+        self.recency.insert(tick, uid.clone());
+        self.bytes += bytes;
+        self.orders.insert(
+            uid,
+            CachedOrder {
+                order: order.clone(),
+                seq,
+                bytes,
+                recency_key: tick,
+                last_used: Arc::new(AtomicU64::new(tick)),
+            },
+        );
+
+        self.evict();
+        true
+    }
+    // Удаление заказа с порядковым номером seq из кэша. Отметка об удалении не дает
+    // вернуть заказ записям, зафиксированным до удаления; хранятся последние MAX_REMOVED
+    // отметок. Заказ, созданный заново после удаления, в кэше остается
+    pub fn remove_order(&mut self, order_uid: &str, seq: i64) -> bool {
+        let tick = self.tick();
+        if let Some((_, previous)) = self.removed.insert(order_uid.to_string(), (seq, tick)) {
+            self.removals.remove(&previous);
+        }
+        self.removals.insert(tick, order_uid.to_string());
+        while self.removals.len() > MAX_REMOVED {
+            let Some((oldest, uid)) = self.removals.get_min().cloned() else {
+                break;
             };
+            self.removals.remove(&oldest);
+            self.removed.remove(&uid);
+        }
+        match self.orders.get(order_uid) {
+            Some(cached) if cached.seq <= seq => self.detach(order_uid),
+            _ => false,
+        }
+    }
+    // Удаление заказа из коллекций кэша без отметки (замена и вытеснение)
+    fn detach(&mut self, order_uid: &str) -> bool {
+        match self.orders.remove(order_uid) {
+            Some(cached) => {
+                self.sequence.remove(&cached.seq);
+                self.recency.remove(&cached.recency_key);
+                self.bytes -= cached.bytes;
+                self.unindex(&cached.order, cached.seq);
+                true
+            }
+            None => false,
+        }
+    }
+    // Получение заказа по order_uid, обращение продлевает жизнь заказа в кэше
+    pub fn get_order(&self, order_uid: &str) -> Option<Arc<Order>> {
+        let cached = self.orders.get(order_uid)?;
+        cached.last_used.store(self.tick(), Ordering::Relaxed);
+        Some(cached.order.clone())
+    }
+    // Обход заказов в порядке добавления
+    pub fn orders(&self) -> impl Iterator<Item = &Arc<Order>> {
+        self.sequence.values().map(|uid| &self.orders[uid].order)
+    }
+    // Заказы, у которых поле field равно value, в порядке добавления; для полей товаров —
+    // заказы, в которых есть такой товар. Как и orders, обращение не продлевает жизнь заказов
+    pub fn find(&self, field: IndexField, value: &str) -> impl Iterator<Item = &Arc<Order>> {
+        self.index
+            .get(&(field, value.to_string()))
+            .into_iter()
+            .flatten()
+            .map(|seq| &self.orders[&self.sequence[seq]].order)
+    }
+    // Количество заказов, которые вернет find
+    pub fn count(&self, field: IndexField, value: &str) -> usize {
+        self.index
+            .get(&(field, value.to_string()))
+            .map_or(0, |positions| positions.len())
+    }
+    // Количество заказов в кэше
+    pub fn len(&self) -> usize {
+        self.orders.len()
+    }
+    // Примерный объем заказов в кэше
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+    pub fn limits(&self) -> CacheLimits {
+        self.limits
+    }
 
-            let items_vec: Vec<Item> = items
-                .iter()
-                .map(|item_row| Item {
-                    chrt_id: item_row.chrt_id,
-                    track_number: item_row.track_number.clone(),
-                    price: item_row.price,
-                    rid: item_row.rid.clone(),
-                    name: item_row.name.clone(),
-                    sale: item_row.sale,
-                    size: item_row.size.clone(),
-                    total_price: item_row.total_price,
-                    nm_id: item_row.nm_id,
-                    brand: item_row.brand.clone(),
-                    status: item_row.status,
-                })
-                .collect();
-
-            // Создаем и добавляем новый заказ в AppState
-            let order = Order {
-                order_uid: order_row.order_uid,
-                track_number: order_row.track_number,
-                entry: order_row.entry,
-                delivery, // Используем уже созданную структуру Delivery
-                payment,  // Используем уже созданную структуру Payment
-                items: items_vec,
-                locale: order_row.locale,
-                internal_signature: order_row.internal_signature,
-                customer_id: order_row.customer_id,
-                delivery_service: order_row.delivery_service,
-                shardkey: order_row.shardkey,
-                sm_id: order_row.sm_id,
-                date_created: order_row.date_created,
-                oof_shard: order_row.oof_shard,
+    fn index(&mut self, order: &Order, seq: i64) {
+        for field in IndexField::ALL {
+            for value in field.values(order) {
+                self.index.entry((field, value)).or_default().insert(seq);
+            }
+        }
+    }
+
+    // Удаление заказа из индексов; пустые значения удаляются целиком
+    fn unindex(&mut self, order: &Order, seq: i64) {
+        for field in IndexField::ALL {
+            for value in field.values(order) {
+                let key = (field, value);
+                if let Some(positions) = self.index.get_mut(&key) {
+                    positions.remove(&seq);
+                    if positions.is_empty() {
+                        self.index.remove(&key);
+                    }
+                }
+            }
+        }
+    }
+
+    fn over_limits(&self) -> bool {
+        self.limits
+            .max_entries
+            .is_some_and(|max| self.orders.len() > max)
+            || self.limits.max_bytes.is_some_and(|max| self.bytes > max)
+    }
+
+    // Вытеснение давно не запрошенных заказов; последний добавленный заказ остается всегда.
+    // Чтения из снимков не меняют recency, поэтому ключ кандидата сверяется с last_used
+    // и при необходимости обновляется, прежде чем заказ будет вытеснен
+    fn evict(&mut self) {
+        while self.over_limits() && self.orders.len() > 1 {
+            let (key, uid) = match self.recency.get_min() {
+                Some((key, uid)) => (*key, uid.clone()),
+                None => break,
             };
+            let last_used = self.orders[&uid].last_used.load(Ordering::Relaxed);
+            if last_used > key {
+                // к заказу обращались после последнего изменения recency
+                self.recency.remove(&key);
+                self.recency.insert(last_used, uid.clone());
+                if let Some(cached) = self.orders.get_mut(&uid) {
+                    cached.recency_key = last_used;
+                }
+                continue;
+            }
+            self.detach(&uid);
+        }
+    }
+}
+
+// Примерный объем заказа в памяти: размеры структур и содержимое строк
+fn approximate_size(order: &Order) -> usize {
+    let delivery = &order.delivery;
+    let payment = &order.payment;
+    let strings = [
+        &order.order_uid,
+        &order.track_number,
+        &order.entry,
+        &order.locale,
+        &order.internal_signature,
+        &order.customer_id,
+        &order.delivery_service,
+        &order.shardkey,
+        &order.date_created,
+        &order.oof_shard,
+        &delivery.name,
+        &delivery.phone,
+        &delivery.zip,
+        &delivery.city,
+        &delivery.address,
+        &delivery.region,
+        &delivery.email,
+        &payment.transaction,
+        &payment.request_id,
+        &payment.currency,
+        &payment.provider,
+        &payment.bank,
+    ];
+    let items: usize = order
+        .items
+        .iter()
+        .map(|item| {
+            std::mem::size_of::<Item>()
+                + item.track_number.len()
+                + item.rid.len()
+                + item.name.len()
+                + item.size.len()
+                + item.brand.len()
+        })
+        .sum();
+    let extras = if order.extras.is_empty() {
+        0
+    } else {
+        serde_json::to_string(&order.extras).map_or(0, |json| json.len())
+    };
+    // значения во вторичных индексах вместе с позицией заказа
+    let index: usize = IndexField::ALL
+        .iter()
+        .flat_map(|field| field.values(order))
+        .map(|value| value.len() + std::mem::size_of::<u64>())
+        .sum();
+    std::mem::size_of::<Order>()
+        + strings.iter().map(|value| value.len()).sum::<usize>()
+        + items
+        + extras
+        + index
+        // ключи в orders, sequence и recency
+        + 3 * order.order_uid.len()
+}
+
+// Общий кэш заказов для обработчиков. Читатели получают неизменяемый снимок AppState
+// без блокировок и никогда не ждут писателей; писатели выполняются по очереди,
+// изменяют копию текущего снимка и атомарно публикуют ее
+pub struct OrderCache {
+    current: ArcSwap<AppState>,
+    writer: Mutex<()>,
+    warm: AtomicBool, // заказы из БД загружены
+    last_ingestion: Mutex<Option<DateTime<Utc>>>,
+}
 
-            self.add_order(order); // Добавляем в AppState
+impl OrderCache {
+    // Кэш с уже загруженными заказами
+    pub fn new(state: AppState) -> Self {
+        OrderCache {
+            current: ArcSwap::from_pointee(state),
+            writer: Mutex::new(()),
+            warm: AtomicBool::new(true),
+            last_ingestion: Mutex::new(None),
         }
+    }
+    // Пустой кэш, заказы из БД в который загружаются позже (см. finish_warm_up)
+    pub fn warming(limits: CacheLimits) -> Self {
+        let cache = OrderCache::new(AppState::with_limits(limits));
+        cache.warm.store(false, Ordering::Release);
+        cache
+    }
+    pub fn is_warm(&self) -> bool {
+        self.warm.load(Ordering::Acquire)
+    }
+    // Замена содержимого кэша загруженными из БД заказами. Изменения, сделанные во время
+    // загрузки, применяются поверх: удаленные заказы убираются из загруженных, принятые
+    // и измененные сохраняются на позициях своих порядковых номеров
+    pub fn finish_warm_up(&self, loaded: AppState) {
+        self.write(|current| {
+            let mut loaded = loaded;
+            for uid in current.removals.values() {
+                let (seq, _) = current.removed[uid];
+                loaded.remove_order(uid, seq);
+            }
+            for order in current.orders() {
+                loaded.add_order(order.clone());
+            }
+            *current = loaded;
+        });
+        self.warm.store(true, Ordering::Release);
+    }
+    // Отметка о приеме нового заказа
+    pub fn record_ingestion(&self) {
+        *self.last_ingestion.lock().unwrap() = Some(Utc::now());
+    }
+    // Время приема последнего заказа с момента запуска
+    pub fn last_ingestion(&self) -> Option<DateTime<Utc>> {
+        *self.last_ingestion.lock().unwrap()
+    }
+    // Текущий снимок кэша; последующие изменения на него не влияют
+    pub fn read(&self) -> Arc<AppState> {
+        self.current.load_full()
+    }
+    // Изменение кэша; новый снимок становится виден читателям после завершения f
+    pub fn write<R>(&self, f: impl FnOnce(&mut AppState) -> R) -> R {
+        let _guard = self.writer.lock().unwrap();
+        let mut next = AppState::clone(&self.current.load());
+        let result = f(&mut next);
+        self.current.store(Arc::new(next));
+        result
+    }
+}
 
+// Загрузка всех заказов из хранилища в AppState в порядке поступления
+impl AppState {
+    pub async fn load_orders(&mut self, repo: &dyn OrderRepository) -> Result<(), sqlx::Error> {
+        let _timer = crate::metrics_module::METRICS
+            .load_orders_duration
+            .start_timer();
+        let mut orders = repo.stream_all();
+        while let Some(order) = orders.try_next().await? {
+            self.add_order(order);
+        }
         Ok(())
     }
 }
-// Функция для создания таблиц
-pub async fn create_table(table: &str, pool: &PgPool) -> Result<(), sqlx::Error> {
-    match table {
-        "payment" => {
-            sqlx::query(CREATE_PAYMENT_TABLE).execute(pool).await?;
-            Ok(())
-        }
-        "delivery" => {
-            sqlx::query(CREATE_DELIVERY_TABLE).execute(pool).await?;
-            Ok(())
+
+// Применение всех недостающих миграций
+pub async fn run_migrations<DB>(pool: &Pool<DB>) -> Result<(), MigrateError>
+where
+    DB: Migrations,
+    DB::Connection: Migrate,
+{
+    DB::migrator().run(pool).await
+}
+
+// Откат примененных миграций с версией больше target (0 — откат всех)
+pub async fn revert_migrations<DB>(pool: &Pool<DB>, target: i64) -> Result<(), MigrateError>
+where
+    DB: Migrations,
+    DB::Connection: Migrate,
+{
+    DB::migrator().undo(pool, target).await
+}
+
+// Версии миграций, которые еще не применены к базе данных
+pub async fn pending_migrations<DB>(pool: &Pool<DB>) -> Result<Vec<i64>, MigrateError>
+where
+    DB: Migrations,
+    DB::Connection: Migrate,
+{
+    let applied = applied_migrations(pool).await?;
+    Ok(DB::migrator()
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| migration.version)
+        .filter(|version| !applied.contains(version))
+        .collect())
+}
+
+// Версии миграций, уже примененных к базе данных
+pub async fn applied_migrations<DB>(pool: &Pool<DB>) -> Result<Vec<i64>, MigrateError>
+where
+    DB: Migrations,
+    DB::Connection: Migrate,
+{
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    let applied = conn.list_applied_migrations().await?;
+    Ok(applied
+        .into_iter()
+        .map(|migration| migration.version)
+        .collect())
+}
+
+// Тесты
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Barrier;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // Файлы моделей в порядке записи: заказ из файла получает порядковый номер,
+    // как если бы файлы были записаны в хранилище в этом порядке
+    const MODELS: [&str; 6] = [
+        "models/model1.json",
+        "models/model2.json",
+        "models/model3.json",
+        "models/model4.json",
+        "models/model5.json",
+        "models/model_extended.json",
+    ];
+
+    pub(crate) fn load_order(file_path: &str) -> Order {
+        let content = std::fs::read_to_string(file_path).expect("Unable to read file");
+        let mut order: Order = serde_json::from_str(&content).expect("JSON was not well-formatted");
+        order.seq = MODELS
+            .iter()
+            .position(|model| *model == file_path)
+            .expect("unknown model file") as i64
+            + 1;
+        order
+    }
+
+    // Читатели не блокируются, пока писатель держит кэш
+    #[test]
+    fn test_reads_do_not_wait_for_writer() {
+        let cache = Arc::new(OrderCache::new(AppState::new()));
+        cache.write(|state| state.add_order(load_order("models/model1.json")));
+
+        let started = Arc::new(Barrier::new(2));
+        let writer = {
+            let cache = cache.clone();
+            let started = started.clone();
+            thread::spawn(move || {
+                cache.write(|state| {
+                    started.wait();
+                    // долгая запись
+                    thread::sleep(Duration::from_millis(500));
+                    state.add_order(load_order("models/model2.json"));
+                })
+            })
+        };
+
+        started.wait();
+        let read_started = Instant::now();
+        let snapshot = cache.read();
+        assert!(read_started.elapsed() < Duration::from_millis(100));
+        // снимок содержит состояние до начала записи
+        assert_eq!(snapshot.len(), 1);
+        assert!(snapshot.get_order("b563feb7b2b84b6test").is_some());
+
+        writer.join().unwrap();
+        assert_eq!(cache.read().len(), 2);
+        assert_eq!(snapshot.len(), 1);
+    }
+
+    // Параллельные читатели во время массовой записи видят согласованные снимки
+    #[test]
+    fn test_parallel_readers_during_bulk_ingestion() {
+        const ORDERS: usize = 5_000;
+        const READERS: usize = 8;
+
+        let template = load_order("models/model1.json");
+        let cache = Arc::new(OrderCache::new(AppState::new()));
+        let ingesting = Arc::new(AtomicBool::new(true));
+        let reads_during_ingestion = Arc::new(AtomicUsize::new(0));
+
+        let readers: Vec<_> = (0..READERS)
+            .map(|_| {
+                let cache = cache.clone();
+                let ingesting = ingesting.clone();
+                let reads = reads_during_ingestion.clone();
+                thread::spawn(move || {
+                    let mut last_len = 0;
+                    while ingesting.load(Ordering::Acquire) {
+                        let snapshot = cache.read();
+                        // размер снимка совпадает с количеством заказов при обходе
+                        // и не уменьшается между чтениями
+                        let len = snapshot.orders().count();
+                        assert_eq!(len, snapshot.len());
+                        assert!(len >= last_len);
+                        if let Some(order) = snapshot.orders().last() {
+                            assert!(snapshot.get_order(&order.order_uid).is_some());
+                        }
+                        last_len = len;
+                        reads.fetch_add(1, Ordering::Relaxed);
+                    }
+                })
+            })
+            .collect();
+
+        for index in 0..ORDERS {
+            let mut order = template.clone();
+            order.order_uid = format!("stress{}", index);
+            order.seq = index as i64 + 1;
+            cache.write(|state| state.add_order(order));
         }
-        "orders" => {
-            sqlx::query(CREATE_ORDERS_TABLE).execute(pool).await?;
-            Ok(())
+        ingesting.store(false, Ordering::Release);
+        for reader in readers {
+            reader.join().unwrap();
         }
-        "item" => {
-            sqlx::query(CREATE_ITEM_TABLE).execute(pool).await?;
-            Ok(())
+
+        assert_eq!(cache.read().len(), ORDERS);
+        // каждый читатель успел выполнить хотя бы одно чтение
+        assert!(reads_during_ingestion.load(Ordering::Relaxed) >= READERS);
+        let uids: Vec<String> = cache
+            .read()
+            .orders()
+            .take(3)
+            .map(|order| order.order_uid.clone())
+            .collect();
+        assert_eq!(uids, vec!["stress0", "stress1", "stress2"]);
+    }
+
+    fn uids(state: &AppState) -> Vec<&str> {
+        state
+            .orders()
+            .map(|order| order.order_uid.as_str())
+            .collect()
+    }
+
+    // При превышении количества вытесняется заказ, к которому дольше всего не обращались
+    #[test]
+    fn test_lru_eviction_by_entries() {
+        let mut state = AppState::with_limits(CacheLimits {
+            max_entries: Some(2),
+            max_bytes: None,
+        });
+        state.add_order(load_order("models/model1.json"));
+        state.add_order(load_order("models/model2.json"));
+        // обращение защищает первый заказ от вытеснения
+        assert!(state.get_order("b563feb7b2b84b6test").is_some());
+        state.add_order(load_order("models/model3.json"));
+
+        assert_eq!(state.len(), 2);
+        assert_eq!(
+            uids(&state),
+            vec!["b563feb7b2b84b6test", "b563feb7b2b84b6test2"]
+        );
+        assert!(state.get_order("b563feb7b2b84b6test1").is_none());
+
+        // повторное добавление не меняет позицию заказа в списке
+        state.add_order(load_order("models/model1.json"));
+        assert_eq!(
+            uids(&state),
+            vec!["b563feb7b2b84b6test", "b563feb7b2b84b6test2"]
+        );
+    }
+
+    // Ограничение объема учитывает размер заказов, последний заказ остается всегда
+    #[test]
+    fn test_lru_eviction_by_bytes() {
+        let orders: Vec<Order> = [
+            "models/model1.json",
+            "models/model2.json",
+            "models/model3.json",
+        ]
+        .iter()
+        .map(|file_path| load_order(file_path))
+        .collect();
+        // помещаются только два последних заказа
+        let max_bytes = approximate_size(&orders[1]) + approximate_size(&orders[2]);
+        let mut state = AppState::with_limits(CacheLimits {
+            max_entries: None,
+            max_bytes: Some(max_bytes),
+        });
+        for order in orders {
+            state.add_order(order);
         }
-        _ => {
-            return Err(sqlx::Error::Protocol(table.to_string()));
+        assert_eq!(state.bytes(), max_bytes);
+        assert_eq!(
+            uids(&state),
+            vec!["b563feb7b2b84b6test1", "b563feb7b2b84b6test2"]
+        );
+
+        let mut tiny = AppState::with_limits(CacheLimits {
+            max_entries: None,
+            max_bytes: Some(1),
+        });
+        tiny.add_order(load_order("models/model1.json"));
+        assert_eq!(tiny.len(), 1);
+    }
+
+    #[test]
+    fn test_remove_order() {
+        let mut state = AppState::new();
+        state.add_order(load_order("models/model1.json"));
+        state.add_order(load_order("models/model2.json"));
+
+        assert!(state.remove_order("b563feb7b2b84b6test", 1));
+        assert!(!state.remove_order("b563feb7b2b84b6test", 1));
+        assert_eq!(uids(&state), vec!["b563feb7b2b84b6test1"]);
+        assert_eq!(
+            state.bytes(),
+            approximate_size(&load_order("models/model2.json"))
+        );
+    }
+
+    // Обновления кэша, пришедшие не по порядку, не заменяют более новые версии
+    // и не возвращают удаленный заказ
+    #[test]
+    fn test_out_of_order_writes() {
+        let mut state = AppState::new();
+        let version = |version: i64, seq: i64| {
+            let mut order = load_order("models/model1.json");
+            order.version = version;
+            order.seq = seq;
+            order
+        };
+        let cached_version = |state: &AppState| {
+            state
+                .get_order("b563feb7b2b84b6test")
+                .map(|order| (order.seq, order.version))
+        };
+
+        assert!(state.add_order(version(1, 1)));
+        assert!(state.add_order(version(3, 1)));
+        assert!(!state.add_order(version(2, 1)));
+        assert_eq!(cached_version(&state), Some((1, 3)));
+
+        // запись, зафиксированная до удаления, не возвращает заказ
+        assert!(state.remove_order("b563feb7b2b84b6test", 1));
+        assert!(!state.add_order(version(3, 1)));
+        assert_eq!(cached_version(&state), None);
+
+        // заказ, созданный заново, получает новый порядковый номер и добавляется;
+        // запоздавшее удаление прежнего заказа его не убирает
+        assert!(state.add_order(version(1, 7)));
+        assert!(!state.remove_order("b563feb7b2b84b6test", 1));
+        assert!(!state.add_order(version(2, 1)));
+        assert_eq!(cached_version(&state), Some((7, 1)));
+
+        // хранятся только последние MAX_REMOVED отметок
+        assert!(state.remove_order("b563feb7b2b84b6test", 7));
+        for index in 0..MAX_REMOVED {
+            state.remove_order(&format!("removed{}", index), 1);
         }
+        assert_eq!(state.removed.len(), MAX_REMOVED);
+        assert_eq!(state.removals.len(), MAX_REMOVED);
+        assert!(state.add_order(version(1, 7)));
+    }
+
+    fn found(state: &AppState, field: IndexField, value: &str) -> Vec<String> {
+        state
+            .find(field, value)
+            .map(|order| order.order_uid.clone())
+            .collect()
+    }
+
+    // Вторичные индексы следуют за добавлением, заменой, удалением и вытеснением заказов
+    #[test]
+    fn test_secondary_indexes() {
+        let mut state = AppState::with_limits(CacheLimits {
+            max_entries: Some(3),
+            max_bytes: None,
+        });
+        state.add_order(load_order("models/model1.json"));
+        state.add_order(load_order("models/model_extended.json"));
+        state.add_order(load_order("models/model2.json"));
+
+        assert_eq!(
+            found(&state, IndexField::CustomerId, "test"),
+            ["b563feb7b2b84b6test", "b563feb7b2b84b6ext"]
+        );
+        assert_eq!(
+            found(&state, IndexField::TrackNumber, "TRACKNUMBER1"),
+            ["b563feb7b2b84b6test1"]
+        );
+        // в расширенном заказе два товара с одним nm_id, заказ найден один раз
+        assert_eq!(
+            found(&state, IndexField::NmId, "2389212"),
+            ["b563feb7b2b84b6test", "b563feb7b2b84b6ext"]
+        );
+        assert_eq!(
+            found(&state, IndexField::Rid, "RID123456"),
+            ["b563feb7b2b84b6test1"]
+        );
+        assert_eq!(
+            state.count(IndexField::Transaction, "b563feb7b2b84b6test"),
+            2
+        );
+        assert_eq!(state.count(IndexField::CustomerId, "missing"), 0);
+
+        // замена заказа переносит его в индексах, позиция сохраняется
+        let mut changed = load_order("models/model1.json");
+        changed.customer_id = "customer1".to_string();
+        changed.items[0].nm_id = 1;
+        state.add_order(changed);
+        let snapshot = state.clone();
+        assert_eq!(
+            found(&state, IndexField::CustomerId, "customer1"),
+            ["b563feb7b2b84b6test", "b563feb7b2b84b6test1"]
+        );
+        assert_eq!(
+            found(&state, IndexField::CustomerId, "test"),
+            ["b563feb7b2b84b6ext"]
+        );
+        assert_eq!(
+            found(&state, IndexField::NmId, "1"),
+            ["b563feb7b2b84b6test"]
+        );
+
+        // удаленный и вытесненный заказы пропадают из индексов
+        assert!(state.remove_order("b563feb7b2b84b6ext", 6));
+        assert_eq!(state.count(IndexField::CustomerId, "test"), 0);
+        assert_eq!(state.count(IndexField::NmId, "2389212"), 0);
+        assert!(state.index.keys().all(|(_, value)| value != "test"));
+        state.add_order(load_order("models/model3.json"));
+        state.add_order(load_order("models/model4.json"));
+        // вытеснен model2: к нему обращались раньше, чем к замененному model1
+        assert_eq!(state.len(), 3);
+        assert_eq!(
+            found(&state, IndexField::CustomerId, "customer1"),
+            ["b563feb7b2b84b6test"]
+        );
+        assert_eq!(state.count(IndexField::TrackNumber, "TRACKNUMBER1"), 0);
+
+        // снимок, сделанный раньше, видит прежние индексы
+        assert_eq!(
+            found(&snapshot, IndexField::CustomerId, "test"),
+            ["b563feb7b2b84b6ext"]
+        );
+    }
+
+    // Заказы, принятые во время загрузки кэша, не теряются при ее завершении
+    #[test]
+    fn test_finish_warm_up_keeps_new_orders() {
+        let cache = OrderCache::warming(CacheLimits::default());
+        assert!(!cache.is_warm());
+        cache.write(|state| state.add_order(load_order("models/model3.json")));
+        cache.write(|state| state.add_order(load_order("models/model1.json")));
+
+        // model1 уже был в БД к началу загрузки
+        let mut loaded = AppState::new();
+        loaded.add_order(load_order("models/model1.json"));
+        loaded.add_order(load_order("models/model2.json"));
+        cache.finish_warm_up(loaded);
+
+        assert!(cache.is_warm());
+        assert_eq!(
+            uids(&cache.read()),
+            vec![
+                "b563feb7b2b84b6test",
+                "b563feb7b2b84b6test1",
+                "b563feb7b2b84b6test2"
+            ]
+        );
+    }
+
+    // Удаления и изменения во время загрузки применяются к загруженным заказам
+    #[test]
+    fn test_finish_warm_up_applies_changes() {
+        let cache = OrderCache::warming(CacheLimits::default());
+        // model1 удален, model2 изменен, пока загрузка читала их прежние версии
+        cache.write(|state| state.remove_order("b563feb7b2b84b6test", 1));
+        let mut changed = load_order("models/model2.json");
+        changed.version = 2;
+        cache.write(|state| state.add_order(changed));
+
+        let mut loaded = AppState::new();
+        loaded.add_order(load_order("models/model1.json"));
+        loaded.add_order(load_order("models/model2.json"));
+        loaded.add_order(load_order("models/model3.json"));
+        cache.finish_warm_up(loaded);
+
+        let state = cache.read();
+        assert_eq!(
+            uids(&state),
+            vec!["b563feb7b2b84b6test1", "b563feb7b2b84b6test2"]
+        );
+        assert_eq!(state.get_order("b563feb7b2b84b6test1").unwrap().version, 2);
+        // отметка об удалении сохраняется после загрузки
+        assert!(!cache.write(|state| state.add_order(load_order("models/model1.json"))));
     }
 }
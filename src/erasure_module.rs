@@ -0,0 +1,172 @@
+use crate::db_module::{Order, OrderCache};
+use crate::error_module::AppError;
+use crate::repository_module::Repository;
+use crate::shutdown_module::Shutdown;
+use axum::extract::{Json, Path};
+use axum::response::IntoResponse;
+use serde_json::json;
+use std::sync::Arc;
+
+// Значение, которым заменяются персональные данные
+pub const ERASED: &str = "[erased]";
+
+// Обезличивание заказа: имя, телефон, адрес и email получателя заменяются значением erased,
+// поля, неизвестные схеме, удаляются — в них тоже могут быть персональные данные. Город,
+// регион и индекс, а также payment и товары остаются для учета. Исходным документом
+// обезличенного заказа становится сам заказ
+pub fn anonymize(order: &mut Order, erased: &str) {
+    let delivery = &mut order.delivery;
+    for field in [
+        &mut delivery.name,
+        &mut delivery.phone,
+        &mut delivery.address,
+        &mut delivery.email,
+    ] {
+        *field = erased.to_string();
+    }
+    order.extras.clear();
+}
+
+// POST /customer/:customer_id/erasure — удаление персональных данных покупателя во всех
+// его заказах. Версия измененных заказов увеличивается; уже обезличенные заказы
+// не меняются, поэтому повторный запрос безопасен
+pub async fn erase_customer(
+    state: Arc<OrderCache>,
+    Path(customer_id): Path<String>,
+    repo: Repository,
+    shutdown: Shutdown,
+) -> Result<impl IntoResponse, AppError> {
+    let _write = shutdown.track();
+    let erased = repo.erase_customer(&customer_id, ERASED).await?;
+    tracing::info!(%customer_id, orders = erased.len(), "customer data erased");
+
+    // в кэше обновляются только присутствующие в нем заказы, остальные загрузятся из хранилища
+    state.write(|cache| {
+        for (order_uid, version) in &erased {
+            if let Some(order) = cache.get_order(order_uid) {
+                let mut order = (*order).clone();
+                anonymize(&mut order, ERASED);
+                order.version = *version;
+                cache.add_order(order);
+            }
+        }
+    });
+
+    let orders: Vec<&str> = erased
+        .iter()
+        .map(|(order_uid, _)| order_uid.as_str())
+        .collect();
+    Ok(Json(json!({
+        "customer_id": customer_id,
+        "erased_orders": orders,
+    })))
+}
+
+// Тесты
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_module::AppState;
+    use crate::postgres_module::PgRepository;
+    use crate::tests::setup_database;
+    use axum::http::StatusCode;
+
+    async fn erase(
+        state: &Arc<OrderCache>,
+        repo: &Repository,
+        customer_id: &str,
+    ) -> serde_json::Value {
+        let response = erase_customer(
+            state.clone(),
+            Path(customer_id.to_string()),
+            repo.clone(),
+            Shutdown::new(),
+        )
+        .await
+        .unwrap()
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_erase_customer() {
+        let (pool, _schema) = setup_database().await;
+        let repo: Repository = Arc::new(PgRepository::new(pool));
+        let state = Arc::new(OrderCache::new(AppState::new()));
+        let mut orders = Vec::new();
+        let mut raws = Vec::new();
+        for file_path in [
+            "models/model1.json",
+            "models/model2.json",
+            "models/model_extended.json",
+        ] {
+            let raw = std::fs::read_to_string(file_path).unwrap();
+            let order: Order = serde_json::from_str(&raw).unwrap();
+            crate::ingest_order(&*repo, &state, order.clone(), &raw)
+                .await
+                .unwrap();
+            orders.push(order);
+            raws.push(raw);
+        }
+        // один из заказов покупателя вытеснен из кэша
+        state.write(|cache| {
+            let seq = cache.get_order(&orders[2].order_uid).unwrap().seq;
+            cache.remove_order(&orders[2].order_uid, seq)
+        });
+
+        let body = erase(&state, &repo, "test").await;
+        let mut expected = vec![orders[0].order_uid.clone(), orders[2].order_uid.clone()];
+        expected.sort();
+        assert_eq!(body["erased_orders"], json!(expected));
+
+        for order in [&orders[0], &orders[2]] {
+            let stored = repo.get(&order.order_uid).await.unwrap().unwrap();
+            assert_eq!(stored.delivery.name, ERASED);
+            assert_eq!(stored.delivery.email, ERASED);
+            assert_eq!(stored.delivery.city, order.delivery.city);
+            assert_eq!(
+                serde_json::to_value(&stored.payment).unwrap(),
+                serde_json::to_value(&order.payment).unwrap()
+            );
+            assert_eq!(stored.version, 2);
+
+            // исходный документ обезличен так же
+            let (raw, _) = repo.raw_document(&order.order_uid).await.unwrap().unwrap();
+            let raw = raw.unwrap();
+            let document: serde_json::Value = serde_json::from_str(&raw).unwrap();
+            assert_eq!(document["delivery"]["phone"], ERASED);
+            assert_eq!(document["delivery"]["zip"], order.delivery.zip.as_str());
+            assert!(!raw.contains(&order.delivery.email));
+        }
+        let cached = state.read().get_order(&orders[0].order_uid).unwrap();
+        assert_eq!(cached.delivery.phone, ERASED);
+        assert_eq!(cached.version, 2);
+        assert!(state.read().get_order(&orders[2].order_uid).is_none());
+
+        // заказ другого покупателя не изменен
+        let other = repo.get(&orders[1].order_uid).await.unwrap().unwrap();
+        assert_eq!(other.delivery.name, orders[1].delivery.name);
+        assert_eq!(other.version, 1);
+        let (raw, _) = repo
+            .raw_document(&orders[1].order_uid)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(raw.as_ref(), Some(&raws[1]));
+
+        // повторная отправка исходного заказа теперь конфликтует с обезличенным
+        match crate::ingest_order(&*repo, &state, orders[0].clone(), &raws[0])
+            .await
+            .unwrap()
+        {
+            crate::Ingestion::Conflict(conflicts) => assert_eq!(conflicts.len(), 4),
+            _ => panic!("expected a conflict"),
+        }
+
+        // повторное удаление ничего не меняет
+        let body = erase(&state, &repo, "test").await;
+        assert_eq!(body["erased_orders"], json!([]));
+    }
+}
@@ -0,0 +1,186 @@
+use crate::idempotency_module::FieldDiff;
+use crate::validation_module::Violation;
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde_json::json;
+
+// Ошибки обработки запросов; каждая ошибка отдается клиенту как application/problem+json
+// (RFC 7807) с машиночитаемым полем "code"
+#[derive(Debug)]
+pub enum AppError {
+    Conflict(String, Vec<FieldDiff>), // заказ с таким order_uid уже сохранен с другим содержимым
+    IdempotencyKeyReused(String),     // ключ идемпотентности использован для другого запроса
+    Validation(Vec<Violation>),       // нарушены бизнес-правила
+    InvalidOrder(String),             // json корректен, но не соответствует структуре заказа
+    MalformedBody(String),            // тело запроса не является json
+    InvalidHeader(String),            // некорректный заголовок Idempotency-Key
+    InvalidQuery(String),             // некорректные параметры запроса
+    NotFound(String),                 // заказ не найден
+    VersionMismatch(String, Option<i64>), // If-Match не совпал с версией заказа (None — заказа нет)
+    Unavailable(String),              // база данных недоступна (текст исходной ошибки)
+    Internal(String),                 // прочие ошибки сервера (текст исходной ошибки)
+}
+
+impl AppError {
+    fn status(&self) -> StatusCode {
+        match self {
+            AppError::Conflict(..) => StatusCode::CONFLICT,
+            AppError::Validation(_)
+            | AppError::InvalidOrder(_)
+            | AppError::IdempotencyKeyReused(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::MalformedBody(_) | AppError::InvalidHeader(_) | AppError::InvalidQuery(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::VersionMismatch(..) => StatusCode::PRECONDITION_FAILED,
+            AppError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Conflict(..) => "order_exists",
+            AppError::IdempotencyKeyReused(_) => "idempotency_key_reused",
+            AppError::Validation(_) => "validation_failed",
+            AppError::InvalidOrder(_) => "invalid_order",
+            AppError::MalformedBody(_) => "malformed_body",
+            AppError::InvalidHeader(_) => "invalid_idempotency_key",
+            AppError::InvalidQuery(_) => "invalid_query",
+            AppError::NotFound(_) => "order_not_found",
+            AppError::VersionMismatch(..) => "version_mismatch",
+            AppError::Unavailable(_) => "database_unavailable",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    fn detail(&self) -> String {
+        match self {
+            AppError::Conflict(uid, _) => {
+                format!("order '{}' already exists with different content", uid)
+            }
+            AppError::IdempotencyKeyReused(key) => format!(
+                "Idempotency-Key '{}' was already used for a different order",
+                key
+            ),
+            AppError::Validation(violations) => {
+                format!("order violates {} business rule(s)", violations.len())
+            }
+            AppError::NotFound(uid) => format!("order '{}' not found", uid),
+            AppError::VersionMismatch(uid, Some(version)) => format!(
+                "order '{}' has version {}, which does not match If-Match",
+                uid, version
+            ),
+            AppError::VersionMismatch(uid, None) => {
+                format!("order '{}' does not exist, but If-Match was given", uid)
+            }
+            AppError::InvalidOrder(detail)
+            | AppError::MalformedBody(detail)
+            | AppError::InvalidHeader(detail)
+            | AppError::InvalidQuery(detail) => detail.clone(),
+            // текст ошибок БД клиенту не передается
+            AppError::Unavailable(_) => "database is unavailable".to_string(),
+            AppError::Internal(_) => "internal server error".to_string(),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        // исходная ошибка сервера остается в журнале, а не в ответе клиенту
+        match &self {
+            AppError::Unavailable(err) | AppError::Internal(err) => {
+                tracing::error!(code = self.code(), error = %err, "request failed")
+            }
+            _ => tracing::info!(code = self.code(), detail = %self.detail(), "request rejected"),
+        }
+        let mut body = json!({
+            "type": "about:blank",
+            "title": status.canonical_reason().unwrap_or_default(),
+            "status": status.as_u16(),
+            "code": self.code(),
+            "detail": self.detail(),
+        });
+        // дополнительные поля, по которым клиент может понять причину ошибки
+        match &self {
+            AppError::Conflict(uid, conflicts) => {
+                body["order_uid"] = json!(uid);
+                body["conflicts"] = json!(conflicts);
+            }
+            AppError::NotFound(uid) => body["order_uid"] = json!(uid),
+            AppError::VersionMismatch(uid, version) => {
+                body["order_uid"] = json!(uid);
+                body["etag"] = json!(version.map(crate::update_module::etag));
+            }
+            AppError::Validation(violations) => body["errors"] = json!(violations),
+            _ => {}
+        }
+
+        (
+            status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            body.to_string(),
+        )
+            .into_response()
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(err: sqlx::Error) -> Self {
+        match &err {
+            sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_) => AppError::Unavailable(err.to_string()),
+            // класс 08 — ошибки соединения, 57P01..57P03 — остановка или запуск сервера БД
+            sqlx::Error::Database(db_err)
+                if db_err
+                    .code()
+                    .is_some_and(|code| code.starts_with("08") || code.starts_with("57P")) =>
+            {
+                AppError::Unavailable(err.to_string())
+            }
+            _ => AppError::Internal(err.to_string()),
+        }
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        match rejection {
+            JsonRejection::JsonDataError(err) => AppError::InvalidOrder(err.body_text()),
+            other => AppError::MalformedBody(other.body_text()),
+        }
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        AppError::InvalidQuery(rejection.body_text())
+    }
+}
+
+// Тесты
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_database_errors() {
+        let unavailable = AppError::from(sqlx::Error::PoolTimedOut).into_response();
+        assert_eq!(unavailable.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            unavailable.headers()[header::CONTENT_TYPE],
+            "application/problem+json"
+        );
+
+        let internal = AppError::from(sqlx::Error::RowNotFound);
+        assert_eq!(internal.code(), "internal_error");
+        assert_eq!(
+            internal.into_response().status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
@@ -0,0 +1,209 @@
+use crate::db_module::OrderCache;
+use crate::repository_module::Repository;
+use crate::shutdown_module::Shutdown;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Время ожидания ответа БД при проверке готовности
+const DB_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+// GET /healthz — процесс жив и обрабатывает запросы
+pub async fn healthz() -> impl IntoResponse {
+    Json(json!({ "status": "ok" }))
+}
+
+// GET /readyz — сервис готов принимать трафик: БД доступна, миграции применены,
+// кэш загружен и остановка не запрошена. Иначе 503 с результатами всех проверок
+pub async fn readyz(
+    state: Arc<OrderCache>,
+    repo: Repository,
+    shutdown: Shutdown,
+) -> impl IntoResponse {
+    let database = match tokio::time::timeout(DB_CHECK_TIMEOUT, repo.ping()).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(err)) => Err(err.to_string()),
+        Err(_) => Err(format!("no response within {:?}", DB_CHECK_TIMEOUT)),
+    };
+    let migrations = match &database {
+        Ok(()) => match repo.pending_migrations().await {
+            Ok(pending) if pending.is_empty() => Ok(()),
+            Ok(pending) => Err(format!("pending migrations: {:?}", pending)),
+            Err(err) => Err(err.to_string()),
+        },
+        Err(_) => Err("database is unavailable".to_string()),
+    };
+    let cache = if state.is_warm() {
+        Ok(())
+    } else {
+        Err("warming up".to_string())
+    };
+    let running = if shutdown.is_triggered() {
+        Err("shutting down".to_string())
+    } else {
+        Ok(())
+    };
+
+    let checks = [
+        ("database", database),
+        ("migrations", migrations),
+        ("cache", cache),
+        ("shutdown", running),
+    ];
+    let ready = checks.iter().all(|(_, check)| check.is_ok());
+    let checks: serde_json::Map<String, serde_json::Value> = checks
+        .into_iter()
+        .map(|(name, check)| {
+            let value = match check {
+                Ok(()) => json!("ok"),
+                Err(err) => json!(err),
+            };
+            (name.to_string(), value)
+        })
+        .collect();
+
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = json!({
+        "status": if ready { "ready" } else { "not_ready" },
+        "checks": checks,
+    });
+    (status, Json(body))
+}
+
+// GET /status — подробное состояние сервиса для диагностики; pool — null, если
+// хранилище работает без пула подключений
+pub async fn status(state: Arc<OrderCache>, repo: Repository) -> impl IntoResponse {
+    let snapshot = state.read();
+    let limits = snapshot.limits();
+    let pool = repo.pool_status().map(|pool| {
+        json!({
+            "size": pool.size,
+            "idle": pool.idle,
+            "max_connections": pool.max_connections,
+        })
+    });
+    Json(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "pool": pool,
+        "cache": {
+            "warm": state.is_warm(),
+            "entries": snapshot.len(),
+            "bytes": snapshot.bytes(),
+            "max_entries": limits.max_entries,
+            "max_bytes": limits.max_bytes,
+        },
+        "last_ingestion": state.last_ingestion().map(|time| time.to_rfc3339()),
+    }))
+}
+
+// Тесты
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_module::{self, AppState, CacheLimits};
+    use crate::memory_module::MemoryRepository;
+    use crate::postgres_module::PgRepository;
+    use crate::tests::setup_database;
+
+    async fn body_json(response: impl IntoResponse) -> (StatusCode, serde_json::Value) {
+        let response = response.into_response();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_readiness() {
+        let (pool, _schema) = setup_database().await;
+        let repo: Repository = Arc::new(PgRepository::new(pool.clone()));
+        let state = Arc::new(OrderCache::warming(CacheLimits::default()));
+        let shutdown = Shutdown::new();
+
+        // Пока кэш загружается, сервис жив, но не готов
+        let (status, body) = body_json(healthz().await).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ok");
+        let (status, body) =
+            body_json(readyz(state.clone(), repo.clone(), shutdown.clone()).await).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["checks"]["database"], "ok");
+        assert_eq!(body["checks"]["migrations"], "ok");
+        assert_eq!(body["checks"]["cache"], "warming up");
+
+        state.finish_warm_up(AppState::new());
+        let (status, body) =
+            body_json(readyz(state.clone(), repo.clone(), shutdown.clone()).await).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ready");
+
+        // Непримененная миграция
+        let head = db_module::MIGRATOR
+            .iter()
+            .map(|migration| migration.version)
+            .max()
+            .unwrap();
+        db_module::revert_migrations(&pool, head - 1).await.unwrap();
+        let (status, body) =
+            body_json(readyz(state.clone(), repo.clone(), shutdown.clone()).await).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            body["checks"]["migrations"],
+            format!("pending migrations: [{}]", head)
+        );
+        db_module::run_migrations(&pool).await.unwrap();
+
+        // После запроса остановки сервис перестает быть готовым
+        shutdown.trigger();
+        let (status, body) = body_json(readyz(state.clone(), repo.clone(), shutdown).await).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["checks"]["shutdown"], "shutting down");
+
+        // Недоступная БД
+        pool.close().await;
+        let (status, body) =
+            body_json(readyz(state.clone(), repo.clone(), Shutdown::new()).await).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_ne!(body["checks"]["database"], "ok");
+    }
+
+    #[tokio::test]
+    async fn test_status() {
+        let (pool, _schema) = setup_database().await;
+        let repo: Repository = Arc::new(PgRepository::new(pool));
+        let state = Arc::new(OrderCache::new(AppState::with_limits(CacheLimits {
+            max_entries: Some(10),
+            max_bytes: None,
+        })));
+
+        let (code, body) = body_json(status(state.clone(), repo.clone()).await).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(body["cache"]["entries"], 0);
+        assert_eq!(body["cache"]["max_entries"], 10);
+        assert_eq!(body["last_ingestion"], serde_json::Value::Null);
+        assert!(body["pool"]["size"].as_u64().unwrap() >= 1);
+
+        let raw = std::fs::read_to_string("models/model1.json").unwrap();
+        let order = serde_json::from_str(&raw).unwrap();
+        assert!(matches!(
+            crate::ingest_order(&*repo, &state, order, &raw)
+                .await
+                .unwrap(),
+            crate::Ingestion::Stored
+        ));
+        let (_, body) = body_json(status(state.clone(), repo).await).await;
+        assert_eq!(body["cache"]["entries"], 1);
+        assert!(body["last_ingestion"].is_string());
+
+        // хранилище в памяти без пула
+        let (_, body) = body_json(status(state, Arc::new(MemoryRepository::new())).await).await;
+        assert_eq!(body["pool"], serde_json::Value::Null);
+    }
+}
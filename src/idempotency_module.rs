@@ -0,0 +1,179 @@
+use crate::db_module::Order;
+use axum::http::{HeaderMap, HeaderName};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+// Ключ идемпотентности, который клиент передает при повторе запроса
+pub static IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+// Признак ответа на повторный запрос: заказ уже был сохранен раньше
+pub static IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+// Максимальная длина ключа идемпотентности
+const MAX_KEY_LEN: usize = 255;
+
+// Поле, значение которого у сохраненного и полученного заказов различается
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldDiff {
+    pub field: String,
+    pub stored: Value,
+    pub received: Value,
+}
+
+// Ключ, сохраненный после успешного приема заказа
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedKey {
+    pub order_uid: String,
+    pub content_hash: String,
+}
+
+// Хэш содержимого заказа: sha256 от json в hex. Поля сериализуются в порядке
+// объявления структуры, поэтому порядок полей в теле запроса на хэш не влияет
+pub fn content_hash(order: &Order) -> String {
+    let json = serde_json::to_vec(order).expect("order is always serializable");
+    Sha256::digest(json)
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+}
+
+// Значение заголовка Idempotency-Key; пустой, слишком длинный или не ASCII ключ — ошибка
+pub fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, String> {
+    let Some(value) = headers.get(&IDEMPOTENCY_KEY) else {
+        return Ok(None);
+    };
+    match value.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => Ok(Some(key.to_string())),
+        _ => Err(format!(
+            "Idempotency-Key must be 1 to {} visible ASCII characters",
+            MAX_KEY_LEN
+        )),
+    }
+}
+
+// Поля, различающиеся у двух заказов, в формате путей валидации: "payment.amount", "items[0].price"
+pub fn diff_orders(stored: &Order, received: &Order) -> Vec<FieldDiff> {
+    let mut diffs = Vec::new();
+    diff_values(
+        String::new(),
+        &serde_json::to_value(stored).expect("order is always serializable"),
+        &serde_json::to_value(received).expect("order is always serializable"),
+        &mut diffs,
+    );
+    diffs
+}
+
+fn diff_values(path: String, stored: &Value, received: &Value, diffs: &mut Vec<FieldDiff>) {
+    match (stored, received) {
+        (Value::Object(stored), Value::Object(received)) => {
+            // ключи обоих объектов по алфавиту: отсутствующее поле сравнивается с null
+            let keys: BTreeSet<&String> = stored.keys().chain(received.keys()).collect();
+            for key in keys {
+                let field = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                diff_values(
+                    field,
+                    stored.get(key).unwrap_or(&Value::Null),
+                    received.get(key).unwrap_or(&Value::Null),
+                    diffs,
+                );
+            }
+        }
+        (Value::Array(stored), Value::Array(received)) => {
+            // отсутствующий элемент одного из массивов сравнивается с null
+            for index in 0..stored.len().max(received.len()) {
+                diff_values(
+                    format!("{}[{}]", path, index),
+                    stored.get(index).unwrap_or(&Value::Null),
+                    received.get(index).unwrap_or(&Value::Null),
+                    diffs,
+                );
+            }
+        }
+        _ if stored != received => diffs.push(FieldDiff {
+            field: path,
+            stored: stored.clone(),
+            received: received.clone(),
+        }),
+        _ => {}
+    }
+}
+
+// Тесты
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn model(path: &str) -> Order {
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let order = model("models/model1.json");
+        let hash = content_hash(&order);
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, content_hash(&order.clone()));
+
+        // порядок полей в исходном json не важен: Value выводит ключи по алфавиту
+        let value: Value =
+            serde_json::from_str(&std::fs::read_to_string("models/model1.json").unwrap()).unwrap();
+        let reordered: Order = serde_json::from_str(&value.to_string()).unwrap();
+        assert_eq!(hash, content_hash(&reordered));
+
+        assert_ne!(hash, content_hash(&model("models/model2.json")));
+    }
+
+    #[test]
+    fn test_diff_orders() {
+        let stored = model("models/model1.json");
+        assert!(diff_orders(&stored, &stored).is_empty());
+
+        let mut received = stored.clone();
+        received.delivery.name = "Other Name".to_string();
+        received.items[0].price += 1;
+        received.items.push(received.items[0].clone());
+        let diffs = diff_orders(&stored, &received);
+
+        assert_eq!(diffs.len(), 3);
+        assert_eq!(
+            diffs[0],
+            FieldDiff {
+                field: "delivery.name".to_string(),
+                stored: Value::from(stored.delivery.name.clone()),
+                received: Value::from("Other Name"),
+            }
+        );
+        assert_eq!(diffs[1].field, "items[0].price");
+        assert_eq!(diffs[2].field, "items[1]");
+        assert_eq!(diffs[2].stored, Value::Null);
+
+        // новое поле, неизвестное схеме, тоже отличие
+        let mut received = stored.clone();
+        received
+            .extras
+            .insert("source".to_string(), Value::from("wb-v2"));
+        let diffs = diff_orders(&stored, &received);
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].field, "source");
+        assert_eq!(diffs[0].stored, Value::Null);
+    }
+
+    #[test]
+    fn test_idempotency_key_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(idempotency_key(&headers), Ok(None));
+        headers.insert(&IDEMPOTENCY_KEY, HeaderValue::from_static("retry-1"));
+        assert_eq!(idempotency_key(&headers), Ok(Some("retry-1".to_string())));
+        headers.insert(&IDEMPOTENCY_KEY, HeaderValue::from_static(""));
+        assert!(idempotency_key(&headers).is_err());
+    }
+}
@@ -0,0 +1,182 @@
+use axum::http::{HeaderName, HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::str::FromStr;
+use std::time::Instant;
+use tracing::Instrument;
+use tracing_subscriber::EnvFilter;
+
+// Заголовок с идентификатором запроса; значение клиента сохраняется, иначе генерируется новое
+pub static REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+// Формат журнала: читаемый текст или json (одна запись на строку)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+// Проверка фильтра уровней в формате RUST_LOG, например "info" или "info,sqlx=warn"
+pub fn parse_filter(level: &str) -> Result<EnvFilter, String> {
+    EnvFilter::try_new(level).map_err(|err| err.to_string())
+}
+
+// Настройка глобального журнала; фильтр проверяется при чтении настроек
+pub fn init(format: LogFormat, level: &str) {
+    let filter = parse_filter(level).expect("log level is validated with the config");
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        LogFormat::Text => builder.init(),
+        // поля события выводятся на верхнем уровне записи, поля span'а запроса — в "span"
+        LogFormat::Json => builder.json().flatten_event(true).init(),
+    }
+}
+
+// Middleware, создающий span запроса с request_id; обработчики дописывают в него order_uid.
+// По завершении в журнал пишется статус и время обработки
+pub async fn request_span<B>(request: Request<B>, next: Next<B>) -> Response {
+    let request_id = request
+        .headers()
+        .get(&REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty() && value.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %request.method(),
+        path = %request.uri().path(),
+        order_uid = tracing::field::Empty,
+    );
+    let started = Instant::now();
+
+    let mut response = next.run(request).instrument(span.clone()).await;
+
+    span.in_scope(|| {
+        tracing::info!(
+            status = response.status().as_u16(),
+            latency_ms = started.elapsed().as_secs_f64() * 1000.0,
+            "request completed"
+        )
+    });
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID.clone(), value);
+    }
+    response
+}
+
+// Добавление order_uid в span текущего запроса
+pub fn record_order_uid(order_uid: &str) {
+    tracing::Span::current().record("order_uid", order_uid);
+}
+
+// Тесты
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::extract::Path;
+    use axum::routing::get;
+    use axum::{middleware, Router};
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+
+    // Буфер, в который пишет журнал в тесте
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_span() {
+        let buffer = Buffer::default();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .flatten_event(true)
+            .with_writer({
+                let buffer = buffer.clone();
+                move || buffer.clone()
+            })
+            .finish();
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let app = Router::new()
+            .route(
+                "/order/:order_uid",
+                get(|Path(order_uid): Path<String>| async move {
+                    record_order_uid(&order_uid);
+                    tracing::warn!("order lookup");
+                }),
+            )
+            .layer(middleware::from_fn(request_span));
+
+        // идентификатор клиента возвращается в ответе
+        let request = Request::get("/order/uid1")
+            .header(&REQUEST_ID, "client-id")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.headers()[&REQUEST_ID], "client-id");
+
+        // без заголовка идентификатор генерируется
+        let request = Request::get("/order/uid2").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let generated = response.headers()[&REQUEST_ID]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(generated.len(), 36);
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let records: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let lookup: Vec<&serde_json::Value> = records
+            .iter()
+            .filter(|record| record["message"] == "order lookup")
+            .collect();
+        assert_eq!(lookup.len(), 2);
+        assert_eq!(lookup[0]["level"], "WARN");
+        assert_eq!(lookup[0]["span"]["request_id"], "client-id");
+        assert_eq!(lookup[0]["span"]["order_uid"], "uid1");
+        assert_eq!(lookup[1]["span"]["request_id"], generated.as_str());
+
+        let completed = records
+            .iter()
+            .find(|record| record["message"] == "request completed")
+            .unwrap();
+        assert_eq!(completed["status"], 200);
+        assert_eq!(completed["span"]["path"], "/order/uid1");
+    }
+
+    #[test]
+    fn test_log_settings() {
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert!("xml".parse::<LogFormat>().is_err());
+        assert!(parse_filter("info,sqlx=warn").is_ok());
+        assert!(parse_filter("info,sqlx=loud").is_err());
+    }
+}
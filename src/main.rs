@@ -1,273 +1,689 @@
-use crate::db_module::AppState;
-use crate::db_module::Order;
+use crate::config_module::{Cli, Command, Config, MigrateAction, Storage};
+use crate::db_module::{AppState, OrderCache};
+use crate::db_module::{Migrations, Order};
+use crate::error_module::AppError;
+use crate::idempotency_module::FieldDiff;
+use crate::memory_module::MemoryRepository;
+use crate::postgres_module::PgRepository;
+use crate::raw_module::RawJson;
+use crate::repository_module::{Keyed, OrderRepository, Repository};
+use crate::shutdown_module::Shutdown;
+use crate::sqlite_module::SqliteRepository;
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::extract::{BodyStream, Path, Query};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware;
+use axum::response::{IntoResponse, Response};
 use axum::routing::get;
-use axum::{extract::Json, response::IntoResponse, routing::post, Router};
+use axum::{extract::Json, routing::post, Router};
+use clap::Parser;
 use dotenv::dotenv;
-use sqlx::postgres::PgPool;
-use sqlx::Error;
-use std::env;
-use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
+use sqlx::migrate::Migrate;
+use sqlx::{Error, Pool};
+use std::sync::Arc;
+mod bulk_module;
+mod config_module;
 mod db_module;
+mod erasure_module;
+mod error_module;
+mod health_module;
+mod idempotency_module;
+mod logging_module;
+mod memory_module;
+mod metrics_module;
+mod nats_module;
+mod postgres_module;
+mod query_module;
+mod raw_module;
+mod repository_module;
+mod shutdown_module;
+mod sqlite_module;
+mod ui_module;
+mod update_module;
+mod validation_module;
 
 #[tokio::main]
-async fn main() -> Result<(), sqlx::Error> {
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let pool = PgPool::connect(&database_url).await?; // подключение к БД
+    let cli = Cli::parse();
+    // настройки из config.toml, окружения и флагов; при ошибках выводим все некорректные параметры
+    let config = match Config::load(&cli) {
+        Ok(config) => config,
+        Err(err) => {
+            // журнал еще не настроен, ошибки выводятся как есть
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+    logging_module::init(config.log_format, &config.log_level);
+    let repo: Repository = match &config.database.storage {
+        Storage::Postgres(connect_options) => {
+            let pool = config.database.connect(connect_options).await?; // подключение к БД
 
-    let tables = vec!["payment", "delivery", "orders", "item"]; // массив с названиями таблиц которые необходимо создать в БД
+            // подкоманда "migrate" управляет схемой БД и не запускает сервер
+            if let Some(Command::Migrate { action }) = cli.command {
+                return migrate_command(&pool, action.unwrap_or(MigrateAction::Up)).await;
+            }
 
-    // Проходим по каждому имени таблицы и проверяем её существование
-    for table in tables {
-        let exists_query = r#"
-            SELECT EXISTS (
-                SELECT 1
-                FROM information_schema.tables
-                WHERE table_schema = 'public'
-                AND table_name = $1
-            );
-        "#; // выполняем запрос для проверки существования таблицы
-        let exists: (bool,) = sqlx::query_as(exists_query)
-            .bind(table)
-            .fetch_one(&pool)
-            .await?;
+            // при запуске сервера применяем все недостающие миграции
+            db_module::run_migrations(&pool).await?;
+            Arc::new(PgRepository::new(pool))
+        }
+        Storage::Sqlite(connect_options) => {
+            let pool = config.database.connect_sqlite(connect_options).await?;
+            if let Some(Command::Migrate { action }) = cli.command {
+                return migrate_command(&pool, action.unwrap_or(MigrateAction::Up)).await;
+            }
+            db_module::run_migrations(&pool).await?;
+            Arc::new(SqliteRepository::new(pool))
+        }
+        Storage::Memory => {
+            if cli.command.is_some() {
+                return Err("migrations require a postgres:// or sqlite: database.url".into());
+            }
+            tracing::warn!("database.url is memory:, orders are lost on shutdown");
+            Arc::new(MemoryRepository::new())
+        }
+    };
 
-        if !exists.0 {
-            // если таблицы нет в базе данных создаем ее
-            println!("the '{}' table does not exist, creating a table", table);
-            db_module::create_table(table, &pool).await?;
-        } else {
-            println!("the '{}' table exists.", table);
+    // кэш заказов загружается из хранилища в фоне, до окончания загрузки /readyz отвечает 503
+    let app_state = Arc::new(OrderCache::warming(config.cache));
+    tokio::spawn(warm_up(app_state.clone(), repo.clone(), config.cache));
+
+    // остановка по SIGINT/SIGTERM
+    let shutdown = Shutdown::new();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown_module::wait_for_signal().await;
+            shutdown.trigger();
         }
+    });
+
+    // запуск подписчика NATS, если брокер указан в настройках
+    let subscriber = config.nats.clone().map(|nats_config| {
+        tokio::spawn(nats_module::run_subscriber(
+            nats_config,
+            repo.clone(),
+            app_state.clone(),
+            shutdown.clone(),
+        ))
+    });
+
+    // инициализация маршрутов
+    let app = app(app_state.clone(), repo.clone(), shutdown.clone());
+
+    let addr = config.socket_addr(); // адрес сервера и порт из настроек
+    tracing::info!(%addr, "listening");
+
+    // Создаем сервер на указанном адресе; после сигнала остановки новые соединения
+    // не принимаются, а начатые запросы обрабатываются до конца
+    let mut server = tokio::spawn(
+        axum::Server::bind(&addr)
+            .serve(app.into_make_service())
+            .with_graceful_shutdown({
+                let shutdown = shutdown.clone();
+                async move { shutdown.triggered().await }
+            }),
+    );
+    tokio::select! {
+        result = &mut server => {
+            // сервер остановился без сигнала
+            result??;
+            return Ok(());
+        }
+        _ = shutdown.triggered() => {}
     }
 
-    let app_state = Arc::new(Mutex::new(AppState::new())); // инициализация переменной которая хранит заказы
+    // Ожидание незавершенных записей заказов, сервера и подписчика NATS не дольше shutdown_timeout
+    tracing::info!(
+        timeout = ?config.shutdown_timeout,
+        "shutdown requested, waiting for in-flight work"
+    );
+    let deadline = tokio::time::Instant::now() + config.shutdown_timeout;
+    let summary = shutdown.drain(deadline).await;
+    let server_status = finish_before(deadline, server).await;
+    let subscriber_status = match subscriber {
+        Some(subscriber) => finish_before(deadline, subscriber).await,
+        None => "not running",
+    };
+    repo.close().await;
 
-    let mut state = app_state.lock().unwrap();
-    state
-        .load_orders(&pool)
-        .await
-        .expect("Failed to load orders"); // загрузка заказов из базы данных, в том случае если они там есть
+    tracing::info!(
+        drained_writes = summary.drained,
+        abandoned_writes = summary.abandoned,
+        http_server = server_status,
+        nats_subscriber = subscriber_status,
+        "shutdown complete, storage closed"
+    );
+    Ok(())
+}
 
-    // инициализация маршрутов
-    let app = Router::new()
+// Маршруты сервиса вместе с middleware; используется сервером и тестами
+pub(crate) fn app(app_state: Arc<OrderCache>, repo: Repository, shutdown: Shutdown) -> Router {
+    Router::new()
         .route(
             "/order",
             post({
-                let pool = pool.clone();
+                let repo = repo.clone();
                 let app_state = app_state.clone();
-                move |input: Json<db_module::Order>| state_handler(app_state, input, pool)
-                // передаем пул для подключения к БД и данные заказов
+                let shutdown = shutdown.clone();
+                move |headers: HeaderMap,
+                      input: Result<RawJson<db_module::Order>, JsonRejection>| {
+                    state_handler(app_state, headers, input, repo, shutdown)
+                }
+                // передаем хранилище и данные заказов
             }),
         ) // post запрос на который отправляются заказы
+        .route(
+            "/order/:order_uid",
+            get({
+                let repo = repo.clone();
+                let app_state = app_state.clone();
+                move |uid: Path<String>,
+                      params: Result<Query<raw_module::OrderParams>, QueryRejection>| {
+                    get_order(app_state, uid, params, repo)
+                }
+            })
+            .put({
+                let repo = repo.clone();
+                let app_state = app_state.clone();
+                let shutdown = shutdown.clone();
+                move |uid: Path<String>,
+                      headers: HeaderMap,
+                      input: Result<RawJson<db_module::Order>, JsonRejection>| {
+                    update_module::put_order(app_state, uid, headers, input, repo, shutdown)
+                }
+            })
+            .patch({
+                let repo = repo.clone();
+                let app_state = app_state.clone();
+                let shutdown = shutdown.clone();
+                move |uid: Path<String>,
+                      headers: HeaderMap,
+                      patch: Result<Json<serde_json::Value>, JsonRejection>| {
+                    update_module::patch_order(app_state, uid, headers, patch, repo, shutdown)
+                }
+            })
+            .delete({
+                let repo = repo.clone();
+                let app_state = app_state.clone();
+                let shutdown = shutdown.clone();
+                move |uid: Path<String>, headers: HeaderMap| {
+                    update_module::delete_order(app_state, uid, headers, repo, shutdown)
+                }
+            }), // замена, частичное изменение и удаление заказа
+        )
+        .route(
+            "/customer/:customer_id/erasure",
+            post({
+                let repo = repo.clone();
+                let app_state = app_state.clone();
+                let shutdown = shutdown.clone();
+                move |customer_id: Path<String>| {
+                    erasure_module::erase_customer(app_state, customer_id, repo, shutdown)
+                }
+            }), // удаление персональных данных покупателя
+        )
+        .route(
+            "/orders/bulk",
+            post({
+                let repo = repo.clone();
+                let app_state = app_state.clone();
+                let shutdown = shutdown.clone();
+                move |headers: HeaderMap, body: BodyStream| {
+                    bulk_module::bulk_orders(app_state, headers, body, repo, shutdown)
+                }
+            }), // прием множества заказов json-массивом или NDJSON
+        )
         .route(
             "/orders",
             get({
                 let app_state = app_state.clone();
-                move || get_state(app_state)
+                move |query: Result<Query<query_module::OrdersQuery>, QueryRejection>| {
+                    get_state(app_state, query)
+                }
             }), // get запрос который возвращает заказы
-        );
+        )
+        .route(
+            "/ui",
+            get({
+                let app_state = app_state.clone();
+                move |params: Option<Query<ui_module::SearchParams>>| {
+                    ui_module::search_page(app_state, params)
+                }
+            }), // страница поиска заказа для операторов
+        )
+        .route("/healthz", get(health_module::healthz))
+        .route(
+            "/readyz",
+            get({
+                let repo = repo.clone();
+                let app_state = app_state.clone();
+                let shutdown = shutdown.clone();
+                move || health_module::readyz(app_state, repo, shutdown)
+            }),
+        )
+        .route(
+            "/status",
+            get({
+                let repo = repo.clone();
+                let app_state = app_state.clone();
+                move || health_module::status(app_state, repo)
+            }),
+        )
+        .route(
+            "/metrics",
+            get({
+                let repo = repo.clone();
+                let app_state = app_state.clone();
+                move || metrics_module::metrics(app_state, repo)
+            }),
+        )
+        .route_layer(middleware::from_fn(metrics_module::track_http)) // метрики HTTP запросов
+        .layer(middleware::from_fn(logging_module::request_span)) // span запроса с request_id
+}
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 8081)); // указываем адрес сервера и порт
+// Загрузка заказов из хранилища в кэш; при ошибке загрузка повторяется
+async fn warm_up(state: Arc<OrderCache>, repo: Repository, limits: db_module::CacheLimits) {
+    loop {
+        let mut loaded = AppState::with_limits(limits);
+        match loaded.load_orders(&*repo).await {
+            Ok(()) => {
+                tracing::info!(
+                    orders = loaded.len(),
+                    bytes = loaded.bytes(),
+                    "orders loaded from storage"
+                );
+                state.finish_warm_up(loaded);
+                return;
+            }
+            Err(err) => tracing::error!(error = %err, "failed to load orders, retrying"),
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+    }
+}
 
-    // Создаем сервер на указанном адресе
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+// Ожидание завершения задачи до deadline; не успевшая задача прерывается
+async fn finish_before<T>(
+    deadline: tokio::time::Instant,
+    mut task: tokio::task::JoinHandle<T>,
+) -> &'static str {
+    if tokio::time::timeout_at(deadline, &mut task).await.is_ok() {
+        return "stopped";
+    }
+    task.abort();
+    "aborted"
+}
+
+// Подкоманда управления миграциями:
+//   migrate [up]          — применить все недостающие миграции
+//   migrate down [VERSION] — откатить миграции новее VERSION (по умолчанию последнюю)
+//   migrate status        — показать список миграций и их состояние
+async fn migrate_command<DB>(
+    pool: &Pool<DB>,
+    action: MigrateAction,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    DB: Migrations,
+    DB::Connection: Migrate,
+{
+    match action {
+        MigrateAction::Up => db_module::run_migrations(pool).await?,
+        MigrateAction::Down { version } => {
+            let target = match version {
+                Some(version) => version,
+                None => {
+                    // откатываем только последнюю примененную миграцию
+                    let applied = db_module::applied_migrations(pool).await?;
+                    let latest = applied.iter().copied().max().unwrap_or(0);
+                    applied
+                        .into_iter()
+                        .filter(|v| *v < latest)
+                        .max()
+                        .unwrap_or(0)
+                }
+            };
+            db_module::revert_migrations(pool, target).await?;
+        }
+        MigrateAction::Status => {}
+    }
+
+    let applied = db_module::applied_migrations(pool).await?;
+    for migration in DB::migrator()
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+    {
+        let mark = if applied.contains(&migration.version) {
+            "applied"
+        } else {
+            "pending"
+        };
+        println!(
+            "{:>4} {:<30} {}",
+            migration.version, migration.description, mark
+        );
+    }
     Ok(())
 }
 
 // обработчик post запросов
 async fn state_handler(
-    state: Arc<Mutex<AppState>>,
-    Json(payload): Json<db_module::Order>,
-    _pool: PgPool, // извлекаем пул подключений
-                   // извлекаем общее состояние
-) -> impl IntoResponse {
-    match insert_order(&_pool, &payload.clone()).await {
-        Ok(true) => {
-            let mut app_state = state.lock().unwrap();
-            app_state.add_order(payload);
-            (axum::http::StatusCode::OK, "Order received\n")
-        }
-        Ok(false) => {
-            // данные уже содержатся в базе
-            return (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                "Order dont received: data already exists",
-            );
-        }
-        Err(_) => {
-            // ошибка вставки
-            return (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                "Order don't received: server error",
-            );
-        }
+    state: Arc<OrderCache>,
+    headers: HeaderMap,
+    payload: Result<RawJson<db_module::Order>, JsonRejection>,
+    repo: Repository, // хранилище заказов
+    shutdown: Shutdown,
+) -> Result<Response, AppError> {
+    // при остановке сервиса запись дожидаются, прежде чем закрыть пул
+    let _write = shutdown.track();
+    let result = accept_order(&state, &headers, payload, &*repo).await;
+    metrics_module::METRICS.record_order(metrics_module::SOURCE_HTTP, &result);
+
+    // повтор получает тот же ответ, что и исходный запрос, с пометкой в заголовке
+    let mut response = (StatusCode::OK, "Order received\n").into_response();
+    if !result? {
+        response.headers_mut().insert(
+            idempotency_module::IDEMPOTENT_REPLAYED.clone(),
+            HeaderValue::from_static("true"),
+        );
     }
+    Ok(response)
 }
-async fn get_state(state: Arc<Mutex<AppState>>) -> impl IntoResponse {
-    // Получаем доступ к mutex guard
-    let locked_state = state.lock().unwrap();
 
-    // Возвращаем текущее состояние в виде JSON
-    Json(locked_state.get_orders()) // Здесь используется ссылка на locked_state
-}
+// Прием заказа; Ok(true) — заказ записан, Ok(false) — такой же заказ уже был сохранен
+async fn accept_order(
+    state: &Arc<OrderCache>,
+    headers: &HeaderMap,
+    payload: Result<RawJson<db_module::Order>, JsonRejection>,
+    repo: &dyn OrderRepository,
+) -> Result<bool, AppError> {
+    let key = idempotency_module::idempotency_key(headers).map_err(AppError::InvalidHeader)?;
+    // некорректный json -> 400, несоответствие структуре -> 422
+    let RawJson {
+        value: mut payload,
+        raw,
+    } = payload?;
+    logging_module::record_order_uid(&payload.order_uid);
 
-async fn insert_order(pool: &PgPool, order: &Order) -> Result<bool, Error> {
-    // Проверяем, содержится ли в базе запись с указанным "order_uid"
-    match check_order_exists(&pool, &order.order_uid).await {
-        Ok(true) => Ok(false), // запись уже есть в БД
-        Ok(false) => {
-            // запись отстутствует, выполняем вставку
-            let delivery_id: i32 = sqlx::query!(
-                r#"
-        INSERT INTO delivery (name, phone, zip, city, address, region, email)
-        VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id
-        "#,
-                order.delivery.name,
-                order.delivery.phone,
-                order.delivery.zip,
-                order.delivery.city,
-                order.delivery.address,
-                order.delivery.region,
-                order.delivery.email,
-            )
-            .fetch_one(pool)
-            .await?
-            .id;
-
-            let payment_id: i32 = sqlx::query!(
-        r#"
-        INSERT INTO payment (transaction, request_id, currency, provider, amount, payment_dt, bank, delivery_cost, goods_total, custom_fee)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING id
-        "#,
-        order.payment.transaction,
-        order.payment.request_id,
-        order.payment.currency,
-        order.payment.provider,
-        order.payment.amount,
-        order.payment.payment_dt,
-        order.payment.bank,
-        order.payment.delivery_cost,
-        order.payment.goods_total,
-        order.payment.custom_fee,
-    )
-    .fetch_one(pool)
-    .await?
-    .id;
-            sqlx::query!(
-        r#"
-        INSERT INTO orders (order_uid, track_number, entry, delivery_id, payment_id, locale, internal_signature, customer_id, delivery_service, shardkey, sm_id, date_created, oof_shard)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
-        "#,
-        order.order_uid,
-        order.track_number,
-        order.entry,
-        delivery_id,
-        payment_id,
-        order.locale,
-        order.internal_signature,
-        order.customer_id,
-        order.delivery_service,
-        order.shardkey,
-        order.sm_id,
-        order.date_created,
-        order.oof_shard,
-    )
-    .execute(pool)
-    .await?;
-            for item in &order.items {
-                sqlx::query!(
-            r#"
-            INSERT INTO item (chrt_id, track_number, price, rid, name, sale, size, total_price, nm_id, brand, status, order_uid)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
-            "#,
-            item.chrt_id,
-            item.track_number,
-            item.price,
-            item.rid,
-            item.name,
-            item.sale,
-            item.size,
-            item.total_price,
-            item.nm_id,
-            item.brand,
-            item.status,
-            order.order_uid,
-        )
-        .execute(pool)
-        .await?;
-            }
-            Ok(true)
+    // проверка бизнес-правил до записи в БД
+    validation_module::validate_order(&payload).map_err(AppError::Validation)?;
+
+    let Some(key) = key else {
+        let order_uid = payload.order_uid.clone();
+        return match ingest_order(repo, state, payload, &raw).await? {
+            Ingestion::Stored => Ok(true),
+            Ingestion::Replayed => Ok(false),
+            Ingestion::Conflict(conflicts) => Err(AppError::Conflict(order_uid, conflicts)),
+        };
+    };
+
+    // ключ занимается в одной транзакции с записью заказа: из одновременных запросов
+    // с одним ключом заказ записывает только первый
+    let content_hash = idempotency_module::content_hash(&payload);
+    let saved = match repo.insert_keyed(&payload, &raw, &key).await? {
+        Keyed::Inserted(seq) => {
+            tracing::info!(order_uid = %payload.order_uid, "order stored");
+            payload.seq = seq;
+            state.write(|cache| cache.add_order(payload));
+            state.record_ingestion();
+            return Ok(true);
         }
-        Err(err) => Err(err),
+        Keyed::KeyTaken(saved) => saved,
+        // заказ уже был сохранен: повтор без ключа или запрос с другим ключом
+        Keyed::Exists => match compare_with_stored(repo, state, &payload).await? {
+            Ingestion::Conflict(conflicts) => {
+                return Err(AppError::Conflict(payload.order_uid, conflicts))
+            }
+            _ => {
+                repo.save_key(&key, &payload.order_uid, &content_hash)
+                    .await?
+            }
+        },
+    };
+    // ключ уже использован: тот же заказ получает исходный ответ, другой — ошибку
+    if saved.order_uid != payload.order_uid || saved.content_hash != content_hash {
+        return Err(AppError::IdempotencyKeyReused(key));
     }
+    Ok(false)
+}
+
+// обработчик get запроса списка заказов с фильтрами, сортировкой и пагинацией
+async fn get_state(
+    state: Arc<OrderCache>,
+    query: Result<Query<query_module::OrdersQuery>, QueryRejection>,
+) -> Result<impl IntoResponse, AppError> {
+    let Query(query) = query?;
+    // Получаем снимок кэша, запись новых заказов его не блокирует
+    let snapshot = state.read();
+
+    // Возвращаем страницу заказов в виде JSON
+    let page = query_module::query_orders(&snapshot, &query).map_err(AppError::InvalidQuery)?;
+    Ok(Json(page))
 }
 
-// Функция для проверки полученных данных на то что они уже есть БД
-async fn check_order_exists(db_pool: &PgPool, uid: &String) -> Result<bool, Error> {
-    // Выполняем запрос для поиска записи в БД по значению "order_uid"
-    let result = sqlx::query!(
-        r#"
-        SELECT order_uid FROM orders WHERE order_uid = $1
-        "#,
-        uid
+// обработчик get запроса одного заказа по order_uid; с ?raw=true возвращается исходный документ
+async fn get_order(
+    state: Arc<OrderCache>,
+    Path(order_uid): Path<String>,
+    params: Result<Query<raw_module::OrderParams>, QueryRejection>,
+    repo: Repository,
+) -> Result<Response, AppError> {
+    logging_module::record_order_uid(&order_uid);
+    let Query(params) = params?;
+    if params.raw {
+        return raw_module::raw_order(&*repo, order_uid).await;
+    }
+    // ETag — версия заказа, ее передают в If-Match при изменении
+    let order = match state.read().get_order(&order_uid) {
+        Some(order) => order,
+        // заказа нет в кэше (например, он был вытеснен): ищем в БД и возвращаем в кэш
+        None => match repo.get(&order_uid).await? {
+            Some(order) => {
+                let order = Arc::new(order);
+                state.write(|cache| cache.add_order(order.clone()));
+                order
+            }
+            None => return Err(AppError::NotFound(order_uid)),
+        },
+    };
+    Ok((
+        [(header::ETAG, update_module::etag(order.version))],
+        Json(order),
     )
-    .fetch_optional(db_pool)
-    .await;
+        .into_response())
+}
 
-    match result {
-        Ok(Some(_)) => Ok(true), // запись найдена
-        Ok(None) => Ok(false),   // запись не найдена
-        Err(err) => Err(err),    // обработка ошибки
+// Результат приема заказа
+pub(crate) enum Ingestion {
+    Stored,                   // заказ записан
+    Replayed,                 // такой же заказ уже сохранен
+    Conflict(Vec<FieldDiff>), // с тем же order_uid сохранен другой заказ
+}
+
+// Общий путь приема заказа для HTTP и брокера сообщений: запись в БД вместе
+// с исходным документом и, после успешного коммита, добавление в кэш
+async fn ingest_order(
+    repo: &dyn OrderRepository,
+    state: &Arc<OrderCache>,
+    mut order: Order,
+    raw: &str,
+) -> Result<Ingestion, Error> {
+    let Some(seq) = repo.insert(&order, raw).await? else {
+        return compare_with_stored(repo, state, &order).await;
+    };
+    tracing::info!(order_uid = %order.order_uid, "order stored");
+    order.seq = seq;
+    state.write(|cache| cache.add_order(order));
+    state.record_ingestion();
+    Ok(Ingestion::Stored)
+}
+
+// Сравнение полученного заказа с уже сохраненным заказом с тем же order_uid
+pub(crate) async fn compare_with_stored(
+    repo: &dyn OrderRepository,
+    state: &Arc<OrderCache>,
+    order: &Order,
+) -> Result<Ingestion, Error> {
+    let content_hash = idempotency_module::content_hash(order);
+    let stored_hash = repo.content_hash(&order.order_uid).await?;
+    if stored_hash.as_deref() == Some(content_hash.as_str()) {
+        return Ok(Ingestion::Replayed);
     }
+
+    // хэш отличается или еще не вычислен: сравниваем поля с сохраненным заказом
+    let cached = state.read().get_order(&order.order_uid);
+    let stored = match cached {
+        Some(stored) => stored,
+        None => repo
+            .get(&order.order_uid)
+            .await?
+            .map(Arc::new)
+            .ok_or(Error::RowNotFound)?, // заказ удален между проверкой и чтением
+    };
+    let conflicts = idempotency_module::diff_orders(&stored, order);
+    if !conflicts.is_empty() {
+        return Ok(Ingestion::Conflict(conflicts));
+    }
+    if stored_hash.is_none() {
+        // заказ записан до появления хэшей, сохраняем хэш для следующих сравнений
+        repo.backfill_hash(&order.order_uid, &content_hash).await?;
+    }
+    Ok(Ingestion::Replayed)
 }
 
 // Тесты
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
-    use axum::http::StatusCode;
     use reqwest::Client;
-    use sqlx::PgPool;
+    use sqlx::postgres::PgConnectOptions;
+    use sqlx::{Connection, PgConnection, PgPool};
     use std::env;
     use std::fs;
-    use std::sync::{Arc, Mutex};
-    use tokio;
+    use std::str::FromStr;
+    use tokio::task::JoinHandle;
+
+    // Схема PostgreSQL, созданная для одного теста; удаляется вместе с данными при выходе
+    // из теста, в том числе при панике
+    pub(crate) struct TestSchema {
+        name: String,
+    }
 
-    // Функция инициализации БД
-    async fn setup_database() -> PgPool {
+    impl Drop for TestSchema {
+        // Тест выполняется в однопоточном runtime, поэтому схема удаляется в отдельном
+        // потоке со своим runtime. Подключения пула теста закрываются принудительно:
+        // незавершенная транзакция иначе удерживала бы блокировки таблиц схемы
+        fn drop(&mut self) {
+            let name = self.name.clone();
+            let dropped = std::thread::spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .unwrap();
+                runtime.block_on(async {
+                    let mut conn = PgConnection::connect(&database_url()).await?;
+                    sqlx::query(
+                        "SELECT pg_terminate_backend(pid) FROM pg_stat_activity \
+                         WHERE application_name = $1",
+                    )
+                    .bind(&name)
+                    .execute(&mut conn)
+                    .await?;
+                    sqlx::query(&format!("DROP SCHEMA IF EXISTS {} CASCADE", name))
+                        .execute(&mut conn)
+                        .await
+                })
+            })
+            .join();
+            if !matches!(dropped, Ok(Ok(_))) {
+                eprintln!("failed to drop test schema {}", self.name);
+            }
+        }
+    }
+
+    fn database_url() -> String {
         dotenv().ok();
-        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-        let pool = PgPool::connect(&database_url).await.unwrap();
-
-        // Очистка и повторное создание таблиц
-        let drop_item_table = r#"DROP TABLE IF EXISTS item CASCADE;"#;
-        let drop_orders_table = r#"DROP TABLE IF EXISTS orders CASCADE;"#;
-        let drop_payment_table = r#"DROP TABLE IF EXISTS payment CASCADE;"#;
-        let drop_delivery_table = r#"DROP TABLE IF EXISTS delivery CASCADE;"#;
-
-        sqlx::query(drop_item_table).execute(&pool).await.unwrap();
-        sqlx::query(drop_orders_table).execute(&pool).await.unwrap();
-        sqlx::query(drop_payment_table)
-            .execute(&pool)
-            .await
-            .unwrap();
-        sqlx::query(drop_delivery_table)
-            .execute(&pool)
+        env::var("DATABASE_URL").expect("DATABASE_URL must be set")
+    }
+
+    // Функция инициализации БД: каждый тест получает собственную пустую схему с примененными
+    // миграциями, поэтому тесты выполняются параллельно и не трогают таблицы в DATABASE_URL
+    pub(crate) async fn setup_database() -> (PgPool, TestSchema) {
+        let database_url = database_url();
+        let schema = TestSchema {
+            name: format!("test_{}", uuid::Uuid::new_v4().simple()),
+        };
+        let mut conn = PgConnection::connect(&database_url).await.unwrap();
+        sqlx::query(&format!("CREATE SCHEMA {}", schema.name))
+            .execute(&mut conn)
             .await
             .unwrap();
+        drop(conn);
 
-        // Создание таблиц
-        let tables = vec!["payment", "delivery", "orders", "item"];
-        for table in tables {
-            db_module::create_table(table, &pool).await.unwrap();
+        // все подключения пула работают только со своей схемой и помечены ее именем
+        let options = PgConnectOptions::from_str(&database_url)
+            .unwrap()
+            .application_name(&schema.name)
+            .options([("search_path", schema.name.as_str())]);
+        let pool = PgPool::connect_with(options).await.unwrap();
+        db_module::run_migrations(&pool).await.unwrap();
+
+        (pool, schema)
+    }
+
+    // Сервер с маршрутами app на свободном порту; возвращает адрес для запросов
+    pub(crate) fn serve(
+        app: Router,
+        shutdown: &Shutdown,
+    ) -> (String, JoinHandle<hyper::Result<()>>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service())
+                .with_graceful_shutdown({
+                    let shutdown = shutdown.clone();
+                    async move { shutdown.triggered().await }
+                }),
+        );
+        (base_url, server)
+    }
+
+    // Удаление всех таблиц вместе с историей миграций
+    async fn drop_schema(pool: &PgPool) {
+        for table in [
+            "item",
+            "orders",
+            "payment",
+            "delivery",
+            "idempotency_keys",
+            "_sqlx_migrations",
+        ] {
+            sqlx::query(&format!("DROP TABLE IF EXISTS {} CASCADE;", table))
+                .execute(pool)
+                .await
+                .unwrap();
         }
+    }
 
-        pool
+    async fn table_exists(pool: &PgPool, table: &str) -> bool {
+        let exists: (bool,) = sqlx::query_as(
+            r#"
+            SELECT EXISTS (
+                SELECT 1
+                FROM information_schema.tables
+                WHERE table_schema = current_schema()
+                AND table_name = $1
+            );
+            "#,
+        )
+        .bind(table)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        exists.0
     }
 
     async fn load_json_from_file(file_path: &str) -> serde_json::Value {
@@ -276,10 +692,13 @@ mod tests {
     }
 
     // Функция генерирующая post запрос с указанным json
-    async fn perform_test_order_request(order_data: &serde_json::Value) -> StatusCode {
+    async fn perform_test_order_request(
+        base_url: &str,
+        order_data: &serde_json::Value,
+    ) -> StatusCode {
         let client = Client::new();
         let response = client
-            .post("http://127.0.0.1:8081/order")
+            .post(format!("{}/order", base_url))
             .json(order_data)
             .send()
             .await
@@ -290,35 +709,15 @@ mod tests {
 
     #[tokio::test]
     async fn test_order_creation() {
-        let pool = setup_database().await;
-
-        // Запускаем сервер в фоновом режиме
-        let app_state = Arc::new(Mutex::new(AppState::new()));
-        {
-            let mut state = app_state.lock().unwrap();
-            state.load_orders(&pool).await.unwrap();
-        }
-
-        let app = Router::new()
-            .route(
-                "/order",
-                post({
-                    let pool = pool.clone();
-                    let app_state = app_state.clone();
-                    move |input: Json<db_module::Order>| state_handler(app_state, input, pool)
-                }),
-            )
-            .route(
-                "/orders",
-                get({
-                    let app_state = app_state.clone();
-                    move || get_state(app_state)
-                }),
-            );
-
-        let addr = SocketAddr::from(([127, 0, 0, 1], 8081));
+        let (pool, _schema) = setup_database().await;
+        let repo: Repository = Arc::new(PgRepository::new(pool));
 
-        tokio::spawn(axum::Server::bind(&addr).serve(app.into_make_service()));
+        // Запускаем сервер в фоновом режиме на свободном порту
+        let mut state = AppState::new();
+        state.load_orders(&*repo).await.unwrap();
+        let app_state = Arc::new(OrderCache::new(state));
+        let shutdown = Shutdown::new();
+        let (base_url, server) = serve(app(app_state, repo, shutdown.clone()), &shutdown);
 
         // Различные валидные данные
         let json_data_1 = load_json_from_file("models/model1.json").await;
@@ -333,16 +732,16 @@ mod tests {
         let json_data_incorrect_3 = load_json_from_file("models/model1.json").await; // данные которые уже есть в БД
 
         // Выполняем post запросы
-        let status_1 = perform_test_order_request(&json_data_1).await;
-        let status_2 = perform_test_order_request(&json_data_2).await;
-        let status_3 = perform_test_order_request(&json_data_3).await;
-        let status_4 = perform_test_order_request(&json_data_4).await;
-        let status_5 = perform_test_order_request(&json_data_5).await;
-        let status_6 = perform_test_order_request(&json_data_6).await;
+        let status_1 = perform_test_order_request(&base_url, &json_data_1).await;
+        let status_2 = perform_test_order_request(&base_url, &json_data_2).await;
+        let status_3 = perform_test_order_request(&base_url, &json_data_3).await;
+        let status_4 = perform_test_order_request(&base_url, &json_data_4).await;
+        let status_5 = perform_test_order_request(&base_url, &json_data_5).await;
+        let status_6 = perform_test_order_request(&base_url, &json_data_6).await;
 
-        let status_7 = perform_test_order_request(&json_data_incorrect_2).await;
-        let status_8 = perform_test_order_request(&json_data_incorrect_1).await;
-        let status_9 = perform_test_order_request(&json_data_incorrect_3).await;
+        let status_7 = perform_test_order_request(&base_url, &json_data_incorrect_2).await;
+        let status_8 = perform_test_order_request(&base_url, &json_data_incorrect_1).await;
+        let status_9 = perform_test_order_request(&base_url, &json_data_incorrect_3).await;
 
         assert_eq!(status_1, StatusCode::OK);
         assert_eq!(status_2, StatusCode::OK);
@@ -353,27 +752,538 @@ mod tests {
 
         assert_eq!(status_7, StatusCode::UNPROCESSABLE_ENTITY);
         assert_eq!(status_8, StatusCode::UNPROCESSABLE_ENTITY);
-        assert_eq!(status_9, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(status_9, StatusCode::OK); // повтор того же заказа получает исходный ответ
+
+        // Повтор помечается заголовком Idempotent-Replayed
+        let replay_response = Client::new()
+            .post(format!("{}/order", base_url))
+            .json(&json_data_1)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(replay_response.status(), StatusCode::OK);
+        assert_eq!(replay_response.headers()["idempotent-replayed"], "true");
+        assert_eq!(replay_response.text().await.unwrap(), "Order received\n");
+
+        // Ошибки отдаются в формате application/problem+json с машиночитаемым кодом;
+        // другой заказ с тем же order_uid отклоняется со списком различающихся полей
+        let mut json_data_changed = json_data_1.clone();
+        json_data_changed["delivery"]["name"] = "Other Name".into();
+        let duplicate_response = Client::new()
+            .post(format!("{}/order", base_url))
+            .json(&json_data_changed)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(duplicate_response.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            duplicate_response.headers()["content-type"],
+            "application/problem+json"
+        );
+        let duplicate_json: serde_json::Value = duplicate_response.json().await.unwrap();
+        assert_eq!(duplicate_json["code"], "order_exists");
+        assert_eq!(duplicate_json["status"], 409);
+        assert_eq!(duplicate_json["order_uid"], "b563feb7b2b84b6test");
+        assert_eq!(
+            duplicate_json["conflicts"],
+            serde_json::json!([{
+                "field": "delivery.name",
+                "stored": json_data_1["delivery"]["name"],
+                "received": "Other Name",
+            }])
+        );
+
+        // Синтаксически некорректный json
+        let malformed_response = Client::new()
+            .post(format!("{}/order", base_url))
+            .header("content-type", "application/json")
+            .body("{\"order_uid\": ")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(malformed_response.status(), StatusCode::BAD_REQUEST);
+        let malformed_json: serde_json::Value = malformed_response.json().await.unwrap();
+        assert_eq!(malformed_json["code"], "malformed_body");
+
+        let incomplete_response = Client::new()
+            .post(format!("{}/order", base_url))
+            .json(&json_data_incorrect_2)
+            .send()
+            .await
+            .unwrap();
+        let incomplete_json: serde_json::Value = incomplete_response.json().await.unwrap();
+        assert_eq!(incomplete_json["code"], "invalid_order");
+
+        // Заказ, нарушающий бизнес-правила, отклоняется со списком нарушений
+        let mut json_data_invalid = json_data_1.clone();
+        json_data_invalid["order_uid"] = "invalid_order_uid".into();
+        json_data_invalid["payment"]["amount"] = 1.into();
+        json_data_invalid["payment"]["currency"] = "XYZ".into();
+        json_data_invalid["items"][0]["size"] = "x".repeat(51).into();
+        let invalid_response = Client::new()
+            .post(format!("{}/order", base_url))
+            .json(&json_data_invalid)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(invalid_response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let invalid_json: serde_json::Value = invalid_response.json().await.unwrap();
+        assert_eq!(invalid_json["code"], "validation_failed");
+        let errors = invalid_json["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0]["field"], "payment.amount");
+        assert_eq!(errors[0]["rule"], "amount_matches_total");
+        assert_eq!(errors[1]["field"], "payment.currency");
+        assert_eq!(errors[2]["field"], "items[0].size");
+        assert_eq!(errors[2]["rule"], "max_length");
 
         // Проверка получения всех заказов
         let client = Client::new();
         let orders_response = client
-            .get("http://127.0.0.1:8081/orders")
+            .get(format!("{}/orders", base_url))
             .send()
             .await
             .unwrap();
 
         assert_eq!(orders_response.status(), StatusCode::OK);
-        let orders_json: serde_json::Value = orders_response.json().await.unwrap();
+        let page_json: serde_json::Value = orders_response.json().await.unwrap();
+        assert_eq!(page_json["total"], 6);
+        assert!(page_json["next_cursor"].is_null());
+        let orders_json = &page_json["orders"];
         assert!(orders_json.is_array()); // проверяем, что ответ - массив
-        assert!(orders_json.as_array().unwrap().len() > 0); // проверяем, что есть хотя бы один заказ
-
-        // Проверка соответствия отправленных и полученных данных
-        assert_eq!(json_data_1, orders_json[0]);
-        assert_eq!(json_data_2, orders_json[1]);
-        assert_eq!(json_data_3, orders_json[2]);
-        assert_eq!(json_data_4, orders_json[3]);
-        assert_eq!(json_data_5, orders_json[4]);
-        assert_eq!(json_data_6, orders_json[5]);
+        assert!(!orders_json.as_array().unwrap().is_empty()); // проверяем, что есть хотя бы один заказ
+
+        // Проверка соответствия отправленных и полученных данных; заказы сравниваются
+        // по order_uid, без расчета на порядок в ответе
+        let received: std::collections::HashMap<&str, &serde_json::Value> = orders_json
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|order| (order["order_uid"].as_str().unwrap(), order))
+            .collect();
+        for sent in [
+            &json_data_1,
+            &json_data_2,
+            &json_data_3,
+            &json_data_4,
+            &json_data_5,
+            &json_data_6,
+        ] {
+            assert_eq!(
+                Some(&sent),
+                received.get(sent["order_uid"].as_str().unwrap())
+            );
+        }
+
+        // Проверка фильтрации и пагинации
+        let filtered_response = client
+            .get(format!(
+                "{}/orders?track_number=WBILMTESTTRACK&sort=order_uid&limit=1",
+                base_url
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(filtered_response.status(), StatusCode::OK);
+        let filtered_json: serde_json::Value = filtered_response.json().await.unwrap();
+        assert_eq!(filtered_json["total"], 2);
+        assert_eq!(filtered_json["orders"][0], json_data_6);
+        let next_response = client
+            .get(format!(
+                "{}/orders?track_number=WBILMTESTTRACK&sort=order_uid&limit=1&cursor={}",
+                base_url,
+                filtered_json["next_cursor"].as_str().unwrap()
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(next_response.status(), StatusCode::OK);
+        let next_json: serde_json::Value = next_response.json().await.unwrap();
+        assert_eq!(next_json["orders"][0], json_data_1);
+        assert!(next_json["next_cursor"].is_null());
+
+        let invalid_response = client
+            .get(format!("{}/orders?sort=unknown", base_url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(invalid_response.status(), StatusCode::BAD_REQUEST);
+        let invalid_query_json: serde_json::Value = invalid_response.json().await.unwrap();
+        assert_eq!(invalid_query_json["code"], "invalid_query");
+
+        // Проверка получения заказа по order_uid
+        let order_response = client
+            .get(format!("{}/order/b563feb7b2b84b6test2", base_url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(order_response.status(), StatusCode::OK);
+        let order_json: serde_json::Value = order_response.json().await.unwrap();
+        assert_eq!(json_data_3, order_json);
+
+        // Запрос несуществующего заказа
+        let missing_response = client
+            .get(format!("{}/order/missing_uid", base_url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(missing_response.status(), StatusCode::NOT_FOUND);
+        let missing_json: serde_json::Value = missing_response.json().await.unwrap();
+        assert_eq!(missing_json["order_uid"], "missing_uid");
+        assert_eq!(missing_json["code"], "order_not_found");
+
+        // Запрос с Idempotency-Key: повтор с тем же ключом получает исходный ответ,
+        // другой заказ с тем же ключом отклоняется
+        let mut json_data_keyed = json_data_1.clone();
+        json_data_keyed["order_uid"] = "keyed_order_uid".into();
+        let post_with_key = |body: serde_json::Value| {
+            Client::new()
+                .post(format!("{}/order", base_url))
+                .header("idempotency-key", "retry-key-1")
+                .json(&body)
+                .send()
+        };
+        let first = post_with_key(json_data_keyed.clone()).await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        assert!(first.headers().get("idempotent-replayed").is_none());
+        let retry = post_with_key(json_data_keyed.clone()).await.unwrap();
+        assert_eq!(retry.status(), StatusCode::OK);
+        assert_eq!(retry.headers()["idempotent-replayed"], "true");
+        let reused = post_with_key(json_data_2.clone()).await.unwrap();
+        assert_eq!(reused.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let reused_json: serde_json::Value = reused.json().await.unwrap();
+        assert_eq!(reused_json["code"], "idempotency_key_reused");
+        let oversized_key = Client::new()
+            .post(format!("{}/order", base_url))
+            .header("idempotency-key", "k".repeat(256))
+            .json(&json_data_keyed)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(oversized_key.status(), StatusCode::BAD_REQUEST);
+        let oversized_json: serde_json::Value = oversized_key.json().await.unwrap();
+        assert_eq!(oversized_json["code"], "invalid_idempotency_key");
+
+        // После сигнала остановки сервер закрывает соединения и завершается
+        shutdown.trigger();
+        tokio::time::timeout(std::time::Duration::from_secs(5), server)
+            .await
+            .expect("server did not stop")
+            .unwrap()
+            .unwrap();
+        let summary = shutdown
+            .drain(tokio::time::Instant::now() + std::time::Duration::from_secs(1))
+            .await;
+        assert_eq!(summary.abandoned, 0);
+    }
+
+    #[tokio::test]
+    async fn test_insert_order_rollback() {
+        let (pool, _schema) = setup_database().await;
+        let repo = PgRepository::new(pool.clone());
+
+        let json_data = load_json_from_file("models/model_extended.json").await;
+        let mut order: Order = serde_json::from_value(json_data).unwrap();
+        order.order_uid = "rollback_test_uid".to_string();
+        order.delivery.name = "Rollback Testov".to_string();
+        order.payment.transaction = "rollback_test_transaction".to_string();
+        // Второй товар не помещается в колонку size VARCHAR(50),
+        // поэтому вставка падает уже после записи delivery, payment и orders
+        order.items[1].size = "x".repeat(51);
+
+        let raw = serde_json::to_string(&order).unwrap();
+        assert!(repo.insert(&order, &raw).await.is_err());
+
+        // Проверяем, что транзакция откатилась целиком
+        let orders: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM orders WHERE order_uid = $1")
+            .bind(&order.order_uid)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let items: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM item WHERE order_uid = $1")
+            .bind(&order.order_uid)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let deliveries: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM delivery WHERE name = $1")
+            .bind(&order.delivery.name)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let payments: (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM payment WHERE transaction = $1")
+                .bind(&order.payment.transaction)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+
+        assert_eq!(orders.0, 0);
+        assert_eq!(items.0, 0);
+        assert_eq!(deliveries.0, 0);
+        assert_eq!(payments.0, 0);
+
+        // После отката тот же заказ с корректными данными успешно записывается
+        order.items[1].size = "0".to_string();
+        let raw = serde_json::to_string(&order).unwrap();
+        assert!(repo.insert(&order, &raw).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_insert() {
+        let (pool, _schema) = setup_database().await;
+        let repo = Arc::new(PgRepository::new(pool.clone()));
+        let raw = fs::read_to_string("models/model1.json").unwrap();
+        let order: Order = serde_json::from_str(&raw).unwrap();
+
+        // Параллельная транзакция записывает строку orders с тем же order_uid
+        // и фиксируется, когда вставка уже прошла проверку существования
+        let mut other = pool.begin().await.unwrap();
+        sqlx::query(
+            "INSERT INTO orders (order_uid, track_number, entry, locale, internal_signature, \
+             customer_id, delivery_service, shardkey, sm_id, date_created, oof_shard) \
+             VALUES ($1, '', '', '', '', '', '', '', 0, '', '')",
+        )
+        .bind(&order.order_uid)
+        .execute(&mut *other)
+        .await
+        .unwrap();
+        let insert = tokio::spawn({
+            let (repo, order, raw) = (repo.clone(), order.clone(), raw.clone());
+            async move { repo.insert(&order, &raw).await }
+        });
+        // ждем, пока вставка заблокируется на незафиксированной строке
+        loop {
+            let waiting: (i64,) = sqlx::query_as(
+                "SELECT COUNT(*) FROM pg_stat_activity \
+                 WHERE application_name = current_setting('application_name') \
+                 AND wait_event_type = 'Lock'",
+            )
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+            if waiting.0 > 0 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        other.commit().await.unwrap();
+
+        // проигравшая вставка сообщает, что заказ уже есть, и не оставляет delivery и payment
+        assert_eq!(insert.await.unwrap().unwrap(), None);
+        for table in ["delivery", "payment", "item"] {
+            let count: (i64,) = sqlx::query_as(&format!("SELECT COUNT(*) FROM {}", table))
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            assert_eq!(count.0, 0, "{}", table);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_migrations_up_and_down() {
+        let (pool, _schema) = setup_database().await;
+        let tables = ["payment", "delivery", "orders", "item"];
+        let head: Vec<i64> = db_module::MIGRATOR
+            .iter()
+            .filter(|migration| !migration.migration_type.is_down_migration())
+            .map(|migration| migration.version)
+            .collect();
+
+        // Пустая база мигрируется до последней версии
+        drop_schema(&pool).await;
+        for table in tables {
+            assert!(!table_exists(&pool, table).await);
+        }
+        db_module::run_migrations(&pool).await.unwrap();
+        for table in tables {
+            assert!(table_exists(&pool, table).await);
+        }
+        assert_eq!(db_module::applied_migrations(&pool).await.unwrap(), head);
+
+        // Повторный запуск ничего не меняет
+        db_module::run_migrations(&pool).await.unwrap();
+        assert_eq!(db_module::applied_migrations(&pool).await.unwrap(), head);
+
+        // Откат последней миграции, затем всех оставшихся
+        let previous = head[head.len() - 2];
+        db_module::revert_migrations(&pool, previous).await.unwrap();
+        assert_eq!(
+            db_module::applied_migrations(&pool).await.unwrap(),
+            head[..head.len() - 1]
+        );
+        db_module::revert_migrations(&pool, 0).await.unwrap();
+        for table in tables {
+            assert!(!table_exists(&pool, table).await);
+        }
+        assert!(db_module::applied_migrations(&pool)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Возвращаем схему в актуальное состояние
+        db_module::run_migrations(&pool).await.unwrap();
+        assert!(table_exists(&pool, "orders").await);
+    }
+
+    #[tokio::test]
+    async fn test_load_orders() {
+        let (pool, _schema) = setup_database().await;
+        let repo = PgRepository::new(pool);
+
+        // Записываем заказы и загружаем их в новый кэш
+        let mut expected = Vec::new();
+        for file_path in [
+            "models/model3.json",
+            "models/model1.json",
+            "models/model_extended.json",
+            "models/model2.json",
+        ] {
+            let json_data = load_json_from_file(file_path).await;
+            let order: Order = serde_json::from_value(json_data.clone()).unwrap();
+            assert!(repo
+                .insert(&order, &json_data.to_string())
+                .await
+                .unwrap()
+                .is_some());
+            expected.push(json_data);
+        }
+
+        let mut state = AppState::new();
+        state.load_orders(&repo).await.unwrap();
+
+        // Заказы загружены полностью и в порядке поступления
+        let loaded: Vec<serde_json::Value> = state
+            .orders()
+            .map(|order| serde_json::to_value(order).unwrap())
+            .collect();
+        assert_eq!(loaded, expected);
+    }
+
+    // Вытесненный из кэша заказ загружается из БД при запросе
+    #[tokio::test]
+    async fn test_get_order_falls_back_to_database() {
+        let (pool, _schema) = setup_database().await;
+        let repo: Repository = Arc::new(PgRepository::new(pool.clone()));
+
+        let cache = Arc::new(OrderCache::new(AppState::with_limits(
+            db_module::CacheLimits {
+                max_entries: Some(2),
+                max_bytes: None,
+            },
+        )));
+        for file_path in [
+            "models/model1.json",
+            "models/model2.json",
+            "models/model3.json",
+        ] {
+            let json_data = load_json_from_file(file_path).await;
+            let order: Order = serde_json::from_value(json_data.clone()).unwrap();
+            assert!(matches!(
+                ingest_order(&*repo, &cache, order, &json_data.to_string())
+                    .await
+                    .unwrap(),
+                Ingestion::Stored
+            ));
+        }
+        assert!(cache.read().get_order("b563feb7b2b84b6test").is_none());
+
+        // повтор вытесненного заказа, записанного без хэша, сравнивается с заказом из БД
+        sqlx::query("UPDATE orders SET content_hash = NULL")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let json_data = load_json_from_file("models/model1.json").await;
+        let order: Order = serde_json::from_value(json_data.clone()).unwrap();
+        let content_hash = idempotency_module::content_hash(&order);
+        assert!(matches!(
+            ingest_order(&*repo, &cache, order, &json_data.to_string())
+                .await
+                .unwrap(),
+            Ingestion::Replayed
+        ));
+        let stored: (Option<String>,) =
+            sqlx::query_as("SELECT content_hash FROM orders WHERE order_uid = $1")
+                .bind("b563feb7b2b84b6test")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(stored.0, Some(content_hash));
+        assert!(cache.read().get_order("b563feb7b2b84b6test").is_none());
+
+        let response = get_order(
+            cache.clone(),
+            Path("b563feb7b2b84b6test".to_string()),
+            Ok(Query(Default::default())),
+            repo.clone(),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        // заказ возвращен в кэш на прежнюю позицию, вместо него вытеснен другой
+        let snapshot = cache.read();
+        assert!(snapshot.get_order("b563feb7b2b84b6test").is_some());
+        let uids: Vec<&str> = snapshot
+            .orders()
+            .map(|order| order.order_uid.as_str())
+            .collect();
+        assert_eq!(uids, ["b563feb7b2b84b6test", "b563feb7b2b84b6test2"]);
+
+        let response = get_order(
+            cache.clone(),
+            Path("missing".to_string()),
+            Ok(Query(Default::default())),
+            repo,
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    // Замер времени прогрева кэша на 100 тысячах заказов:
+    // cargo test --release -- --ignored bench_load_orders --nocapture
+    #[tokio::test]
+    #[ignore]
+    async fn bench_load_orders() {
+        const ORDERS: i32 = 100_000;
+        let (pool, _schema) = setup_database().await;
+
+        // Заполнение БД одним запросом на таблицу, у каждого заказа по два товара
+        let seed = [
+            r#"INSERT INTO delivery (name, phone, zip, city, address, region, email)
+               SELECT 'Test Testov', '+9720000000', '2639809', 'Kiryat Mozkin',
+                      'Ploshad Mira 15', 'Kraiot', 'test@gmail.com'
+               FROM generate_series(1, $1)"#,
+            r#"INSERT INTO payment (transaction, request_id, currency, provider, amount,
+                                    payment_dt, bank, delivery_cost, goods_total, custom_fee)
+               SELECT 'bench' || g, '', 'USD', 'wbpay', 2134, 1637907727, 'alpha', 1500, 634, 0
+               FROM generate_series(1, $1) g"#,
+            r#"INSERT INTO orders (order_uid, track_number, entry, delivery_id, payment_id,
+                                   locale, internal_signature, customer_id, delivery_service,
+                                   shardkey, sm_id, date_created, oof_shard)
+               SELECT 'bench' || g, 'WBILMTESTTRACK', 'WBIL', g, g, 'en', '', 'test', 'meest',
+                      '9', 99, '2021-11-26T06:22:19Z', '1'
+               FROM generate_series(1, $1) g"#,
+            r#"INSERT INTO item (chrt_id, track_number, price, rid, name, sale, size,
+                                 total_price, nm_id, brand, status, order_uid)
+               SELECT 9934930, 'WBILMTESTTRACK', 453, 'rid' || g || '_' || i, 'Mascaras', 30,
+                      '0', 317, 2389212, 'Vivienne Sabo', 202, 'bench' || g
+               FROM generate_series(1, $1) g, generate_series(1, 2) i"#,
+        ];
+        for query in seed {
+            sqlx::query(query)
+                .bind(ORDERS)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let started = std::time::Instant::now();
+        let mut state = AppState::new();
+        state.load_orders(&PgRepository::new(pool)).await.unwrap();
+        let elapsed = started.elapsed();
+
+        assert_eq!(state.orders().count(), ORDERS as usize);
+        assert!(state.orders().all(|order| order.items.len() == 2));
+        println!("load_orders: {} orders in {:?}", ORDERS, elapsed);
     }
 }
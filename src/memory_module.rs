@@ -0,0 +1,265 @@
+use crate::db_module::{self, Order};
+use crate::erasure_module::anonymize;
+use crate::idempotency_module::{content_hash, SavedKey};
+use crate::repository_module::{Keyed, OrderRepository, PoolStatus, Removal, Saved};
+use crate::update_module::IfMatch;
+use axum::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
+use sqlx::migrate::MigrateError;
+use sqlx::Error;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+// Хранилище заказов в памяти процесса, без БД: для локального запуска и тестов HTTP слоя.
+// Данные теряются при остановке
+#[derive(Default)]
+pub struct MemoryRepository {
+    store: Mutex<Store>,
+}
+
+#[derive(Default)]
+struct Store {
+    orders: HashMap<String, Stored>,
+    sequence: BTreeMap<i64, String>, // порядок поступления
+    last_seq: i64,
+    keys: HashMap<String, SavedKey>,
+}
+
+struct Stored {
+    order: Order,
+    raw: Option<String>,
+    content_hash: Option<String>,
+}
+
+impl Store {
+    // Запись нового заказа; номера выдаются с 1, как в последовательности orders.seq
+    fn insert(&mut self, order: &Order, raw: &str) -> Option<i64> {
+        if self.orders.contains_key(&order.order_uid) {
+            return None;
+        }
+        let mut order = order.clone();
+        order.version = db_module::initial_version();
+        self.last_seq += 1;
+        order.seq = self.last_seq;
+        self.sequence.insert(order.seq, order.order_uid.clone());
+        self.orders.insert(
+            order.order_uid.clone(),
+            Stored {
+                content_hash: Some(content_hash(&order)),
+                raw: Some(raw.to_string()),
+                order,
+            },
+        );
+        Some(self.last_seq)
+    }
+
+    // Запись ключа, если он свободен или его заказ удален; возвращает запись под ключом
+    fn reserve_key(&mut self, key: &str, order_uid: &str, content_hash: &str) -> SavedKey {
+        match self.keys.get(key) {
+            Some(saved) if self.orders.contains_key(&saved.order_uid) => saved.clone(),
+            _ => {
+                let saved = SavedKey {
+                    order_uid: order_uid.to_string(),
+                    content_hash: content_hash.to_string(),
+                };
+                self.keys.insert(key.to_string(), saved.clone());
+                saved
+            }
+        }
+    }
+}
+
+impl MemoryRepository {
+    pub fn new() -> Self {
+        MemoryRepository::default()
+    }
+
+    fn store(&self) -> std::sync::MutexGuard<'_, Store> {
+        self.store.lock().unwrap()
+    }
+}
+
+#[async_trait]
+impl OrderRepository for MemoryRepository {
+    async fn insert(&self, order: &Order, raw: &str) -> Result<Option<i64>, Error> {
+        Ok(self.store().insert(order, raw))
+    }
+
+    async fn insert_keyed(&self, order: &Order, raw: &str, key: &str) -> Result<Keyed, Error> {
+        let mut store = self.store();
+        if store.orders.contains_key(&order.order_uid) {
+            return Ok(Keyed::Exists);
+        }
+        // заказа еще нет, поэтому ключ занят им самим, только если был свободен
+        let saved = store.reserve_key(key, &order.order_uid, &content_hash(order));
+        if saved.order_uid != order.order_uid {
+            return Ok(Keyed::KeyTaken(saved));
+        }
+        let seq = store
+            .insert(order, raw)
+            .expect("order is checked to be new");
+        Ok(Keyed::Inserted(seq))
+    }
+
+    async fn insert_batch(&self, orders: &[(&Order, &str)]) -> Result<HashMap<String, i64>, Error> {
+        let mut store = self.store();
+        Ok(orders
+            .iter()
+            .filter_map(|(order, raw)| Some((order.order_uid.clone(), store.insert(order, raw)?)))
+            .collect())
+    }
+
+    async fn get(&self, order_uid: &str) -> Result<Option<Order>, Error> {
+        Ok(self
+            .store()
+            .orders
+            .get(order_uid)
+            .map(|stored| stored.order.clone()))
+    }
+
+    async fn list(&self, offset: usize, limit: usize) -> Result<Vec<Order>, Error> {
+        let store = self.store();
+        Ok(store
+            .sequence
+            .values()
+            .skip(offset)
+            .take(limit)
+            .map(|uid| store.orders[uid].order.clone())
+            .collect())
+    }
+
+    // Все заказы выбираются одним проходом по sequence, без постраничного чтения через list
+    fn stream_all(&self) -> BoxStream<'_, Result<Order, Error>> {
+        let store = self.store();
+        let orders: Vec<Order> = store
+            .sequence
+            .values()
+            .map(|uid| store.orders[uid].order.clone())
+            .collect();
+        stream::iter(orders.into_iter().map(Ok)).boxed()
+    }
+
+    async fn update(
+        &self,
+        order: &Order,
+        raw: &str,
+        condition: Option<&IfMatch>,
+    ) -> Result<Saved, Error> {
+        let mut store = self.store();
+        let Some(stored) = store.orders.get_mut(&order.order_uid) else {
+            if condition.is_some() {
+                return Ok(Saved::Mismatch(None));
+            }
+            let seq = store
+                .insert(order, raw)
+                .expect("order is checked to be new");
+            return Ok(Saved::Created(seq));
+        };
+        let (version, seq) = (stored.order.version, stored.order.seq);
+        if condition.is_some_and(|condition| !condition.matches(version)) {
+            return Ok(Saved::Mismatch(Some(version)));
+        }
+        stored.order = order.clone();
+        stored.order.version = version + 1;
+        stored.order.seq = seq;
+        stored.raw = Some(raw.to_string());
+        stored.content_hash = Some(content_hash(order));
+        Ok(Saved::Updated(version + 1, seq))
+    }
+
+    async fn delete(&self, order_uid: &str, condition: Option<&IfMatch>) -> Result<Removal, Error> {
+        let mut store = self.store();
+        let Some(stored) = store.orders.get(order_uid) else {
+            return Ok(Removal::Missing);
+        };
+        let version = stored.order.version;
+        if condition.is_some_and(|condition| !condition.matches(version)) {
+            return Ok(Removal::Mismatch(version));
+        }
+        let seq = stored.order.seq;
+        store.orders.remove(order_uid);
+        store.sequence.remove(&seq);
+        store.keys.retain(|_, saved| saved.order_uid != order_uid);
+        Ok(Removal::Removed(seq))
+    }
+
+    async fn raw_document(&self, order_uid: &str) -> Result<Option<(Option<String>, i64)>, Error> {
+        Ok(self
+            .store()
+            .orders
+            .get(order_uid)
+            .map(|stored| (stored.raw.clone(), stored.order.version)))
+    }
+
+    async fn content_hash(&self, order_uid: &str) -> Result<Option<String>, Error> {
+        Ok(self
+            .store()
+            .orders
+            .get(order_uid)
+            .and_then(|stored| stored.content_hash.clone()))
+    }
+
+    async fn backfill_hash(&self, order_uid: &str, content_hash: &str) -> Result<(), Error> {
+        if let Some(stored) = self.store().orders.get_mut(order_uid) {
+            stored
+                .content_hash
+                .get_or_insert_with(|| content_hash.to_string());
+        }
+        Ok(())
+    }
+
+    async fn erase_customer(
+        &self,
+        customer_id: &str,
+        erased: &str,
+    ) -> Result<Vec<(String, i64)>, Error> {
+        let mut store = self.store();
+        let mut changed = Vec::new();
+        for stored in store.orders.values_mut() {
+            let delivery = &stored.order.delivery;
+            if stored.order.customer_id != customer_id
+                || [
+                    &delivery.name,
+                    &delivery.phone,
+                    &delivery.address,
+                    &delivery.email,
+                ]
+                .iter()
+                .all(|field| *field == erased)
+            {
+                continue;
+            }
+            anonymize(&mut stored.order, erased);
+            stored.raw = Some(serde_json::to_string(&stored.order).expect("order is serializable"));
+            stored.order.version += 1;
+            stored.content_hash = None;
+            changed.push((stored.order.order_uid.clone(), stored.order.version));
+        }
+        changed.sort();
+        Ok(changed)
+    }
+
+    async fn save_key(
+        &self,
+        key: &str,
+        order_uid: &str,
+        content_hash: &str,
+    ) -> Result<SavedKey, Error> {
+        Ok(self.store().reserve_key(key, order_uid, content_hash))
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    // схема в памяти не меняется, миграций нет
+    async fn pending_migrations(&self) -> Result<Vec<i64>, MigrateError> {
+        Ok(Vec::new())
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        None
+    }
+
+    async fn close(&self) {}
+}
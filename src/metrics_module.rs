@@ -0,0 +1,266 @@
+use crate::db_module::OrderCache;
+use crate::error_module::AppError;
+use crate::repository_module::{OrderRepository, Repository};
+use axum::extract::MatchedPath;
+use axum::http::{header, Request};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use std::sync::{Arc, LazyLock};
+use std::time::Instant;
+
+// Метрики сервиса; регистрируются один раз при первом обращении
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    orders_accepted: IntCounterVec, // source
+    orders_rejected: IntCounterVec, // source, reason
+    pub insert_order_duration: Histogram,
+    pub load_orders_duration: Histogram,
+    cache_entries: IntGauge,
+    cache_bytes: IntGauge,
+    pool_connections: IntGaugeVec,       // state: idle | in_use
+    http_requests: IntCounterVec,        // method, route, status
+    http_request_duration: HistogramVec, // method, route
+}
+
+// Источник заказа
+pub const SOURCE_HTTP: &str = "http";
+pub const SOURCE_NATS: &str = "nats";
+pub const SOURCE_BULK: &str = "bulk"; // POST /orders/bulk
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let orders_accepted = IntCounterVec::new(
+            Opts::new("orders_accepted_total", "Orders stored in the database"),
+            &["source"],
+        )
+        .unwrap();
+        let orders_rejected = IntCounterVec::new(
+            Opts::new(
+                "orders_rejected_total",
+                "Orders that were not stored, by reason",
+            ),
+            &["source", "reason"],
+        )
+        .unwrap();
+        let insert_order_duration = Histogram::with_opts(HistogramOpts::new(
+            "insert_order_duration_seconds",
+            "Duration of the insert_order transaction",
+        ))
+        .unwrap();
+        // загрузка всего кэша занимает секунды, поэтому границы шире стандартных
+        let load_orders_duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "load_orders_duration_seconds",
+                "Duration of loading all orders from the database into the cache",
+            )
+            .buckets(prometheus::exponential_buckets(0.01, 4.0, 8).unwrap()),
+        )
+        .unwrap();
+        let cache_entries = IntGauge::new("order_cache_entries", "Orders in the cache").unwrap();
+        let cache_bytes = IntGauge::new(
+            "order_cache_bytes",
+            "Approximate memory used by orders in the cache",
+        )
+        .unwrap();
+        let pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Database pool connections by state"),
+            &["state"],
+        )
+        .unwrap();
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency"),
+            &["method", "route"],
+        )
+        .unwrap();
+
+        for collector in [
+            Box::new(orders_accepted.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(orders_rejected.clone()),
+            Box::new(insert_order_duration.clone()),
+            Box::new(load_orders_duration.clone()),
+            Box::new(cache_entries.clone()),
+            Box::new(cache_bytes.clone()),
+            Box::new(pool_connections.clone()),
+            Box::new(http_requests.clone()),
+            Box::new(http_request_duration.clone()),
+        ] {
+            registry.register(collector).unwrap();
+        }
+
+        Metrics {
+            registry,
+            orders_accepted,
+            orders_rejected,
+            insert_order_duration,
+            load_orders_duration,
+            cache_entries,
+            cache_bytes,
+            pool_connections,
+            http_requests,
+            http_request_duration,
+        }
+    }
+
+    pub fn order_accepted(&self, source: &str) {
+        self.orders_accepted.with_label_values(&[source]).inc();
+    }
+    // reason: duplicate, conflict, validation, malformed или db_error
+    pub fn order_rejected(&self, source: &str, reason: &str) {
+        self.orders_rejected
+            .with_label_values(&[source, reason])
+            .inc();
+    }
+    // Учет результата приема заказа: Ok(true) — заказ записан,
+    // Ok(false) — повтор уже сохраненного заказа
+    pub fn record_order(&self, source: &str, result: &Result<bool, AppError>) {
+        match result {
+            Ok(true) => self.order_accepted(source),
+            Ok(false) => self.order_rejected(source, "duplicate"),
+            Err(err) => self.order_rejected(source, rejection_reason(err)),
+        }
+    }
+
+    // Текстовое представление метрик; значения gauge снимаются в момент запроса
+    pub fn render(&self, state: &OrderCache, repo: &dyn OrderRepository) -> String {
+        let snapshot = state.read();
+        self.cache_entries.set(snapshot.len() as i64);
+        self.cache_bytes.set(snapshot.bytes() as i64);
+        if let Some(pool) = repo.pool_status() {
+            let idle = pool.idle as i64;
+            self.pool_connections.with_label_values(&["idle"]).set(idle);
+            self.pool_connections
+                .with_label_values(&["in_use"])
+                .set(pool.size as i64 - idle);
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+fn rejection_reason(err: &AppError) -> &'static str {
+    match err {
+        AppError::Conflict(..) | AppError::IdempotencyKeyReused(_) => "conflict",
+        AppError::Validation(_) => "validation",
+        AppError::InvalidOrder(_)
+        | AppError::MalformedBody(_)
+        | AppError::InvalidHeader(_)
+        | AppError::InvalidQuery(_) => "malformed",
+        AppError::VersionMismatch(..) => "conflict",
+        AppError::NotFound(_) | AppError::Unavailable(_) | AppError::Internal(_) => "db_error",
+    }
+}
+
+// GET /metrics — метрики в текстовом формате Prometheus
+pub async fn metrics(state: Arc<OrderCache>, repo: Repository) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        METRICS.render(&state, &*repo),
+    )
+}
+
+// Middleware учета HTTP запросов; метка route — шаблон маршрута, а не фактический путь,
+// чтобы order_uid не порождал новые временные ряды
+pub async fn track_http<B>(request: Request<B>, next: Next<B>) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().to_string();
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    METRICS
+        .http_request_duration
+        .with_label_values(&[&method, &route])
+        .observe(started.elapsed().as_secs_f64());
+    METRICS
+        .http_requests
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .inc();
+    response
+}
+
+// Тесты
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::{middleware, Router};
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_order_outcomes() {
+        let rejected = |reason: &str| {
+            METRICS
+                .orders_rejected
+                .with_label_values(&["test", reason])
+                .get()
+        };
+        METRICS.record_order("test", &Ok(true));
+        METRICS.record_order("test", &Ok(false));
+        METRICS.record_order(
+            "test",
+            &Err(AppError::Conflict("uid".to_string(), Vec::new())),
+        );
+        METRICS.record_order("test", &Err(AppError::Validation(Vec::new())));
+        METRICS.record_order("test", &Err(AppError::MalformedBody(String::new())));
+        METRICS.record_order("test", &Err(AppError::Unavailable(String::new())));
+        METRICS.order_rejected("test", "db_error");
+
+        assert_eq!(
+            METRICS.orders_accepted.with_label_values(&["test"]).get(),
+            1
+        );
+        assert_eq!(rejected("duplicate"), 1);
+        assert_eq!(rejected("conflict"), 1);
+        assert_eq!(rejected("validation"), 1);
+        assert_eq!(rejected("malformed"), 1);
+        assert_eq!(rejected("db_error"), 2);
+    }
+
+    #[tokio::test]
+    async fn test_http_metrics() {
+        let app = Router::new()
+            .route("/metrics-test/:id", get(|| async { "ok" }))
+            .route_layer(middleware::from_fn(track_http));
+
+        for id in ["a", "b"] {
+            let request = Request::get(format!("/metrics-test/{}", id))
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(request).await.unwrap();
+        }
+
+        let state = OrderCache::new(crate::db_module::AppState::new());
+        let pool = sqlx::PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+        let text = METRICS.render(&state, &crate::postgres_module::PgRepository::new(pool));
+        assert!(text.contains(
+            r#"http_requests_total{method="GET",route="/metrics-test/:id",status="200"} 2"#
+        ));
+        assert!(text.contains(
+            r#"http_request_duration_seconds_count{method="GET",route="/metrics-test/:id"} 2"#
+        ));
+        assert!(text.contains("order_cache_entries 0"));
+        assert!(text.contains(r#"db_pool_connections{state="in_use"} 0"#));
+        assert!(text.contains("# TYPE insert_order_duration_seconds histogram"));
+    }
+}
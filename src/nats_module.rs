@@ -0,0 +1,457 @@
+use crate::db_module::{Order, OrderCache};
+use crate::error_module::AppError;
+use crate::metrics_module::{METRICS, SOURCE_NATS};
+use crate::repository_module::{OrderRepository, Repository};
+use crate::shutdown_module::Shutdown;
+use crate::Ingestion;
+use futures::StreamExt;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::Instrument;
+
+// Пауза перед повторным подключением к NATS, если соединение не удалось установить
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+// Настройки подписчика NATS
+#[derive(Clone, Debug)]
+pub struct NatsConfig {
+    pub url: String,
+    pub subject: String,
+    pub queue_group: Option<String>,
+}
+
+// Ответ брокеру на полученное сообщение (протокол подтверждений JetStream)
+#[derive(Debug, PartialEq)]
+enum Reply {
+    Ack,  // заказ записан в БД (или такой же заказ уже был там)
+    Nak,  // БД недоступна, сообщение нужно доставить повторно
+    Term, // сообщение некорректно, отклонено БД или конфликтует с сохраненным заказом
+}
+
+impl Reply {
+    fn payload(&self) -> &'static str {
+        match self {
+            Reply::Ack => "+ACK",
+            Reply::Nak => "-NAK",
+            Reply::Term => "+TERM",
+        }
+    }
+}
+
+// Подписчик, принимающий заказы из NATS; работает до запроса остановки
+pub async fn run_subscriber(
+    config: NatsConfig,
+    repo: Repository,
+    state: Arc<OrderCache>,
+    shutdown: Shutdown,
+) {
+    while !shutdown.is_triggered() {
+        match consume(&config, &*repo, &state, &shutdown).await {
+            Ok(()) => tracing::info!(subject = %config.subject, "nats subscription closed"),
+            Err(err) => tracing::error!(error = %err, "nats subscriber error"),
+        }
+        // клиент сам восстанавливает оборванное соединение, сюда попадаем только
+        // если подключиться не удалось, подписка была закрыта или запрошена остановка
+        tokio::select! {
+            _ = tokio::time::sleep(RETRY_DELAY) => {}
+            _ = shutdown.triggered() => {}
+        }
+    }
+    tracing::info!("nats subscriber stopped");
+}
+
+async fn consume(
+    config: &NatsConfig,
+    repo: &dyn OrderRepository,
+    state: &Arc<OrderCache>,
+    shutdown: &Shutdown,
+) -> Result<(), async_nats::Error> {
+    let client = async_nats::connect(config.url.as_str()).await?;
+    let mut subscriber = match &config.queue_group {
+        Some(group) => {
+            client
+                .queue_subscribe(config.subject.clone(), group.clone())
+                .await?
+        }
+        None => client.subscribe(config.subject.clone()).await?,
+    };
+    tracing::info!(subject = %config.subject, url = %config.url, "nats subscriber listening");
+
+    loop {
+        // после запроса остановки новые сообщения не берутся в обработку,
+        // начатое сообщение обрабатывается и подтверждается до конца
+        let message = tokio::select! {
+            message = subscriber.next() => message,
+            _ = shutdown.triggered() => break,
+        };
+        let Some(message) = message else {
+            return Ok(());
+        };
+
+        let _write = shutdown.track();
+        // span сообщения, аналог span'а HTTP запроса; order_uid дописывается после разбора
+        let span = tracing::info_span!(
+            "nats_message",
+            subject = %message.subject,
+            order_uid = tracing::field::Empty,
+        );
+        let ack = process_message(&message.payload, repo, state)
+            .instrument(span.clone())
+            .await;
+        span.in_scope(|| tracing::debug!(reply = ack.payload(), "nats message processed"));
+        // подтверждение отправляется только после завершения записи в БД
+        if let Some(reply) = message.reply {
+            client.publish(reply, ack.payload().into()).await?;
+        }
+    }
+
+    // неполученные сообщения брокер доставит другому подписчику группы или после перезапуска
+    subscriber.unsubscribe().await?;
+    client.flush().await?;
+    Ok(())
+}
+
+// Обработка одного сообщения тем же путем, что и POST /order: разбор, проверка и запись
+async fn process_message(
+    payload: &[u8],
+    repo: &dyn OrderRepository,
+    state: &Arc<OrderCache>,
+) -> Reply {
+    let order: Order = match serde_json::from_slice(payload) {
+        Ok(order) => order,
+        Err(err) => {
+            tracing::warn!(error = %err, "nats: malformed order rejected");
+            METRICS.order_rejected(SOURCE_NATS, "malformed");
+            return Reply::Term;
+        }
+    };
+    crate::logging_module::record_order_uid(&order.order_uid);
+    if let Err(violations) = crate::validation_module::validate_order(&order) {
+        tracing::warn!(?violations, "nats: order rejected by validation");
+        METRICS.order_rejected(SOURCE_NATS, "validation");
+        return Reply::Term;
+    }
+
+    // тело сообщения сохраняется как исходный документ заказа
+    let raw = String::from_utf8_lossy(payload);
+    match crate::ingest_order(repo, state, order, &raw).await {
+        Ok(Ingestion::Stored) => {
+            METRICS.order_accepted(SOURCE_NATS);
+            Reply::Ack
+        }
+        Ok(Ingestion::Replayed) => {
+            // повторный заказ тоже подтверждаем, он уже сохранен
+            METRICS.order_rejected(SOURCE_NATS, "duplicate");
+            Reply::Ack
+        }
+        Ok(Ingestion::Conflict(conflicts)) => {
+            // другой заказ с тем же order_uid: повторная доставка не поможет
+            let fields: Vec<&str> = conflicts.iter().map(|diff| diff.field.as_str()).collect();
+            tracing::warn!(?fields, "nats: order conflicts with the stored one");
+            METRICS.order_rejected(SOURCE_NATS, "conflict");
+            Reply::Term
+        }
+        Err(err) => {
+            tracing::error!(error = %err, "nats: failed to store order");
+            METRICS.order_rejected(SOURCE_NATS, "db_error");
+            // повторная доставка поможет, только если БД недоступна; заказ, отклоненный
+            // самой БД (ограничения, некорректные данные), не доставляется повторно
+            match AppError::from(err) {
+                AppError::Unavailable(_) => Reply::Nak,
+                _ => Reply::Term,
+            }
+        }
+    }
+}
+
+// Тесты
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_module::AppState;
+    use crate::postgres_module::PgRepository;
+    use crate::tests::setup_database;
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use tokio::task::JoinHandle;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    // Подписка клиента на встроенном сервере
+    struct Subscription {
+        conn: u64,
+        sid: String,
+        subject: String,
+        outbox: mpsc::UnboundedSender<Vec<u8>>,
+    }
+
+    // Минимальная замена nats-server, работающая внутри процесса теста.
+    // Поддерживает CONNECT, PING/PONG, SUB/UNSUB и PUB без wildcard-подписок
+    struct StandInServer {
+        addr: SocketAddr,
+        subscriptions: Arc<Mutex<Vec<Subscription>>>,
+        connections: Arc<Mutex<HashMap<u64, JoinHandle<()>>>>,
+    }
+
+    impl StandInServer {
+        async fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let subscriptions = Arc::new(Mutex::new(Vec::new()));
+            let connections = Arc::new(Mutex::new(HashMap::new()));
+
+            let subs = subscriptions.clone();
+            let conns = connections.clone();
+            tokio::spawn(async move {
+                let mut next_conn = 0u64;
+                loop {
+                    let (socket, _) = listener.accept().await.unwrap();
+                    next_conn += 1;
+                    let handle = tokio::spawn(serve_connection(
+                        next_conn,
+                        socket,
+                        addr.port(),
+                        subs.clone(),
+                    ));
+                    conns.lock().unwrap().insert(next_conn, handle);
+                }
+            });
+
+            StandInServer {
+                addr,
+                subscriptions,
+                connections,
+            }
+        }
+
+        fn url(&self) -> String {
+            format!("nats://{}", self.addr)
+        }
+
+        // Ожидание, пока кто-нибудь подпишется на subject
+        async fn wait_for_subscription(&self, subject: &str) {
+            tokio::time::timeout(TIMEOUT, async {
+                while !self
+                    .subscriptions
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .any(|sub| sub.subject == subject)
+                {
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+            })
+            .await
+            .expect("subscription was not created");
+        }
+
+        // Обрыв всех клиентских соединений, имитирует перезапуск брокера
+        fn drop_connections(&self) {
+            for (_, handle) in self.connections.lock().unwrap().drain() {
+                handle.abort();
+            }
+            self.subscriptions.lock().unwrap().clear();
+        }
+    }
+
+    async fn serve_connection(
+        conn: u64,
+        socket: tokio::net::TcpStream,
+        port: u16,
+        subscriptions: Arc<Mutex<Vec<Subscription>>>,
+    ) {
+        let (reader, mut writer) = socket.into_split();
+        let (outbox, mut inbox) = mpsc::unbounded_channel::<Vec<u8>>();
+        let writer_task = tokio::spawn(async move {
+            while let Some(bytes) = inbox.recv().await {
+                if writer.write_all(&bytes).await.is_err() {
+                    break;
+                }
+            }
+        });
+        // при обрыве соединения задача записи тоже должна завершиться
+        struct AbortOnDrop(JoinHandle<()>);
+        impl Drop for AbortOnDrop {
+            fn drop(&mut self) {
+                self.0.abort();
+            }
+        }
+        let _writer_guard = AbortOnDrop(writer_task);
+
+        let info = format!(
+            "INFO {{\"server_id\":\"stand-in\",\"server_name\":\"stand-in\",\"version\":\"2.10.0\",\"go\":\"go1.21\",\"host\":\"127.0.0.1\",\"port\":{},\"headers\":true,\"max_payload\":1048576,\"proto\":1}}\r\n",
+            port
+        );
+        outbox.send(info.into_bytes()).unwrap();
+
+        let mut reader = BufReader::new(reader);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line).await.unwrap_or(0) == 0 {
+                break;
+            }
+            let args: Vec<&str> = line.split_whitespace().collect();
+            match args.first().map(|cmd| cmd.to_ascii_uppercase()).as_deref() {
+                Some("PING") => outbox.send(b"PONG\r\n".to_vec()).unwrap(),
+                Some("SUB") => {
+                    // SUB <subject> [queue group] <sid>
+                    subscriptions.lock().unwrap().push(Subscription {
+                        conn,
+                        sid: args[args.len() - 1].to_string(),
+                        subject: args[1].to_string(),
+                        outbox: outbox.clone(),
+                    });
+                }
+                Some("UNSUB") => subscriptions
+                    .lock()
+                    .unwrap()
+                    .retain(|sub| !(sub.conn == conn && sub.sid == args[1])),
+                Some("PUB") => {
+                    // PUB <subject> [reply-to] <size>
+                    let size: usize = args[args.len() - 1].parse().unwrap();
+                    let mut payload = vec![0u8; size + 2];
+                    reader.read_exact(&mut payload).await.unwrap();
+                    payload.truncate(size);
+                    let reply = if args.len() == 4 { Some(args[2]) } else { None };
+
+                    for sub in subscriptions.lock().unwrap().iter() {
+                        if sub.subject != args[1] {
+                            continue;
+                        }
+                        let header = match reply {
+                            Some(reply) => {
+                                format!("MSG {} {} {} {}\r\n", args[1], sub.sid, reply, size)
+                            }
+                            None => format!("MSG {} {} {}\r\n", args[1], sub.sid, size),
+                        };
+                        let mut message = header.into_bytes();
+                        message.extend_from_slice(&payload);
+                        message.extend_from_slice(b"\r\n");
+                        let _ = sub.outbox.send(message);
+                    }
+                }
+                _ => {} // CONNECT, PONG и прочее не требуют ответа
+            }
+        }
+        subscriptions.lock().unwrap().retain(|sub| sub.conn != conn);
+    }
+
+    // Отправка заказа с адресом для подтверждения и ожидание ответа подписчика
+    async fn publish_and_wait_ack(
+        client: &async_nats::Client,
+        ack_subject: &str,
+        payload: Vec<u8>,
+    ) -> String {
+        let mut acks = client.subscribe(ack_subject.to_string()).await.unwrap();
+        client.flush().await.unwrap();
+        client
+            .publish_with_reply("orders.test", ack_subject.to_string(), payload.into())
+            .await
+            .unwrap();
+        let ack = tokio::time::timeout(TIMEOUT, acks.next())
+            .await
+            .expect("no ack received")
+            .unwrap();
+        String::from_utf8(ack.payload.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_nats_subscriber() {
+        let (pool, _schema) = setup_database().await;
+        let server = StandInServer::start().await;
+        let state = Arc::new(OrderCache::new(AppState::new()));
+        let shutdown = Shutdown::new();
+
+        let config = NatsConfig {
+            url: server.url(),
+            subject: "orders.test".to_string(),
+            queue_group: Some("order-service".to_string()),
+        };
+        let subscriber = tokio::spawn(run_subscriber(
+            config,
+            Arc::new(PgRepository::new(pool.clone())),
+            state.clone(),
+            shutdown.clone(),
+        ));
+        server.wait_for_subscription("orders.test").await;
+
+        let client = async_nats::connect(server.url()).await.unwrap();
+        let order = std::fs::read("models/model1.json").unwrap();
+
+        // Корректный заказ записывается в БД и кэш и подтверждается
+        let ack = publish_and_wait_ack(&client, "acks.1", order.clone()).await;
+        assert_eq!(ack, "+ACK");
+        assert!(state.read().get_order("b563feb7b2b84b6test").is_some());
+
+        // Повторная доставка того же заказа тоже подтверждается
+        let ack = publish_and_wait_ack(&client, "acks.2", order.clone()).await;
+        assert_eq!(ack, "+ACK");
+
+        // Другой заказ с тем же order_uid отклоняется без повторной доставки
+        let mut changed: Order = serde_json::from_slice(&order).unwrap();
+        changed.delivery.city = "Other City".to_string();
+        let ack =
+            publish_and_wait_ack(&client, "acks.2a", serde_json::to_vec(&changed).unwrap()).await;
+        assert_eq!(ack, "+TERM");
+
+        // Некорректный json не будет доставляться повторно
+        let broken = std::fs::read("models/model_not_correct.json").unwrap();
+        let ack = publish_and_wait_ack(&client, "acks.3", broken).await;
+        assert_eq!(ack, "+TERM");
+
+        // Заказ, отклоненный БД, не доставляется повторно. Заказ проходит проверки
+        // и отклоняется только ограничением, добавленным в схему теста
+        sqlx::query("ALTER TABLE item ADD CONSTRAINT item_size_test CHECK (size <> 'rejected')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let mut failing: Order =
+            serde_json::from_slice(&std::fs::read("models/model2.json").unwrap()).unwrap();
+        failing.items[0].size = "rejected".to_string();
+        let ack =
+            publish_and_wait_ack(&client, "acks.4", serde_json::to_vec(&failing).unwrap()).await;
+        assert_eq!(ack, "+TERM");
+        assert!(state.read().get_order(&failing.order_uid).is_none());
+
+        // После обрыва соединения подписчик переподключается и продолжает работу
+        server.drop_connections();
+        server.wait_for_subscription("orders.test").await;
+        let client = async_nats::connect(server.url()).await.unwrap();
+        let order = std::fs::read("models/model3.json").unwrap();
+        let ack = publish_and_wait_ack(&client, "acks.5", order).await;
+        assert_eq!(ack, "+ACK");
+
+        let stored: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM orders")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored.0, 2);
+
+        // По сигналу остановки подписчик завершается сам
+        shutdown.trigger();
+        tokio::time::timeout(TIMEOUT, subscriber)
+            .await
+            .expect("subscriber did not stop")
+            .unwrap();
+    }
+
+    // Недоступная БД: сообщение доставляется повторно
+    #[tokio::test]
+    async fn test_unavailable_database_naks() {
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .acquire_timeout(Duration::from_secs(1))
+            .connect_lazy("postgres://orders@127.0.0.1:1/orders")
+            .unwrap();
+        let repo = PgRepository::new(pool);
+        let state = Arc::new(OrderCache::new(AppState::new()));
+
+        let order = std::fs::read("models/model1.json").unwrap();
+        assert_eq!(process_message(&order, &repo, &state).await, Reply::Nak);
+        assert!(state.read().get_order("b563feb7b2b84b6test").is_none());
+    }
+}
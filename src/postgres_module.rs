@@ -0,0 +1,929 @@
+use crate::db_module::{self, Item, ItemRow, Order, OrderRow};
+use crate::erasure_module::anonymize;
+use crate::idempotency_module::{content_hash, SavedKey};
+use crate::metrics_module::METRICS;
+use crate::repository_module::{Keyed, OrderRepository, PoolStatus, Removal, Saved};
+use crate::update_module::IfMatch;
+use axum::async_trait;
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+use serde_json::Value;
+use sqlx::migrate::MigrateError;
+use sqlx::postgres::{PgConnection, PgExecutor, PgPool};
+use sqlx::{Connection, Error};
+use std::collections::{HashMap, HashSet};
+
+// Хранилище заказов в PostgreSQL: delivery, payment, orders и item, исходные документы
+// в orders.raw_document и ключи идемпотентности в idempotency_keys
+pub struct PgRepository {
+    pool: PgPool,
+}
+
+impl PgRepository {
+    pub fn new(pool: PgPool) -> Self {
+        PgRepository { pool }
+    }
+}
+
+#[async_trait]
+impl OrderRepository for PgRepository {
+    async fn insert(&self, order: &Order, raw: &str) -> Result<Option<i64>, Error> {
+        insert_order(&self.pool, order, raw).await
+    }
+
+    async fn insert_keyed(&self, order: &Order, raw: &str, key: &str) -> Result<Keyed, Error> {
+        insert_keyed(&self.pool, order, raw, key).await
+    }
+
+    async fn insert_batch(&self, orders: &[(&Order, &str)]) -> Result<HashMap<String, i64>, Error> {
+        insert_batch(&self.pool, orders).await
+    }
+
+    async fn get(&self, order_uid: &str) -> Result<Option<Order>, Error> {
+        fetch_order(&self.pool, order_uid).await
+    }
+
+    async fn list(&self, offset: usize, limit: usize) -> Result<Vec<Order>, Error> {
+        let rows: Vec<OrderRow> = sqlx::query_as(LIST_ORDERS_QUERY)
+            .bind(offset as i64)
+            .bind(limit as i64)
+            .fetch_all(&self.pool)
+            .await?;
+        with_items(&self.pool, rows).await
+    }
+
+    async fn update(
+        &self,
+        order: &Order,
+        raw: &str,
+        condition: Option<&IfMatch>,
+    ) -> Result<Saved, Error> {
+        save_order(&self.pool, order, raw, condition).await
+    }
+
+    async fn delete(&self, order_uid: &str, condition: Option<&IfMatch>) -> Result<Removal, Error> {
+        remove_order(&self.pool, order_uid, condition).await
+    }
+
+    // Заказы читаются одним запросом потоком и обрабатываются пачками,
+    // поэтому на всю загрузку приходится по одному запросу товаров на пачку
+    fn stream_all(&self) -> BoxStream<'_, Result<Order, Error>> {
+        sqlx::query_as::<_, OrderRow>(LOAD_ORDERS_QUERY)
+            .fetch(&self.pool)
+            .try_chunks(LOAD_CHUNK_SIZE)
+            .map_err(|err| err.1)
+            .and_then(|chunk| with_items(&self.pool, chunk))
+            .map_ok(|orders| futures::stream::iter(orders.into_iter().map(Ok)))
+            .try_flatten()
+            .boxed()
+    }
+
+    async fn raw_document(&self, order_uid: &str) -> Result<Option<(Option<String>, i64)>, Error> {
+        fetch_raw(&self.pool, order_uid).await
+    }
+
+    async fn content_hash(&self, order_uid: &str) -> Result<Option<String>, Error> {
+        let stored = sqlx::query_scalar!(
+            r#"
+            SELECT content_hash FROM orders WHERE order_uid = $1
+            "#,
+            order_uid
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(stored.flatten())
+    }
+
+    async fn backfill_hash(&self, order_uid: &str, content_hash: &str) -> Result<(), Error> {
+        sqlx::query!(
+            r#"
+            UPDATE orders SET content_hash = $2 WHERE order_uid = $1 AND content_hash IS NULL
+            "#,
+            order_uid,
+            content_hash,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn erase_customer(
+        &self,
+        customer_id: &str,
+        erased: &str,
+    ) -> Result<Vec<(String, i64)>, Error> {
+        erase_personal_data(&self.pool, customer_id, erased).await
+    }
+
+    async fn save_key(
+        &self,
+        key: &str,
+        order_uid: &str,
+        content_hash: &str,
+    ) -> Result<SavedKey, Error> {
+        let mut tx = self.pool.begin().await?;
+        let saved = reserve_key(&mut tx, key, order_uid, content_hash).await?;
+        tx.commit().await?;
+        Ok(saved.unwrap_or_else(|| SavedKey {
+            order_uid: order_uid.to_string(),
+            content_hash: content_hash.to_string(),
+        }))
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn pending_migrations(&self) -> Result<Vec<i64>, MigrateError> {
+        db_module::pending_migrations(&self.pool).await
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        Some(PoolStatus {
+            size: self.pool.size(),
+            idle: self.pool.num_idle(),
+            max_connections: self.pool.options().get_max_connections(),
+        })
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
+
+// Размер пачки заказов, товары которых загружаются одним запросом
+const LOAD_CHUNK_SIZE: usize = 1000;
+
+// Выборка заказов вместе с delivery и payment, общая часть запросов загрузки
+macro_rules! select_orders {
+    () => {
+        r#"
+        SELECT o.order_uid, o.track_number, o.entry, o.locale, o.internal_signature,
+               o.customer_id, o.delivery_service, o.shardkey, o.sm_id, o.date_created, o.oof_shard,
+               o.version, o.seq, o.extras,
+               d.name, d.phone, d.zip, d.city, d.address, d.region, d.email,
+               p.transaction, p.request_id, p.currency, p.provider, p.amount, p.payment_dt,
+               p.bank, p.delivery_cost, p.goods_total, p.custom_fee
+        FROM orders o
+        JOIN delivery d ON d.id = o.delivery_id
+        JOIN payment p ON p.id = o.payment_id
+        "#
+    };
+}
+
+// Все заказы в порядке поступления
+static LOAD_ORDERS_QUERY: &str = concat!(select_orders!(), "ORDER BY o.seq");
+
+// Страница заказов в порядке поступления
+static LIST_ORDERS_QUERY: &str = concat!(select_orders!(), "ORDER BY o.seq OFFSET $1 LIMIT $2");
+
+// Один заказ по order_uid
+static FETCH_ORDER_QUERY: &str = concat!(select_orders!(), "WHERE o.order_uid = $1");
+
+// Заказы покупателя с необезличенными данными получателя; блокируются до конца транзакции
+static ERASE_ORDERS_QUERY: &str = concat!(
+    select_orders!(),
+    r#"WHERE o.customer_id = $1
+        AND (d.name, d.phone, d.address, d.email) IS DISTINCT FROM ($2, $2, $2, $2)
+    ORDER BY o.order_uid
+    FOR UPDATE OF o, d"#
+);
+
+// Товары для пачки заказов
+static LOAD_ITEMS_QUERY: &str = r#"
+        SELECT order_uid, chrt_id, track_number, price, rid, name, sale, size,
+               total_price, nm_id, brand, status
+        FROM item
+        WHERE order_uid = ANY($1)
+        ORDER BY id
+    "#;
+
+// Товары для пачки строк заказов одним запросом
+async fn with_items<'c>(
+    executor: impl PgExecutor<'c>,
+    rows: Vec<OrderRow>,
+) -> Result<Vec<Order>, Error> {
+    let uids: Vec<&str> = rows.iter().map(|row| row.order_uid.as_str()).collect();
+    let item_rows: Vec<ItemRow> = sqlx::query_as(LOAD_ITEMS_QUERY)
+        .bind(&uids)
+        .fetch_all(executor)
+        .await?;
+    let mut items: HashMap<String, Vec<Item>> = HashMap::new();
+    for row in item_rows {
+        items.entry(row.order_uid).or_default().push(row.item);
+    }
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let order_items = items.remove(&row.order_uid).unwrap_or_default();
+            row.into_order(order_items)
+        })
+        .collect())
+}
+
+// Загрузка одного заказа из БД
+async fn fetch_order(db_pool: &PgPool, order_uid: &str) -> Result<Option<Order>, Error> {
+    let row: Option<OrderRow> = sqlx::query_as(FETCH_ORDER_QUERY)
+        .bind(order_uid)
+        .fetch_optional(db_pool)
+        .await?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+
+    let items: Vec<ItemRow> = sqlx::query_as(LOAD_ITEMS_QUERY)
+        .bind([order_uid])
+        .fetch_all(db_pool)
+        .await?;
+    let items = items.into_iter().map(|row| row.item).collect();
+    Ok(Some(row.into_order(items)))
+}
+
+async fn insert_order(pool: &PgPool, order: &Order, raw: &str) -> Result<Option<i64>, Error> {
+    // Вся запись заказа выполняется в одной транзакции: при ошибке на любом шаге
+    // транзакция откатывается и в БД не остается "осиротевших" delivery/payment
+    let _timer = METRICS.insert_order_duration.start_timer();
+    let mut tx = pool.begin().await?;
+
+    // Проверяем, содержится ли в базе запись с указанным "order_uid"
+    if check_order_exists(&mut tx, &order.order_uid).await? {
+        return Ok(None); // запись уже есть в БД, транзакция откатывается при drop
+    }
+
+    // запись отстутствует, выполняем вставку; заказ, записанный параллельным запросом
+    // после проверки, тоже откатывает транзакцию
+    let Some(seq) = insert_order_rows(&mut tx, order, raw).await? else {
+        return Ok(None);
+    };
+
+    tx.commit().await?; // фиксируем все вставки разом
+    Ok(Some(seq))
+}
+
+// Запись нового заказа и ключа идемпотентности в одной транзакции
+async fn insert_keyed(pool: &PgPool, order: &Order, raw: &str, key: &str) -> Result<Keyed, Error> {
+    let _timer = METRICS.insert_order_duration.start_timer();
+    let mut tx = pool.begin().await?;
+    if check_order_exists(&mut tx, &order.order_uid).await? {
+        return Ok(Keyed::Exists);
+    }
+    // ключ занимается до вставки заказа: второй запрос с тем же ключом ждет
+    // завершения этой транзакции и получает занятый ключ
+    if let Some(saved) = reserve_key(&mut tx, key, &order.order_uid, &content_hash(order)).await? {
+        return Ok(Keyed::KeyTaken(saved));
+    }
+    let Some(seq) = insert_order_rows(&mut tx, order, raw).await? else {
+        return Ok(Keyed::Exists); // ключ освобождается вместе с откатом транзакции
+    };
+    tx.commit().await?;
+    Ok(Keyed::Inserted(seq))
+}
+
+// Вставка заказа в delivery, payment, orders и item в рамках транзакции вызывающего;
+// raw — исходный документ заказа. Возвращает порядковый номер заказа; Ok(None) — заказ
+// с таким order_uid уже записан параллельной транзакцией, вызывающий откатывает свою
+// транзакцию вместе с delivery и payment
+async fn insert_order_rows(
+    conn: &mut PgConnection,
+    order: &Order,
+    raw: &str,
+) -> Result<Option<i64>, Error> {
+    let delivery_id: i32 = sqlx::query!(
+        r#"
+        INSERT INTO delivery (name, phone, zip, city, address, region, email)
+        VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id
+        "#,
+        order.delivery.name,
+        order.delivery.phone,
+        order.delivery.zip,
+        order.delivery.city,
+        order.delivery.address,
+        order.delivery.region,
+        order.delivery.email,
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    .id;
+
+    let payment_id: i32 = sqlx::query!(
+        r#"
+        INSERT INTO payment (transaction, request_id, currency, provider, amount, payment_dt, bank, delivery_cost, goods_total, custom_fee)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING id
+        "#,
+        order.payment.transaction,
+        order.payment.request_id,
+        order.payment.currency,
+        order.payment.provider,
+        order.payment.amount,
+        order.payment.payment_dt,
+        order.payment.bank,
+        order.payment.delivery_cost,
+        order.payment.goods_total,
+        order.payment.custom_fee,
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    .id;
+
+    let seq = sqlx::query_scalar!(
+        r#"
+        INSERT INTO orders (order_uid, track_number, entry, delivery_id, payment_id, locale, internal_signature, customer_id, delivery_service, shardkey, sm_id, date_created, oof_shard, content_hash, raw_document, extras)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
+        ON CONFLICT (order_uid) DO NOTHING
+        RETURNING seq
+        "#,
+        order.order_uid,
+        order.track_number,
+        order.entry,
+        delivery_id,
+        payment_id,
+        order.locale,
+        order.internal_signature,
+        order.customer_id,
+        order.delivery_service,
+        order.shardkey,
+        order.sm_id,
+        order.date_created,
+        order.oof_shard,
+        content_hash(order),
+        raw,
+        sqlx::types::Json(&order.extras) as _,
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    if seq.is_none() {
+        return Ok(None);
+    }
+
+    insert_items(conn, order).await?;
+    Ok(seq)
+}
+
+// Вставка товаров заказа
+async fn insert_items(conn: &mut PgConnection, order: &Order) -> Result<(), Error> {
+    for item in &order.items {
+        sqlx::query!(
+            r#"
+            INSERT INTO item (chrt_id, track_number, price, rid, name, sale, size, total_price, nm_id, brand, status, order_uid)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+            "#,
+            item.chrt_id,
+            item.track_number,
+            item.price,
+            item.rid,
+            item.name,
+            item.sale,
+            item.size,
+            item.total_price,
+            item.nm_id,
+            item.brand,
+            item.status,
+            order.order_uid,
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+// Функция для проверки полученных данных на то что они уже есть БД
+async fn check_order_exists(conn: &mut PgConnection, uid: &str) -> Result<bool, Error> {
+    // Выполняем запрос для поиска записи в БД по значению "order_uid"
+    let result = sqlx::query!(
+        r#"
+        SELECT order_uid FROM orders WHERE order_uid = $1
+        "#,
+        uid
+    )
+    .fetch_optional(conn)
+    .await;
+
+    match result {
+        Ok(Some(_)) => Ok(true), // запись найдена
+        Ok(None) => Ok(false),   // запись не найдена
+        Err(err) => Err(err),    // обработка ошибки
+    }
+}
+
+// Запись заказа в одной транзакции: новый заказ вставляется, у существующего обновляются
+// delivery, payment и orders, а товары заменяются целиком. Строка заказа блокируется
+// до коммита, поэтому проверка версии и изменение не разделяются параллельной записью
+async fn save_order(
+    pool: &PgPool,
+    order: &Order,
+    raw: &str,
+    condition: Option<&IfMatch>,
+) -> Result<Saved, Error> {
+    let mut tx = pool.begin().await?;
+    let current = loop {
+        let current = sqlx::query!(
+            r#"
+            SELECT delivery_id, payment_id, version, seq FROM orders
+            WHERE order_uid = $1 FOR UPDATE
+            "#,
+            order.order_uid
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some(current) = current {
+            break current;
+        }
+        if condition.is_some() {
+            return Ok(Saved::Mismatch(None));
+        }
+        // вложенная транзакция — точка сохранения: если заказ создан параллельным
+        // запросом, строки delivery и payment этой попытки откатываются, а заказ
+        // изменяется как существующий
+        let mut attempt = tx.begin().await?;
+        if let Some(seq) = insert_order_rows(&mut attempt, order, raw).await? {
+            attempt.commit().await?;
+            tx.commit().await?;
+            return Ok(Saved::Created(seq));
+        }
+        attempt.rollback().await?;
+    };
+    if condition.is_some_and(|condition| !condition.matches(current.version)) {
+        return Ok(Saved::Mismatch(Some(current.version)));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE delivery SET name = $2, phone = $3, zip = $4, city = $5, address = $6, region = $7, email = $8
+        WHERE id = $1
+        "#,
+        current.delivery_id,
+        order.delivery.name,
+        order.delivery.phone,
+        order.delivery.zip,
+        order.delivery.city,
+        order.delivery.address,
+        order.delivery.region,
+        order.delivery.email,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE payment SET transaction = $2, request_id = $3, currency = $4, provider = $5, amount = $6,
+            payment_dt = $7, bank = $8, delivery_cost = $9, goods_total = $10, custom_fee = $11
+        WHERE id = $1
+        "#,
+        current.payment_id,
+        order.payment.transaction,
+        order.payment.request_id,
+        order.payment.currency,
+        order.payment.provider,
+        order.payment.amount,
+        order.payment.payment_dt,
+        order.payment.bank,
+        order.payment.delivery_cost,
+        order.payment.goods_total,
+        order.payment.custom_fee,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let version = sqlx::query!(
+        r#"
+        UPDATE orders SET track_number = $2, entry = $3, locale = $4, internal_signature = $5,
+            customer_id = $6, delivery_service = $7, shardkey = $8, sm_id = $9, date_created = $10,
+            oof_shard = $11, content_hash = $12, raw_document = $13, extras = $14,
+            version = version + 1
+        WHERE order_uid = $1
+        RETURNING version
+        "#,
+        order.order_uid,
+        order.track_number,
+        order.entry,
+        order.locale,
+        order.internal_signature,
+        order.customer_id,
+        order.delivery_service,
+        order.shardkey,
+        order.sm_id,
+        order.date_created,
+        order.oof_shard,
+        content_hash(order),
+        raw,
+        sqlx::types::Json(&order.extras) as _,
+    )
+    .fetch_one(&mut *tx)
+    .await?
+    .version;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM item WHERE order_uid = $1
+        "#,
+        order.order_uid
+    )
+    .execute(&mut *tx)
+    .await?;
+    insert_items(&mut tx, order).await?;
+
+    tx.commit().await?;
+    Ok(Saved::Updated(version, current.seq))
+}
+
+// Удаление заказа в одной транзакции; товары удаляются каскадно вместе с orders
+async fn remove_order(
+    pool: &PgPool,
+    order_uid: &str,
+    condition: Option<&IfMatch>,
+) -> Result<Removal, Error> {
+    let mut tx = pool.begin().await?;
+    let current = sqlx::query!(
+        r#"
+        SELECT delivery_id, payment_id, version, seq FROM orders WHERE order_uid = $1 FOR UPDATE
+        "#,
+        order_uid
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(current) = current else {
+        return Ok(Removal::Missing);
+    };
+    if condition.is_some_and(|condition| !condition.matches(current.version)) {
+        return Ok(Removal::Mismatch(current.version));
+    }
+
+    sqlx::query!(
+        r#"
+        DELETE FROM orders WHERE order_uid = $1
+        "#,
+        order_uid
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM delivery WHERE id = $1
+        "#,
+        current.delivery_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM payment WHERE id = $1
+        "#,
+        current.payment_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    // повтор запроса с ключом удаленного заказа должен записать заказ заново
+    sqlx::query!(
+        r#"
+        DELETE FROM idempotency_keys WHERE order_uid = $1
+        "#,
+        order_uid
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(Removal::Removed(current.seq))
+}
+
+// Обезличивание delivery и исходного документа и увеличение версии заказов одним запросом.
+// Хэш содержимого сбрасывается и будет вычислен заново при следующем сравнении
+// (см. compare_with_stored). Возвращает order_uid и новую версию измененных заказов
+async fn erase_personal_data(
+    pool: &PgPool,
+    customer_id: &str,
+    erased: &str,
+) -> Result<Vec<(String, i64)>, Error> {
+    let mut tx = pool.begin().await?;
+    let rows: Vec<OrderRow> = sqlx::query_as(ERASE_ORDERS_QUERY)
+        .bind(customer_id)
+        .bind(erased)
+        .fetch_all(&mut *tx)
+        .await?;
+    let orders = with_items(&mut *tx, rows).await?;
+
+    // исходный документ заменяется обезличенным заказом, вместе с ним и столбец document
+    let mut changed = Vec::new();
+    for mut order in orders {
+        anonymize(&mut order, erased);
+        let raw = serde_json::to_string(&order).expect("order is serializable");
+        sqlx::query!(
+            r#"
+            UPDATE delivery d SET name = $2, phone = $2, address = $2, email = $2
+            FROM orders o
+            WHERE o.delivery_id = d.id AND o.order_uid = $1
+            "#,
+            order.order_uid,
+            erased,
+        )
+        .execute(&mut *tx)
+        .await?;
+        let version = sqlx::query_scalar!(
+            r#"
+            UPDATE orders SET version = version + 1, content_hash = NULL,
+                raw_document = $2, extras = '{}'
+            WHERE order_uid = $1
+            RETURNING version
+            "#,
+            order.order_uid,
+            raw,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        changed.push((order.order_uid, version));
+    }
+    tx.commit().await?;
+    Ok(changed)
+}
+
+// Исходный документ заказа; Ok(None) — заказа нет, Some((None, _)) — заказ записан
+// до появления исходных документов
+async fn fetch_raw(pool: &PgPool, order_uid: &str) -> Result<Option<(Option<String>, i64)>, Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT raw_document, version FROM orders WHERE order_uid = $1
+        "#,
+        order_uid
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|row| (row.raw_document, row.version)))
+}
+
+// Запись ключа в транзакции вызывающего, если ключ свободен или его заказ удален;
+// Some — ключ занят другим заказом, он возвращается без изменений
+async fn reserve_key(
+    conn: &mut PgConnection,
+    key: &str,
+    order_uid: &str,
+    content_hash: &str,
+) -> Result<Option<SavedKey>, Error> {
+    loop {
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO idempotency_keys (key, order_uid, content_hash)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (key) DO NOTHING
+            "#,
+            key,
+            order_uid,
+            content_hash,
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected();
+        if inserted == 1 {
+            return Ok(None);
+        }
+
+        // строка ключа блокируется до конца транзакции, чтобы параллельный запрос
+        // не занял ключ удаленного заказа одновременно с этим
+        let Some(saved) = sqlx::query!(
+            r#"
+            SELECT order_uid, content_hash FROM idempotency_keys WHERE key = $1 FOR UPDATE
+            "#,
+            key
+        )
+        .fetch_optional(&mut *conn)
+        .await?
+        else {
+            continue; // ключ удален вместе с заказом, пробуем записать заново
+        };
+        if check_order_exists(conn, &saved.order_uid).await? {
+            return Ok(Some(SavedKey {
+                order_uid: saved.order_uid,
+                content_hash: saved.content_hash,
+            }));
+        }
+        sqlx::query!(
+            r#"
+            UPDATE idempotency_keys SET order_uid = $2, content_hash = $3, created_at = now()
+            WHERE key = $1
+            "#,
+            key,
+            order_uid,
+            content_hash,
+        )
+        .execute(&mut *conn)
+        .await?;
+        return Ok(None);
+    }
+}
+
+// Запись пачки новых заказов вместе с исходными документами в одной транзакции
+// многострочными вставками (UNNEST). Идентификаторы delivery и payment выделяются заранее,
+// чтобы связать строки без расчета на порядок RETURNING. Возвращает order_uid записанных
+// заказов; заказы, которые уже есть в БД или повторяются в пачке, не записываются
+async fn insert_batch(
+    pool: &PgPool,
+    orders: &[(&Order, &str)],
+) -> Result<HashMap<String, i64>, Error> {
+    let uids: Vec<String> = orders
+        .iter()
+        .map(|(order, _)| order.order_uid.clone())
+        .collect();
+    let existing: HashSet<String> = sqlx::query_scalar!(
+        r#"
+        SELECT order_uid FROM orders WHERE order_uid = ANY($1)
+        "#,
+        &uids
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .collect();
+    let mut seen = HashSet::new();
+    let (orders, raws): (Vec<&Order>, Vec<String>) = orders
+        .iter()
+        .filter(|(order, _)| {
+            !existing.contains(&order.order_uid) && seen.insert(order.order_uid.as_str())
+        })
+        .map(|(order, raw)| (*order, raw.to_string()))
+        .unzip();
+    if orders.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let mut tx = pool.begin().await?;
+    let count = orders.len() as i32;
+    let delivery_ids: Vec<i32> = sqlx::query_scalar!(
+        r#"
+        SELECT nextval(pg_get_serial_sequence('delivery', 'id'))::INT4 AS "id!"
+        FROM generate_series(1, $1::INT4)
+        "#,
+        count
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let payment_ids: Vec<i32> = sqlx::query_scalar!(
+        r#"
+        SELECT nextval(pg_get_serial_sequence('payment', 'id'))::INT4 AS "id!"
+        FROM generate_series(1, $1::INT4)
+        "#,
+        count
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let text_column = |field: fn(&Order) -> String| -> Vec<String> {
+        orders.iter().map(|order| field(order)).collect()
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO delivery (id, name, phone, zip, city, address, region, email)
+        SELECT * FROM UNNEST($1::INT4[], $2::TEXT[], $3::TEXT[], $4::TEXT[], $5::TEXT[],
+            $6::TEXT[], $7::TEXT[], $8::TEXT[])
+        "#,
+        &delivery_ids,
+        &text_column(|order| order.delivery.name.clone()),
+        &text_column(|order| order.delivery.phone.clone()),
+        &text_column(|order| order.delivery.zip.clone()),
+        &text_column(|order| order.delivery.city.clone()),
+        &text_column(|order| order.delivery.address.clone()),
+        &text_column(|order| order.delivery.region.clone()),
+        &text_column(|order| order.delivery.email.clone()),
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let int_column = |field: fn(&Order) -> i32| -> Vec<i32> {
+        orders.iter().map(|order| field(order)).collect()
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO payment (id, transaction, request_id, currency, provider, amount, payment_dt,
+            bank, delivery_cost, goods_total, custom_fee)
+        SELECT * FROM UNNEST($1::INT4[], $2::TEXT[], $3::TEXT[], $4::TEXT[], $5::TEXT[],
+            $6::INT4[], $7::INT8[], $8::TEXT[], $9::INT4[], $10::INT4[], $11::INT4[])
+        "#,
+        &payment_ids,
+        &text_column(|order| order.payment.transaction.clone()),
+        &text_column(|order| order.payment.request_id.clone()),
+        &text_column(|order| order.payment.currency.clone()),
+        &text_column(|order| order.payment.provider.clone()),
+        &int_column(|order| order.payment.amount),
+        &orders
+            .iter()
+            .map(|order| order.payment.payment_dt)
+            .collect::<Vec<i64>>(),
+        &text_column(|order| order.payment.bank.clone()),
+        &int_column(|order| order.payment.delivery_cost),
+        &int_column(|order| order.payment.goods_total),
+        &int_column(|order| order.payment.custom_fee),
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // заказ, записанный параллельным запросом после проверки, пропускается
+    let inserted: HashMap<String, i64> = sqlx::query!(
+        r#"
+        INSERT INTO orders (order_uid, track_number, entry, delivery_id, payment_id, locale,
+            internal_signature, customer_id, delivery_service, shardkey, sm_id, date_created,
+            oof_shard, content_hash, raw_document, extras)
+        SELECT * FROM UNNEST($1::TEXT[], $2::TEXT[], $3::TEXT[], $4::INT4[], $5::INT4[],
+            $6::TEXT[], $7::TEXT[], $8::TEXT[], $9::TEXT[], $10::TEXT[], $11::INT4[],
+            $12::TEXT[], $13::TEXT[], $14::TEXT[], $15::TEXT[], $16::JSONB[])
+        ON CONFLICT (order_uid) DO NOTHING
+        RETURNING order_uid, seq
+        "#,
+        &text_column(|order| order.order_uid.clone()),
+        &text_column(|order| order.track_number.clone()),
+        &text_column(|order| order.entry.clone()),
+        &delivery_ids,
+        &payment_ids,
+        &text_column(|order| order.locale.clone()),
+        &text_column(|order| order.internal_signature.clone()),
+        &text_column(|order| order.customer_id.clone()),
+        &text_column(|order| order.delivery_service.clone()),
+        &text_column(|order| order.shardkey.clone()),
+        &int_column(|order| order.sm_id),
+        &text_column(|order| order.date_created.clone()),
+        &text_column(|order| order.oof_shard.clone()),
+        &text_column(content_hash),
+        &raws,
+        &orders
+            .iter()
+            .map(|order| Value::Object(order.extras.clone()))
+            .collect::<Vec<Value>>(),
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|row| (row.order_uid, row.seq))
+    .collect();
+
+    if inserted.len() < orders.len() {
+        // delivery и payment пропущенных заказов не нужны
+        let (lost_deliveries, lost_payments): (Vec<i32>, Vec<i32>) = orders
+            .iter()
+            .zip(delivery_ids.iter().zip(&payment_ids))
+            .filter(|(order, _)| !inserted.contains_key(&order.order_uid))
+            .map(|(_, (delivery_id, payment_id))| (*delivery_id, *payment_id))
+            .unzip();
+        sqlx::query!(
+            r#"
+            DELETE FROM delivery WHERE id = ANY($1)
+            "#,
+            &lost_deliveries
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            r#"
+            DELETE FROM payment WHERE id = ANY($1)
+            "#,
+            &lost_payments
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let items: Vec<(&str, &Item)> = orders
+        .iter()
+        .filter(|order| inserted.contains_key(&order.order_uid))
+        .flat_map(|order| {
+            order
+                .items
+                .iter()
+                .map(|item| (order.order_uid.as_str(), item))
+        })
+        .collect();
+    let item_text = |field: fn(&Item) -> &str| -> Vec<String> {
+        items
+            .iter()
+            .map(|(_, item)| field(item).to_string())
+            .collect()
+    };
+    let item_int = |field: fn(&Item) -> i32| -> Vec<i32> {
+        items.iter().map(|(_, item)| field(item)).collect()
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO item (chrt_id, track_number, price, rid, name, sale, size, total_price,
+            nm_id, brand, status, order_uid)
+        SELECT * FROM UNNEST($1::INT4[], $2::TEXT[], $3::INT4[], $4::TEXT[], $5::TEXT[],
+            $6::INT4[], $7::TEXT[], $8::INT4[], $9::INT4[], $10::TEXT[], $11::INT4[], $12::TEXT[])
+        "#,
+        &item_int(|item| item.chrt_id),
+        &item_text(|item| &item.track_number),
+        &item_int(|item| item.price),
+        &item_text(|item| &item.rid),
+        &item_text(|item| &item.name),
+        &item_int(|item| item.sale),
+        &item_text(|item| &item.size),
+        &item_int(|item| item.total_price),
+        &item_int(|item| item.nm_id),
+        &item_text(|item| &item.brand),
+        &item_int(|item| item.status),
+        &items
+            .iter()
+            .map(|(order_uid, _)| order_uid.to_string())
+            .collect::<Vec<String>>(),
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(inserted)
+}
@@ -0,0 +1,514 @@
+use crate::db_module::{AppState, IndexField, Order};
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt::Write;
+use std::sync::Arc;
+
+// Размер страницы по умолчанию и максимально допустимый
+pub const DEFAULT_LIMIT: usize = 100;
+pub const MAX_LIMIT: usize = 1000;
+
+// Параметры запроса GET /orders
+#[derive(Deserialize, Debug, Default)]
+pub struct OrdersQuery {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    pub cursor: Option<String>, // next_cursor предыдущей страницы
+    pub customer_id: Option<String>,
+    pub track_number: Option<String>,
+    pub nm_id: Option<i32>,          // заказы с товаром nm_id
+    pub rid: Option<String>,         // заказы с товаром rid
+    pub transaction: Option<String>, // payment.transaction
+    pub delivery_service: Option<String>,
+    pub locale: Option<String>,
+    pub date_from: Option<String>, // RFC 3339, граница включается
+    pub date_to: Option<String>,   // RFC 3339, граница включается
+    pub sort: Option<String>,      // поле сортировки, "-" в начале для обратного порядка
+}
+
+// Ответ GET /orders
+#[derive(Serialize, Debug)]
+pub struct OrdersPage {
+    pub orders: Vec<Arc<Order>>,
+    pub total: usize, // количество заказов, удовлетворяющих фильтрам
+    pub next_cursor: Option<String>,
+}
+
+// Поля, по которым возможна сортировка
+#[derive(Clone, Copy, Debug)]
+enum SortField {
+    DateCreated,
+    OrderUid,
+    CustomerId,
+    TrackNumber,
+}
+
+impl SortField {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "date_created" => Some(SortField::DateCreated),
+            "order_uid" => Some(SortField::OrderUid),
+            "customer_id" => Some(SortField::CustomerId),
+            "track_number" => Some(SortField::TrackNumber),
+            _ => None,
+        }
+    }
+
+    fn key<'a>(&self, order: &'a Order) -> &'a str {
+        match self {
+            SortField::DateCreated => &order.date_created,
+            SortField::OrderUid => &order.order_uid,
+            SortField::CustomerId => &order.customer_id,
+            SortField::TrackNumber => &order.track_number,
+        }
+    }
+
+    // Сравнение значений ключа; даты сравниваются как моменты времени
+    fn compare(&self, a: &str, b: &str) -> Ordering {
+        match self {
+            SortField::DateCreated => parse_date(a).cmp(&parse_date(b)).then_with(|| a.cmp(b)),
+            _ => a.cmp(b),
+        }
+    }
+}
+
+// Позиция последнего заказа страницы: ключ сортировки и orders.seq. Клиенту передается
+// как hex от json, поэтому продолжение выдачи не зависит от того, остался ли сам заказ в кэше
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Cursor {
+    sort: Option<String>,
+    key: Option<String>,
+    seq: i64,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("cursor is always serializable");
+        json.iter()
+            .fold(String::with_capacity(json.len() * 2), |mut hex, byte| {
+                let _ = write!(hex, "{:02x}", byte);
+                hex
+            })
+    }
+
+    fn decode(value: &str) -> Option<Self> {
+        if !value.len().is_multiple_of(2) || !value.is_ascii() {
+            return None;
+        }
+        let bytes = (0..value.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&value[i..i + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+}
+
+fn parse_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(value).ok()
+}
+
+fn parse_bound(
+    name: &str,
+    value: &Option<String>,
+) -> Result<Option<DateTime<FixedOffset>>, String> {
+    match value {
+        Some(value) => parse_date(value)
+            .map(Some)
+            .ok_or_else(|| format!("'{}' must be an RFC 3339 date: {}", name, value)),
+        None => Ok(None),
+    }
+}
+
+// Выборка страницы заказов из кэша с учетом фильтров, сортировки и пагинации
+pub fn query_orders(state: &AppState, query: &OrdersQuery) -> Result<OrdersPage, String> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(format!("'limit' must be between 1 and {}", MAX_LIMIT));
+    }
+    if query.cursor.is_some() && query.offset.is_some() {
+        return Err("'cursor' and 'offset' can not be used together".to_string());
+    }
+    let date_from = parse_bound("date_from", &query.date_from)?;
+    let date_to = parse_bound("date_to", &query.date_to)?;
+    let sort = match query.sort.as_deref() {
+        Some(sort) => {
+            let (descending, name) = match sort.strip_prefix('-') {
+                Some(name) => (true, name),
+                None => (false, sort),
+            };
+            let field =
+                SortField::parse(name).ok_or_else(|| format!("unknown sort field: {}", name))?;
+            Some((field, descending))
+        }
+        None => None,
+    };
+
+    let matches = |value: &String, filter: &Option<String>| match filter {
+        Some(filter) => value == filter,
+        None => true,
+    };
+
+    // Фильтры по индексированным полям: заказы берутся из самого короткого индекса,
+    // остальные фильтры проверяются на них. Без таких фильтров просматривается весь кэш
+    let nm_id = query.nm_id.map(|nm_id| nm_id.to_string());
+    let indexed = [
+        (IndexField::CustomerId, &query.customer_id),
+        (IndexField::TrackNumber, &query.track_number),
+        (IndexField::NmId, &nm_id),
+        (IndexField::Rid, &query.rid),
+        (IndexField::Transaction, &query.transaction),
+    ];
+    let narrowest = indexed
+        .iter()
+        .filter_map(|(field, value)| Some((*field, value.as_deref()?)))
+        .min_by_key(|(field, value)| state.count(*field, value));
+    let candidates: Box<dyn Iterator<Item = &Arc<Order>>> = match narrowest {
+        Some((field, value)) => Box::new(state.find(field, value)),
+        None => Box::new(state.orders()),
+    };
+
+    // Фильтрация, заказы идут в порядке добавления
+    let mut orders: Vec<&Arc<Order>> = candidates
+        .filter(|order| {
+            matches(&order.customer_id, &query.customer_id)
+                && matches(&order.track_number, &query.track_number)
+                && matches(&order.payment.transaction, &query.transaction)
+                && query
+                    .nm_id
+                    .is_none_or(|nm_id| order.items.iter().any(|item| item.nm_id == nm_id))
+                && query
+                    .rid
+                    .as_ref()
+                    .is_none_or(|rid| order.items.iter().any(|item| &item.rid == rid))
+                && matches(&order.delivery_service, &query.delivery_service)
+                && matches(&order.locale, &query.locale)
+        })
+        .filter(|order| {
+            if date_from.is_none() && date_to.is_none() {
+                return true;
+            }
+            // заказы с некорректной датой не попадают в выборку по диапазону
+            match parse_date(&order.date_created) {
+                Some(date) => {
+                    date_from.is_none_or(|from| date >= from) && date_to.is_none_or(|to| date <= to)
+                }
+                None => false,
+            }
+        })
+        .collect();
+
+    // Порядок выдачи по ключу сортировки; равные ключи, как и заказы без сортировки,
+    // идут по orders.seq, т.е. в порядке добавления
+    let compare = |order: &Order, key: Option<&str>| match (sort, key) {
+        (Some((field, descending)), Some(key)) => {
+            let ordering = field.compare(field.key(order), key);
+            if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        }
+        _ => Ordering::Equal,
+    };
+    if let Some((field, _)) = sort {
+        // сортировка устойчивая, а кэш перебирается по возрастанию seq
+        orders.sort_by(|a, b| compare(a, Some(field.key(b))));
+    }
+
+    let total = orders.len();
+    let start = match &query.cursor {
+        Some(value) => {
+            let cursor = Cursor::decode(value)
+                .filter(|cursor| cursor.key.is_some() == sort.is_some())
+                .ok_or_else(|| format!("invalid cursor: {}", value))?;
+            if cursor.sort != query.sort {
+                return Err("'cursor' was issued for a different 'sort'".to_string());
+            }
+            // первый заказ после позиции курсора, даже если заказа курсора уже нет
+            orders.partition_point(|order| {
+                compare(order, cursor.key.as_deref()).then(order.seq.cmp(&cursor.seq))
+                    != Ordering::Greater
+            })
+        }
+        None => query.offset.unwrap_or(0).min(total),
+    };
+    let end = (start + limit).min(total);
+
+    let page: Vec<Arc<Order>> = orders[start..end]
+        .iter()
+        .map(|order| Arc::clone(order))
+        .collect();
+    let next_cursor = if end < total {
+        page.last().map(|order| {
+            Cursor {
+                sort: query.sort.clone(),
+                key: sort.map(|(field, _)| field.key(order).to_string()),
+                seq: order.seq,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+
+    Ok(OrdersPage {
+        orders: page,
+        total,
+        next_cursor,
+    })
+}
+
+// Тесты
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_module::tests::load_order;
+
+    // Кэш с пятью заказами, даты которых идут не по порядку добавления
+    fn test_state() -> AppState {
+        let mut state = AppState::new();
+        for file_path in [
+            "models/model1.json",
+            "models/model2.json",
+            "models/model3.json",
+            "models/model4.json",
+            "models/model5.json",
+        ] {
+            state.add_order(load_order(file_path));
+        }
+        state
+    }
+
+    fn uids(page: &OrdersPage) -> Vec<&str> {
+        page.orders
+            .iter()
+            .map(|order| order.order_uid.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_default_query_returns_insertion_order() {
+        let state = test_state();
+        let page = query_orders(&state, &OrdersQuery::default()).unwrap();
+
+        assert_eq!(page.total, 5);
+        assert_eq!(page.next_cursor, None);
+        assert_eq!(
+            uids(&page),
+            vec![
+                "b563feb7b2b84b6test",
+                "b563feb7b2b84b6test1",
+                "b563feb7b2b84b6test2",
+                "b563feb7b2b84b6test3",
+                "b563feb7b2b84b6test4",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_filters() {
+        let state = test_state();
+
+        let query = OrdersQuery {
+            track_number: Some("TRACKNUMBER2".to_string()),
+            ..Default::default()
+        };
+        let page = query_orders(&state, &query).unwrap();
+        assert_eq!(uids(&page), vec!["b563feb7b2b84b6test2"]);
+
+        let query = OrdersQuery {
+            customer_id: Some("customer3".to_string()),
+            locale: Some("en".to_string()),
+            ..Default::default()
+        };
+        let page = query_orders(&state, &query).unwrap();
+        assert_eq!(uids(&page), vec!["b563feb7b2b84b6test3"]);
+
+        let query = OrdersQuery {
+            locale: Some("en".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query_orders(&state, &query).unwrap().total, 5);
+
+        let query = OrdersQuery {
+            delivery_service: Some("unknown".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query_orders(&state, &query).unwrap().total, 0);
+
+        let query = OrdersQuery {
+            date_from: Some("2021-11-02T00:00:00Z".to_string()),
+            date_to: Some("2021-11-03T06:22:19Z".to_string()),
+            ..Default::default()
+        };
+        let page = query_orders(&state, &query).unwrap();
+        assert_eq!(
+            uids(&page),
+            vec!["b563feb7b2b84b6test2", "b563feb7b2b84b6test3"]
+        );
+    }
+
+    // Фильтры по товарам и оплате, в том числе вместе с другими индексированными полями
+    #[test]
+    fn test_indexed_filters() {
+        let mut state = test_state();
+        state.add_order(load_order("models/model_extended.json"));
+
+        let query = OrdersQuery {
+            nm_id: Some(2389212),
+            ..Default::default()
+        };
+        let page = query_orders(&state, &query).unwrap();
+        assert_eq!(
+            uids(&page),
+            vec!["b563feb7b2b84b6test", "b563feb7b2b84b6ext"]
+        );
+
+        let query = OrdersQuery {
+            rid: Some("RID345678".to_string()),
+            ..Default::default()
+        };
+        let page = query_orders(&state, &query).unwrap();
+        assert_eq!(uids(&page), vec!["b563feb7b2b84b6test3"]);
+
+        let query = OrdersQuery {
+            transaction: Some("b563feb7b2b84b6test".to_string()),
+            track_number: Some("WBILMTESTTRACK".to_string()),
+            limit: Some(1),
+            ..Default::default()
+        };
+        let page = query_orders(&state, &query).unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(uids(&page), vec!["b563feb7b2b84b6test"]);
+        assert!(page.next_cursor.is_some());
+
+        let query = OrdersQuery {
+            customer_id: Some("customer1".to_string()),
+            nm_id: Some(2389212),
+            ..Default::default()
+        };
+        assert_eq!(query_orders(&state, &query).unwrap().total, 0);
+    }
+
+    #[test]
+    fn test_sort_and_cursor_pagination() {
+        let state = test_state();
+
+        let mut query = OrdersQuery {
+            limit: Some(2),
+            sort: Some("-date_created".to_string()),
+            ..Default::default()
+        };
+        let first = query_orders(&state, &query).unwrap();
+        assert_eq!(first.total, 5);
+        assert_eq!(
+            uids(&first),
+            vec!["b563feb7b2b84b6test", "b563feb7b2b84b6test4"]
+        );
+
+        query.cursor = first.next_cursor;
+        let second = query_orders(&state, &query).unwrap();
+        assert_eq!(
+            uids(&second),
+            vec!["b563feb7b2b84b6test3", "b563feb7b2b84b6test2"]
+        );
+
+        query.cursor = second.next_cursor;
+        let last = query_orders(&state, &query).unwrap();
+        assert_eq!(uids(&last), vec!["b563feb7b2b84b6test1"]);
+        assert_eq!(last.next_cursor, None);
+
+        let query = OrdersQuery {
+            offset: Some(4),
+            sort: Some("order_uid".to_string()),
+            ..Default::default()
+        };
+        let page = query_orders(&state, &query).unwrap();
+        assert_eq!(uids(&page), vec!["b563feb7b2b84b6test4"]);
+    }
+
+    // Курсор остается верным, когда его заказ удален или вытеснен из кэша
+    #[test]
+    fn test_cursor_of_removed_order() {
+        let mut state = test_state();
+
+        let mut query = OrdersQuery {
+            limit: Some(2),
+            sort: Some("-date_created".to_string()),
+            ..Default::default()
+        };
+        let first = query_orders(&state, &query).unwrap();
+        let last = first.orders.last().unwrap();
+        assert!(state.remove_order(&last.order_uid, last.seq));
+
+        query.cursor = first.next_cursor;
+        let second = query_orders(&state, &query).unwrap();
+        assert_eq!(
+            uids(&second),
+            vec!["b563feb7b2b84b6test3", "b563feb7b2b84b6test2"]
+        );
+
+        // без сортировки продолжение идет по orders.seq
+        let mut query = OrdersQuery {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let first = query_orders(&state, &query).unwrap();
+        assert_eq!(
+            uids(&first),
+            vec!["b563feb7b2b84b6test", "b563feb7b2b84b6test1"]
+        );
+        let last = first.orders.last().unwrap();
+        assert!(state.remove_order(&last.order_uid, last.seq));
+
+        query.cursor = first.next_cursor;
+        let second = query_orders(&state, &query).unwrap();
+        assert_eq!(
+            uids(&second),
+            vec!["b563feb7b2b84b6test2", "b563feb7b2b84b6test3"]
+        );
+    }
+
+    #[test]
+    fn test_invalid_query() {
+        let state = test_state();
+        let query = OrdersQuery {
+            limit: Some(1),
+            ..Default::default()
+        };
+        let cursor = query_orders(&state, &query).unwrap().next_cursor.unwrap();
+
+        let invalid = [
+            OrdersQuery {
+                limit: Some(0),
+                ..Default::default()
+            },
+            OrdersQuery {
+                sort: Some("price".to_string()),
+                ..Default::default()
+            },
+            OrdersQuery {
+                cursor: Some("missing".to_string()),
+                ..Default::default()
+            },
+            OrdersQuery {
+                cursor: Some(cursor.clone()),
+                sort: Some("order_uid".to_string()),
+                ..Default::default()
+            },
+            OrdersQuery {
+                date_from: Some("yesterday".to_string()),
+                ..Default::default()
+            },
+            OrdersQuery {
+                cursor: Some("b563feb7b2b84b6test".to_string()),
+                offset: Some(1),
+                ..Default::default()
+            },
+        ];
+        for query in invalid {
+            assert!(query_orders(&state, &query).is_err());
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use crate::error_module::AppError;
+use crate::repository_module::OrderRepository;
+use axum::async_trait;
+use axum::body::{Body, Bytes};
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{FromRequest, Json};
+use axum::http::{header, HeaderValue, Request};
+use axum::response::{IntoResponse, Response};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+// Json-тело запроса вместе с исходным текстом. Разбор и ошибки те же, что у Json:
+// неверный Content-Type или синтаксис -> 400, несоответствие структуре -> 422
+pub struct RawJson<T> {
+    pub value: T,
+    pub raw: String,
+}
+
+#[async_trait]
+impl<T, S> FromRequest<S, Body> for RawJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = JsonRejection;
+
+    async fn from_request(req: Request<Body>, state: &S) -> Result<Self, Self::Rejection> {
+        let headers = req.headers().clone();
+        let bytes = Bytes::from_request(req, state).await?;
+        // тело разбирается стандартным Json, исходные байты остаются у нас
+        let mut json = Request::new(Body::from(bytes.clone()));
+        *json.headers_mut() = headers;
+        let Json(value) = Json::<T>::from_request(json, state).await?;
+        Ok(RawJson {
+            value,
+            // после успешного разбора тело — корректный UTF-8, текст не меняется
+            raw: String::from_utf8_lossy(&bytes).into_owned(),
+        })
+    }
+}
+
+// Параметры GET /order/:order_uid
+#[derive(Deserialize, Debug, Default)]
+pub struct OrderParams {
+    #[serde(default)]
+    pub raw: bool, // вернуть исходный документ вместо нормализованного заказа
+}
+
+// GET /order/:order_uid?raw=true — документ байт в байт, как он был получен. Исходные
+// документы в кэше не хранятся и всегда читаются из БД; у заказов, записанных раньше,
+// документа нет, для них возвращается нормализованный заказ
+pub async fn raw_order(
+    repo: &dyn OrderRepository,
+    order_uid: String,
+) -> Result<Response, AppError> {
+    let (raw, version) = match repo.raw_document(&order_uid).await? {
+        Some((Some(raw), version)) => (raw, version),
+        Some((None, version)) => match repo.get(&order_uid).await? {
+            Some(order) => (
+                serde_json::to_string(&order).expect("order is always serializable"),
+                version,
+            ),
+            None => return Err(AppError::NotFound(order_uid)),
+        },
+        None => return Err(AppError::NotFound(order_uid)),
+    };
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            ),
+            (
+                header::ETAG,
+                HeaderValue::from_str(&crate::update_module::etag(version))
+                    .expect("etag is a valid header value"),
+            ),
+        ],
+        raw,
+    )
+        .into_response())
+}
+
+// Тесты
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_module::{AppState, OrderCache};
+    use crate::postgres_module::PgRepository;
+    use crate::repository_module::Repository;
+    use crate::shutdown_module::Shutdown;
+    use crate::tests::setup_database;
+    use axum::http::StatusCode;
+    use axum::Router;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    const UID: &str = "raw_order_uid";
+
+    fn app(state: &Arc<OrderCache>, repo: &Repository) -> Router {
+        crate::app(state.clone(), repo.clone(), Shutdown::new())
+    }
+
+    async fn send(
+        app: Router,
+        method: &str,
+        uri: &str,
+        body: String,
+    ) -> (StatusCode, Option<String>, String) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let etag = response
+            .headers()
+            .get(header::ETAG)
+            .map(|value| value.to_str().unwrap().to_string());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, etag, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_raw_document() {
+        let (pool, _schema) = setup_database().await;
+        let repo: Repository = Arc::new(PgRepository::new(pool.clone()));
+        let state = Arc::new(OrderCache::new(AppState::new()));
+
+        // документ новой версии схемы: поле верхнего уровня и поле delivery, неизвестные Order
+        let mut document: Value =
+            serde_json::from_str(&std::fs::read_to_string("models/model1.json").unwrap()).unwrap();
+        document["order_uid"] = UID.into();
+        document["schema_version"] = 3.into();
+        document["delivery"]["passport"] = "4510 000000".into();
+        let raw = format!("{}\n", serde_json::to_string_pretty(&document).unwrap());
+
+        let (status, _, _) = send(app(&state, &repo), "POST", "/order", raw.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // исходный документ возвращается байт в байт
+        let uri = format!("/order/{}?raw=true", UID);
+        let (status, etag, body) = send(app(&state, &repo), "GET", &uri, String::new()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(etag.as_deref(), Some("\"1\""));
+        assert_eq!(body, raw);
+
+        // нормализованный заказ содержит неизвестные поля верхнего уровня
+        let (_, _, body) = send(
+            app(&state, &repo),
+            "GET",
+            &format!("/order/{}", UID),
+            String::new(),
+        )
+        .await;
+        let order: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(order["schema_version"], 3);
+        assert_eq!(order["delivery"].get("passport"), None);
+
+        // неизвестные поля переживают перезагрузку кэша, документ доступен в JSONB
+        let mut reloaded = AppState::new();
+        reloaded.load_orders(&*repo).await.unwrap();
+        assert_eq!(
+            reloaded.get_order(UID).unwrap().extras["schema_version"],
+            json!(3)
+        );
+        let passport: (Option<String>,) = sqlx::query_as(
+            "SELECT document->'delivery'->>'passport' FROM orders WHERE order_uid = $1",
+        )
+        .bind(UID)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(passport.0.as_deref(), Some("4510 000000"));
+
+        // PATCH применяется к исходному документу и не теряет неизвестные поля
+        let (status, etag, _) = send(
+            app(&state, &repo),
+            "PATCH",
+            &format!("/order/{}", UID),
+            json!({"delivery": {"city": "Kazan"}}).to_string(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(etag.as_deref(), Some("\"2\""));
+        let (_, etag, body) = send(app(&state, &repo), "GET", &uri, String::new()).await;
+        assert_eq!(etag.as_deref(), Some("\"2\""));
+        let patched: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(patched["delivery"]["city"], "Kazan");
+        assert_eq!(patched["delivery"]["passport"], "4510 000000");
+        assert_eq!(patched["schema_version"], 3);
+
+        // у заказа, записанного до появления документов, возвращается нормализованный заказ
+        sqlx::query("UPDATE orders SET raw_document = NULL WHERE order_uid = $1")
+            .bind(UID)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let (status, _, body) = send(app(&state, &repo), "GET", &uri, String::new()).await;
+        assert_eq!(status, StatusCode::OK);
+        let legacy: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(legacy["delivery"]["city"], "Kazan");
+        assert_eq!(legacy["schema_version"], 3);
+
+        let (status, _, _) = send(
+            app(&state, &repo),
+            "GET",
+            "/order/missing?raw=true",
+            String::new(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _, body) = send(
+            app(&state, &repo),
+            "GET",
+            &format!("/order/{}?raw=yes", UID),
+            String::new(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("invalid_query"));
+
+        // удаление персональных данных не оставляет неизвестных полей ни в документе,
+        // ни в extras: в них тоже могут быть персональные данные
+        sqlx::query("UPDATE orders SET raw_document = $2 WHERE order_uid = $1")
+            .bind(UID)
+            .bind(patched.to_string())
+            .execute(&pool)
+            .await
+            .unwrap();
+        let (status, _, _) = send(
+            app(&state, &repo),
+            "POST",
+            "/customer/test/erasure",
+            String::new(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, etag, body) = send(app(&state, &repo), "GET", &uri, String::new()).await;
+        assert_eq!(etag.as_deref(), Some("\"3\""));
+        let erased: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(erased["delivery"]["name"], crate::erasure_module::ERASED);
+        assert_eq!(erased["delivery"]["city"], "Kazan");
+        assert_eq!(erased["delivery"].get("passport"), None);
+        assert_eq!(erased.get("schema_version"), None);
+        assert!(!body.contains("4510 000000"));
+        let document: (Option<String>, Value) = sqlx::query_as(
+            "SELECT document->'delivery'->>'passport', extras FROM orders WHERE order_uid = $1",
+        )
+        .bind(UID)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(document, (None, json!({})));
+        assert_eq!(state.read().get_order(UID).unwrap().extras.len(), 0);
+    }
+}
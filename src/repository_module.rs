@@ -0,0 +1,393 @@
+use crate::db_module::Order;
+use crate::idempotency_module::SavedKey;
+use crate::update_module::IfMatch;
+use axum::async_trait;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use sqlx::migrate::MigrateError;
+use sqlx::Error;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Размер страницы при чтении всех заказов через list
+const STREAM_PAGE_SIZE: usize = 1000;
+
+// Хранилище заказов, общее для обработчиков
+pub type Repository = Arc<dyn OrderRepository>;
+
+// Результат записи заказа
+#[derive(Debug, PartialEq)]
+pub enum Saved {
+    Created(i64),      // заказа не было, он записан с начальной версией; порядковый номер
+    Updated(i64, i64), // заказ изменен: новая версия и порядковый номер
+    Mismatch(Option<i64>), // условие If-Match не выполнено, текущая версия (None — заказа нет)
+}
+
+// Результат записи нового заказа с ключом идемпотентности
+#[derive(Debug, PartialEq)]
+pub enum Keyed {
+    Inserted(i64),      // заказ и ключ записаны вместе; порядковый номер заказа
+    Exists,             // заказ с таким order_uid уже есть, ничего не записано
+    KeyTaken(SavedKey), // ключ уже занят другим запросом, ничего не записано
+}
+
+// Результат удаления заказа
+#[derive(Debug, PartialEq)]
+pub enum Removal {
+    Removed(i64), // порядковый номер удаленного заказа
+    Missing,
+    Mismatch(i64), // условие If-Match не выполнено, текущая версия
+}
+
+// Состояние пула подключений для /status и метрик
+pub struct PoolStatus {
+    pub size: u32,
+    pub idle: usize,
+    pub max_connections: u32,
+}
+
+// Хранение заказов: запись, чтение, изменение и удаление вместе с исходными документами,
+// хэшами содержимого и ключами идемпотентности. Обработчики работают только через этот
+// интерфейс, поэтому HTTP слой проверяется без БД на MemoryRepository
+#[async_trait]
+pub trait OrderRepository: Send + Sync {
+    // Запись нового заказа вместе с исходным документом, возвращает порядковый номер
+    // записанного заказа; Ok(None) — заказ с таким order_uid уже есть, ничего не записано
+    async fn insert(&self, order: &Order, raw: &str) -> Result<Option<i64>, Error>;
+
+    // Запись нового заказа вместе с ключом идемпотентности в одной транзакции: из двух
+    // одновременных запросов с одним ключом заказ записывает только один. Ключ, оставшийся
+    // от удаленного заказа, занимается заново
+    async fn insert_keyed(&self, order: &Order, raw: &str, key: &str) -> Result<Keyed, Error>;
+
+    // Запись пачки новых заказов; возвращает order_uid записанных и их порядковые номера.
+    // Заказы, которые уже есть в хранилище или повторяются в пачке, не записываются
+    async fn insert_batch(&self, orders: &[(&Order, &str)]) -> Result<HashMap<String, i64>, Error>;
+
+    // Заказ по order_uid вместе с текущей версией и порядковым номером
+    async fn get(&self, order_uid: &str) -> Result<Option<Order>, Error>;
+
+    // Страница заказов в порядке поступления
+    async fn list(&self, offset: usize, limit: usize) -> Result<Vec<Order>, Error>;
+
+    // Замена заказа целиком; если заказа нет и условия нет, он создается.
+    // Проверка условия и запись не разделяются параллельной записью
+    async fn update(
+        &self,
+        order: &Order,
+        raw: &str,
+        condition: Option<&IfMatch>,
+    ) -> Result<Saved, Error>;
+
+    // Удаление заказа вместе с ключами идемпотентности
+    async fn delete(&self, order_uid: &str, condition: Option<&IfMatch>) -> Result<Removal, Error>;
+
+    // Все заказы в порядке поступления, для загрузки кэша. По умолчанию читаются
+    // страницами через list; хранилища с потоковым чтением переопределяют метод
+    fn stream_all(&self) -> BoxStream<'_, Result<Order, Error>> {
+        stream::try_unfold(0, move |offset| async move {
+            let page = self.list(offset, STREAM_PAGE_SIZE).await?;
+            if page.is_empty() {
+                return Ok::<_, Error>(None);
+            }
+            let next = offset + page.len();
+            Ok(Some((stream::iter(page.into_iter().map(Ok)), next)))
+        })
+        .try_flatten()
+        .boxed()
+    }
+
+    // Исходный документ заказа и версия; Ok(None) — заказа нет, Some((None, _)) — заказ
+    // записан до появления исходных документов
+    async fn raw_document(&self, order_uid: &str) -> Result<Option<(Option<String>, i64)>, Error>;
+
+    // Сохраненный хэш содержимого; None — заказа нет или хэш еще не вычислен
+    async fn content_hash(&self, order_uid: &str) -> Result<Option<String>, Error>;
+
+    // Сохранение хэша у заказа, записанного без него
+    async fn backfill_hash(&self, order_uid: &str, content_hash: &str) -> Result<(), Error>;
+
+    // Замена персональных данных получателя значением erased во всех заказах покупателя.
+    // Возвращает order_uid и новую версию измененных заказов, по возрастанию order_uid
+    async fn erase_customer(
+        &self,
+        customer_id: &str,
+        erased: &str,
+    ) -> Result<Vec<(String, i64)>, Error>;
+
+    // Сохранение ключа для уже записанного заказа; возвращает запись, оставшуюся под ключом:
+    // при одновременных запросах с одним ключом остается первая
+    async fn save_key(
+        &self,
+        key: &str,
+        order_uid: &str,
+        content_hash: &str,
+    ) -> Result<SavedKey, Error>;
+
+    // Проверка доступности хранилища
+    async fn ping(&self) -> Result<(), Error>;
+
+    // Версии миграций, которые еще не применены
+    async fn pending_migrations(&self) -> Result<Vec<i64>, MigrateError>;
+
+    // Пул подключений, если хранилище его использует
+    fn pool_status(&self) -> Option<PoolStatus>;
+
+    // Закрытие подключений при остановке
+    async fn close(&self);
+}
+
+// Тесты
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_module::MemoryRepository;
+    use crate::postgres_module::PgRepository;
+    use crate::sqlite_module::tests::temp_database;
+    use crate::sqlite_module::SqliteRepository;
+    use crate::tests::setup_database;
+
+    fn model(path: &str) -> (Order, String) {
+        let raw = std::fs::read_to_string(path).unwrap();
+        (serde_json::from_str(&raw).unwrap(), raw)
+    }
+
+    fn uids(orders: &[Order]) -> Vec<&str> {
+        orders
+            .iter()
+            .map(|order| order.order_uid.as_str())
+            .collect()
+    }
+
+    // Одинаковое поведение всех реализаций
+    async fn check_repository(repo: &dyn OrderRepository) {
+        let (first, first_raw) = model("models/model1.json");
+        let (second, second_raw) = model("models/model2.json");
+        let (third, third_raw) = model("models/model3.json");
+
+        // запись и чтение
+        let first_seq = repo.insert(&first, &first_raw).await.unwrap().unwrap();
+        assert_eq!(repo.insert(&first, &first_raw).await.unwrap(), None);
+        let stored = repo.get(&first.order_uid).await.unwrap().unwrap();
+        assert_eq!(
+            serde_json::to_value(&stored).unwrap(),
+            serde_json::to_value(&first).unwrap()
+        );
+        assert_eq!(stored.version, 1);
+        assert_eq!(stored.seq, first_seq);
+        assert!(repo.get("missing").await.unwrap().is_none());
+        assert_eq!(
+            repo.raw_document(&first.order_uid).await.unwrap(),
+            Some((Some(first_raw.clone()), 1))
+        );
+        assert_eq!(
+            repo.content_hash(&first.order_uid).await.unwrap(),
+            Some(crate::idempotency_module::content_hash(&first))
+        );
+
+        // пачка: уже записанный заказ и повтор в пачке не записываются
+        let inserted = repo
+            .insert_batch(&[
+                (&first, &first_raw),
+                (&second, &second_raw),
+                (&third, &third_raw),
+                (&second, &second_raw),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(inserted.len(), 2);
+        let second_seq = inserted[&second.order_uid];
+        let third_seq = inserted[&third.order_uid];
+        assert!(first_seq < second_seq && second_seq < third_seq);
+
+        // порядок поступления
+        let all: Vec<Order> = repo.stream_all().try_collect().await.unwrap();
+        assert_eq!(
+            uids(&all),
+            [&first.order_uid, &second.order_uid, &third.order_uid]
+        );
+        let seqs: Vec<i64> = all.iter().map(|order| order.seq).collect();
+        assert_eq!(seqs, [first_seq, second_seq, third_seq]);
+        let page = repo.list(1, 5).await.unwrap();
+        assert_eq!(uids(&page), [&second.order_uid, &third.order_uid]);
+        assert!(repo.list(3, 5).await.unwrap().is_empty());
+
+        // изменение с условием и без
+        let mut changed = first.clone();
+        changed.delivery.city = "Kazan".to_string();
+        let stale = IfMatch::Tags(vec!["\"2\"".to_string()]);
+        assert_eq!(
+            repo.update(&changed, "{}", Some(&stale)).await.unwrap(),
+            Saved::Mismatch(Some(1))
+        );
+        assert_eq!(
+            repo.update(&changed, "{}", Some(&IfMatch::Any))
+                .await
+                .unwrap(),
+            Saved::Updated(2, first_seq)
+        );
+        let stored = repo.get(&first.order_uid).await.unwrap().unwrap();
+        assert_eq!(stored.delivery.city, "Kazan");
+        assert_eq!(stored.version, 2);
+        assert_eq!(
+            repo.raw_document(&first.order_uid).await.unwrap(),
+            Some((Some("{}".to_string()), 2))
+        );
+        let mut created = first.clone();
+        created.order_uid = "created".to_string();
+        assert_eq!(
+            repo.update(&created, "{}", Some(&IfMatch::Any))
+                .await
+                .unwrap(),
+            Saved::Mismatch(None)
+        );
+        let Saved::Created(created_seq) = repo.update(&created, "{}", None).await.unwrap() else {
+            panic!("order is not created");
+        };
+        assert!(created_seq > third_seq);
+
+        // ключи идемпотентности: остается первая запись
+        let saved = repo
+            .save_key("key", &first.order_uid, "hash-1")
+            .await
+            .unwrap();
+        assert_eq!(saved.order_uid, first.order_uid);
+        let saved = repo
+            .save_key("key", &second.order_uid, "hash-2")
+            .await
+            .unwrap();
+        assert_eq!(saved.order_uid, first.order_uid);
+        assert_eq!(saved.content_hash, "hash-1");
+
+        // заказ с ключом: занятый ключ и существующий заказ ничего не записывают
+        let mut keyed = third.clone();
+        keyed.order_uid = "keyed".to_string();
+        assert_eq!(
+            repo.insert_keyed(&keyed, &third_raw, "key").await.unwrap(),
+            Keyed::KeyTaken(saved)
+        );
+        assert!(repo.get(&keyed.order_uid).await.unwrap().is_none());
+        assert_eq!(
+            repo.insert_keyed(&third, &third_raw, "key-2")
+                .await
+                .unwrap(),
+            Keyed::Exists
+        );
+        // ключ, оставшийся без заказа, занимается заново
+        let orphan = repo.save_key("key-2", "missing", "hash").await.unwrap();
+        assert_eq!(orphan.order_uid, "missing");
+        let Keyed::Inserted(keyed_seq) = repo
+            .insert_keyed(&keyed, &third_raw, "key-2")
+            .await
+            .unwrap()
+        else {
+            panic!("keyed order is not inserted");
+        };
+        assert!(keyed_seq > created_seq);
+        assert_eq!(
+            repo.save_key("key-2", "other", "hash").await.unwrap(),
+            SavedKey {
+                order_uid: keyed.order_uid.clone(),
+                content_hash: crate::idempotency_module::content_hash(&keyed),
+            }
+        );
+        assert_eq!(
+            repo.delete(&keyed.order_uid, None).await.unwrap(),
+            Removal::Removed(keyed_seq)
+        );
+
+        // обезличивание покупателя; повторный запрос ничего не меняет
+        assert_eq!(
+            repo.erase_customer(&first.customer_id, "[x]")
+                .await
+                .unwrap(),
+            vec![(first.order_uid.clone(), 3), (created.order_uid.clone(), 2)]
+        );
+        assert!(repo
+            .erase_customer(&first.customer_id, "[x]")
+            .await
+            .unwrap()
+            .is_empty());
+        let stored = repo.get(&first.order_uid).await.unwrap().unwrap();
+        assert_eq!(stored.delivery.email, "[x]");
+        assert_eq!(stored.delivery.city, "Kazan");
+        // исходным документом становится обезличенный заказ
+        assert_eq!(
+            repo.raw_document(&first.order_uid).await.unwrap(),
+            Some((Some(serde_json::to_string(&stored).unwrap()), 3))
+        );
+        assert!(
+            repo.get(&second.order_uid)
+                .await
+                .unwrap()
+                .unwrap()
+                .delivery
+                .name
+                != "[x]"
+        );
+        assert_eq!(repo.content_hash(&first.order_uid).await.unwrap(), None);
+        repo.backfill_hash(&first.order_uid, "hash").await.unwrap();
+        assert_eq!(
+            repo.content_hash(&first.order_uid)
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("hash")
+        );
+
+        // удаление вместе с ключами
+        assert_eq!(
+            repo.delete(&first.order_uid, Some(&stale)).await.unwrap(),
+            Removal::Mismatch(3)
+        );
+        assert_eq!(
+            repo.delete(&first.order_uid, None).await.unwrap(),
+            Removal::Removed(first_seq)
+        );
+        assert_eq!(
+            repo.delete(&first.order_uid, None).await.unwrap(),
+            Removal::Missing
+        );
+        assert_eq!(
+            repo.save_key("key", &second.order_uid, "hash-2")
+                .await
+                .unwrap()
+                .order_uid,
+            second.order_uid
+        );
+        assert!(repo.raw_document(&first.order_uid).await.unwrap().is_none());
+        let all: Vec<Order> = repo.stream_all().try_collect().await.unwrap();
+        assert_eq!(
+            uids(&all),
+            [&second.order_uid, &third.order_uid, &created.order_uid]
+        );
+
+        repo.ping().await.unwrap();
+        assert!(repo.pending_migrations().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_memory_repository() {
+        let repo = MemoryRepository::new();
+        check_repository(&repo).await;
+        assert!(repo.pool_status().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_postgres_repository() {
+        let (pool, _schema) = setup_database().await;
+        let repo = PgRepository::new(pool);
+        check_repository(&repo).await;
+        assert!(repo.pool_status().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_repository() {
+        let (pool, dir) = temp_database("repository").await;
+        crate::db_module::run_migrations(&pool).await.unwrap();
+        let repo = SqliteRepository::new(pool);
+        check_repository(&repo).await;
+        assert!(repo.pool_status().is_some());
+        repo.close().await;
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
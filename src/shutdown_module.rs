@@ -0,0 +1,157 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+// Координация остановки сервиса: сигнал остановки и учет незавершенных записей заказов.
+// Клоны ссылаются на одно и то же состояние
+#[derive(Clone)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    requested: watch::Sender<bool>,
+    in_flight: watch::Sender<usize>, // количество незавершенных записей
+    drained: AtomicUsize,            // записи, завершившиеся после сигнала остановки
+}
+
+// Итог ожидания незавершенных записей
+#[derive(Debug, PartialEq)]
+pub struct DrainSummary {
+    pub drained: usize,   // дождались завершения
+    pub abandoned: usize, // не успели завершиться до истечения срока
+}
+
+// Незавершенная запись; пока значение существует, остановка ее ждет
+pub struct WriteGuard {
+    inner: Arc<Inner>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Shutdown {
+            inner: Arc::new(Inner {
+                requested: watch::channel(false).0,
+                in_flight: watch::channel(0).0,
+                drained: AtomicUsize::new(0),
+            }),
+        }
+    }
+    // Запрос остановки
+    pub fn trigger(&self) {
+        self.inner.requested.send_replace(true);
+    }
+    pub fn is_triggered(&self) -> bool {
+        *self.inner.requested.borrow()
+    }
+    // Завершается после запроса остановки
+    pub async fn triggered(&self) {
+        let mut requested = self.inner.requested.subscribe();
+        // отправитель хранится в self, поэтому ошибка закрытия канала невозможна
+        let _ = requested.wait_for(|requested| *requested).await;
+    }
+    // Регистрация записи, которую нужно дождаться при остановке
+    pub fn track(&self) -> WriteGuard {
+        self.inner.in_flight.send_modify(|count| *count += 1);
+        WriteGuard {
+            inner: self.inner.clone(),
+        }
+    }
+    // Ожидание завершения всех записей, но не дольше deadline
+    pub async fn drain(&self, deadline: Instant) -> DrainSummary {
+        let mut in_flight = self.inner.in_flight.subscribe();
+        let _ = tokio::time::timeout_at(deadline, in_flight.wait_for(|count| *count == 0)).await;
+        DrainSummary {
+            drained: self.inner.drained.load(Ordering::Relaxed),
+            abandoned: *self.inner.in_flight.borrow(),
+        }
+    }
+}
+
+impl Drop for WriteGuard {
+    fn drop(&mut self) {
+        if *self.inner.requested.borrow() {
+            self.inner.drained.fetch_add(1, Ordering::Relaxed);
+        }
+        self.inner.in_flight.send_modify(|count| *count -= 1);
+    }
+}
+
+// Ожидание SIGINT (Ctrl+C) или SIGTERM
+pub async fn wait_for_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for SIGINT");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+// Тесты
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // Остановка дожидается записи, начатой до сигнала
+    #[tokio::test]
+    async fn test_drain_waits_for_writes() {
+        let shutdown = Shutdown::new();
+        let write = shutdown.track();
+        let writer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            drop(write);
+        });
+
+        assert!(!shutdown.is_triggered());
+        shutdown.trigger();
+        shutdown.triggered().await;
+        let started = Instant::now();
+        let summary = shutdown.drain(started + Duration::from_secs(5)).await;
+
+        assert!(started.elapsed() >= Duration::from_millis(150));
+        assert_eq!(
+            summary,
+            DrainSummary {
+                drained: 1,
+                abandoned: 0
+            }
+        );
+        writer.await.unwrap();
+    }
+
+    // По истечении срока незавершенные записи попадают в итог
+    #[tokio::test]
+    async fn test_drain_deadline() {
+        let shutdown = Shutdown::new();
+        let _stuck = shutdown.track();
+        drop(shutdown.track()); // завершилась до сигнала и не учитывается
+
+        shutdown.trigger();
+        let summary = shutdown
+            .drain(Instant::now() + Duration::from_millis(100))
+            .await;
+        assert_eq!(
+            summary,
+            DrainSummary {
+                drained: 0,
+                abandoned: 1
+            }
+        );
+    }
+}
@@ -0,0 +1,706 @@
+use crate::db_module::{self, Item, ItemRow, Order, OrderRow};
+use crate::erasure_module::anonymize;
+use crate::idempotency_module::{content_hash, SavedKey};
+use crate::metrics_module::METRICS;
+use crate::repository_module::{Keyed, OrderRepository, PoolStatus, Removal, Saved};
+use crate::update_module::IfMatch;
+use axum::async_trait;
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+use sqlx::migrate::MigrateError;
+use sqlx::sqlite::{SqliteConnection, SqliteExecutor, SqlitePool};
+use sqlx::Error;
+use std::collections::HashMap;
+use tokio::sync::Mutex;
+
+// Хранилище заказов в файле SQLite: та же схема, что и в PostgreSQL (migrations_sqlite),
+// для локальной разработки и небольших установок без сервера БД.
+// SQLite допускает одного писателя, поэтому записи выполняются по очереди под write_lock:
+// проверка и изменение заказа не разделяются параллельной записью этого процесса
+pub struct SqliteRepository {
+    pool: SqlitePool,
+    write_lock: Mutex<()>,
+}
+
+impl SqliteRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        SqliteRepository {
+            pool,
+            write_lock: Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait]
+impl OrderRepository for SqliteRepository {
+    async fn insert(&self, order: &Order, raw: &str) -> Result<Option<i64>, Error> {
+        let _timer = METRICS.insert_order_duration.start_timer();
+        let _write = self.write_lock.lock().await;
+        let mut tx = self.pool.begin().await?;
+        if check_order_exists(&mut tx, &order.order_uid).await? {
+            return Ok(None);
+        }
+        let seq = insert_order_rows(&mut tx, order, raw).await?;
+        tx.commit().await?;
+        Ok(Some(seq))
+    }
+
+    async fn insert_keyed(&self, order: &Order, raw: &str, key: &str) -> Result<Keyed, Error> {
+        let _timer = METRICS.insert_order_duration.start_timer();
+        let _write = self.write_lock.lock().await;
+        let mut tx = self.pool.begin().await?;
+        if check_order_exists(&mut tx, &order.order_uid).await? {
+            return Ok(Keyed::Exists);
+        }
+        if let Some(saved) =
+            reserve_key(&mut tx, key, &order.order_uid, &content_hash(order)).await?
+        {
+            return Ok(Keyed::KeyTaken(saved));
+        }
+        let seq = insert_order_rows(&mut tx, order, raw).await?;
+        tx.commit().await?;
+        Ok(Keyed::Inserted(seq))
+    }
+
+    // Пачка записывается в одной транзакции построчными вставками: в SQLite они
+    // не требуют обращения к серверу и выполняются быстро
+    async fn insert_batch(&self, orders: &[(&Order, &str)]) -> Result<HashMap<String, i64>, Error> {
+        let _write = self.write_lock.lock().await;
+        let mut tx = self.pool.begin().await?;
+        let mut inserted = HashMap::new();
+        for (order, raw) in orders {
+            if inserted.contains_key(&order.order_uid)
+                || check_order_exists(&mut tx, &order.order_uid).await?
+            {
+                continue;
+            }
+            let seq = insert_order_rows(&mut tx, order, raw).await?;
+            inserted.insert(order.order_uid.clone(), seq);
+        }
+        tx.commit().await?;
+        Ok(inserted)
+    }
+
+    async fn get(&self, order_uid: &str) -> Result<Option<Order>, Error> {
+        let row: Option<OrderRow> = sqlx::query_as(FETCH_ORDER_QUERY)
+            .bind(order_uid)
+            .fetch_optional(&self.pool)
+            .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        Ok(with_items(&self.pool, vec![row]).await?.pop())
+    }
+
+    async fn list(&self, offset: usize, limit: usize) -> Result<Vec<Order>, Error> {
+        let rows: Vec<OrderRow> = sqlx::query_as(LIST_ORDERS_QUERY)
+            .bind(offset as i64)
+            .bind(limit as i64)
+            .fetch_all(&self.pool)
+            .await?;
+        with_items(&self.pool, rows).await
+    }
+
+    async fn update(
+        &self,
+        order: &Order,
+        raw: &str,
+        condition: Option<&IfMatch>,
+    ) -> Result<Saved, Error> {
+        let _write = self.write_lock.lock().await;
+        let mut tx = self.pool.begin().await?;
+        let current: Option<(i64, i64, i64, i64)> = sqlx::query_as(
+            "SELECT delivery_id, payment_id, version, seq FROM orders WHERE order_uid = ?1",
+        )
+        .bind(&order.order_uid)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some((delivery_id, payment_id, version, seq)) = current else {
+            if condition.is_some() {
+                return Ok(Saved::Mismatch(None));
+            }
+            let seq = insert_order_rows(&mut tx, order, raw).await?;
+            tx.commit().await?;
+            return Ok(Saved::Created(seq));
+        };
+        if condition.is_some_and(|condition| !condition.matches(version)) {
+            return Ok(Saved::Mismatch(Some(version)));
+        }
+
+        sqlx::query(
+            r#"
+            UPDATE delivery SET name = ?2, phone = ?3, zip = ?4, city = ?5, address = ?6,
+                region = ?7, email = ?8
+            WHERE id = ?1
+            "#,
+        )
+        .bind(delivery_id)
+        .bind(&order.delivery.name)
+        .bind(&order.delivery.phone)
+        .bind(&order.delivery.zip)
+        .bind(&order.delivery.city)
+        .bind(&order.delivery.address)
+        .bind(&order.delivery.region)
+        .bind(&order.delivery.email)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            UPDATE payment SET "transaction" = ?2, request_id = ?3, currency = ?4, provider = ?5,
+                amount = ?6, payment_dt = ?7, bank = ?8, delivery_cost = ?9, goods_total = ?10,
+                custom_fee = ?11
+            WHERE id = ?1
+            "#,
+        )
+        .bind(payment_id)
+        .bind(&order.payment.transaction)
+        .bind(&order.payment.request_id)
+        .bind(&order.payment.currency)
+        .bind(&order.payment.provider)
+        .bind(order.payment.amount)
+        .bind(order.payment.payment_dt)
+        .bind(&order.payment.bank)
+        .bind(order.payment.delivery_cost)
+        .bind(order.payment.goods_total)
+        .bind(order.payment.custom_fee)
+        .execute(&mut *tx)
+        .await?;
+
+        let version: i64 = sqlx::query_scalar(
+            r#"
+            UPDATE orders SET track_number = ?2, entry = ?3, locale = ?4, internal_signature = ?5,
+                customer_id = ?6, delivery_service = ?7, shardkey = ?8, sm_id = ?9,
+                date_created = ?10, oof_shard = ?11, content_hash = ?12, raw_document = ?13,
+                extras = ?14, version = version + 1
+            WHERE order_uid = ?1
+            RETURNING version
+            "#,
+        )
+        .bind(&order.order_uid)
+        .bind(&order.track_number)
+        .bind(&order.entry)
+        .bind(&order.locale)
+        .bind(&order.internal_signature)
+        .bind(&order.customer_id)
+        .bind(&order.delivery_service)
+        .bind(&order.shardkey)
+        .bind(order.sm_id)
+        .bind(&order.date_created)
+        .bind(&order.oof_shard)
+        .bind(content_hash(order))
+        .bind(raw)
+        .bind(sqlx::types::Json(&order.extras))
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query("DELETE FROM item WHERE order_uid = ?1")
+            .bind(&order.order_uid)
+            .execute(&mut *tx)
+            .await?;
+        insert_items(&mut tx, order).await?;
+
+        tx.commit().await?;
+        Ok(Saved::Updated(version, seq))
+    }
+
+    // Товары удаляются каскадно вместе с orders
+    async fn delete(&self, order_uid: &str, condition: Option<&IfMatch>) -> Result<Removal, Error> {
+        let _write = self.write_lock.lock().await;
+        let mut tx = self.pool.begin().await?;
+        let current: Option<(i64, i64, i64, i64)> = sqlx::query_as(
+            "SELECT delivery_id, payment_id, version, seq FROM orders WHERE order_uid = ?1",
+        )
+        .bind(order_uid)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some((delivery_id, payment_id, version, seq)) = current else {
+            return Ok(Removal::Missing);
+        };
+        if condition.is_some_and(|condition| !condition.matches(version)) {
+            return Ok(Removal::Mismatch(version));
+        }
+
+        sqlx::query("DELETE FROM orders WHERE order_uid = ?1")
+            .bind(order_uid)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM delivery WHERE id = ?1")
+            .bind(delivery_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM payment WHERE id = ?1")
+            .bind(payment_id)
+            .execute(&mut *tx)
+            .await?;
+        // повтор запроса с ключом удаленного заказа должен записать заказ заново
+        sqlx::query("DELETE FROM idempotency_keys WHERE order_uid = ?1")
+            .bind(order_uid)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(Removal::Removed(seq))
+    }
+
+    // Заказы читаются одним запросом потоком и обрабатываются пачками, как в PostgreSQL
+    fn stream_all(&self) -> BoxStream<'_, Result<Order, Error>> {
+        sqlx::query_as::<_, OrderRow>(LOAD_ORDERS_QUERY)
+            .fetch(&self.pool)
+            .try_chunks(LOAD_CHUNK_SIZE)
+            .map_err(|err| err.1)
+            .and_then(|chunk| with_items(&self.pool, chunk))
+            .map_ok(|orders| futures::stream::iter(orders.into_iter().map(Ok)))
+            .try_flatten()
+            .boxed()
+    }
+
+    async fn raw_document(&self, order_uid: &str) -> Result<Option<(Option<String>, i64)>, Error> {
+        sqlx::query_as("SELECT raw_document, version FROM orders WHERE order_uid = ?1")
+            .bind(order_uid)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn content_hash(&self, order_uid: &str) -> Result<Option<String>, Error> {
+        let stored: Option<Option<String>> =
+            sqlx::query_scalar("SELECT content_hash FROM orders WHERE order_uid = ?1")
+                .bind(order_uid)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(stored.flatten())
+    }
+
+    async fn backfill_hash(&self, order_uid: &str, content_hash: &str) -> Result<(), Error> {
+        let _write = self.write_lock.lock().await;
+        sqlx::query(
+            "UPDATE orders SET content_hash = ?2 WHERE order_uid = ?1 AND content_hash IS NULL",
+        )
+        .bind(order_uid)
+        .bind(content_hash)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // Обезличивание delivery и исходного документа и увеличение версии заказов в одной
+    // транзакции. Хэш содержимого сбрасывается и будет вычислен заново при следующем сравнении
+    async fn erase_customer(
+        &self,
+        customer_id: &str,
+        erased: &str,
+    ) -> Result<Vec<(String, i64)>, Error> {
+        let _write = self.write_lock.lock().await;
+        let mut tx = self.pool.begin().await?;
+        let rows: Vec<OrderRow> = sqlx::query_as(ERASE_ORDERS_QUERY)
+            .bind(customer_id)
+            .bind(erased)
+            .fetch_all(&mut *tx)
+            .await?;
+        let orders = with_items(&mut *tx, rows).await?;
+
+        // исходный документ заменяется обезличенным заказом
+        let mut changed = Vec::new();
+        for mut order in orders {
+            anonymize(&mut order, erased);
+            let raw = serde_json::to_string(&order).expect("order is serializable");
+            sqlx::query(
+                r#"
+                UPDATE delivery SET name = ?2, phone = ?2, address = ?2, email = ?2
+                WHERE id = (SELECT delivery_id FROM orders WHERE order_uid = ?1)
+                "#,
+            )
+            .bind(&order.order_uid)
+            .bind(erased)
+            .execute(&mut *tx)
+            .await?;
+            let version: i64 = sqlx::query_scalar(
+                r#"
+                UPDATE orders SET version = version + 1, content_hash = NULL,
+                    raw_document = ?2, extras = '{}'
+                WHERE order_uid = ?1
+                RETURNING version
+                "#,
+            )
+            .bind(&order.order_uid)
+            .bind(&raw)
+            .fetch_one(&mut *tx)
+            .await?;
+            changed.push((order.order_uid, version));
+        }
+        tx.commit().await?;
+        Ok(changed)
+    }
+
+    async fn save_key(
+        &self,
+        key: &str,
+        order_uid: &str,
+        content_hash: &str,
+    ) -> Result<SavedKey, Error> {
+        let _write = self.write_lock.lock().await;
+        let mut tx = self.pool.begin().await?;
+        let saved = reserve_key(&mut tx, key, order_uid, content_hash).await?;
+        tx.commit().await?;
+        Ok(saved.unwrap_or_else(|| SavedKey {
+            order_uid: order_uid.to_string(),
+            content_hash: content_hash.to_string(),
+        }))
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn pending_migrations(&self) -> Result<Vec<i64>, MigrateError> {
+        db_module::pending_migrations(&self.pool).await
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        Some(PoolStatus {
+            size: self.pool.size(),
+            idle: self.pool.num_idle(),
+            max_connections: self.pool.options().get_max_connections(),
+        })
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
+
+// Размер пачки заказов, товары которых загружаются одним запросом
+const LOAD_CHUNK_SIZE: usize = 1000;
+
+// Выборка заказов вместе с delivery и payment, общая часть запросов загрузки
+macro_rules! select_orders {
+    () => {
+        r#"
+        SELECT o.order_uid, o.track_number, o.entry, o.locale, o.internal_signature,
+               o.customer_id, o.delivery_service, o.shardkey, o.sm_id, o.date_created, o.oof_shard,
+               o.version, o.seq, o.extras,
+               d.name, d.phone, d.zip, d.city, d.address, d.region, d.email,
+               p."transaction", p.request_id, p.currency, p.provider, p.amount, p.payment_dt,
+               p.bank, p.delivery_cost, p.goods_total, p.custom_fee
+        FROM orders o
+        JOIN delivery d ON d.id = o.delivery_id
+        JOIN payment p ON p.id = o.payment_id
+        "#
+    };
+}
+
+// Все заказы в порядке поступления
+static LOAD_ORDERS_QUERY: &str = concat!(select_orders!(), "ORDER BY o.seq");
+
+// Страница заказов в порядке поступления
+static LIST_ORDERS_QUERY: &str = concat!(select_orders!(), "ORDER BY o.seq LIMIT ?2 OFFSET ?1");
+
+// Один заказ по order_uid
+static FETCH_ORDER_QUERY: &str = concat!(select_orders!(), "WHERE o.order_uid = ?1");
+
+// Заказы покупателя с необезличенными данными получателя
+static ERASE_ORDERS_QUERY: &str = concat!(
+    select_orders!(),
+    r#"WHERE o.customer_id = ?1
+        AND (d.name, d.phone, d.address, d.email) IS NOT (?2, ?2, ?2, ?2)
+    ORDER BY o.order_uid"#
+);
+
+// Товары для пачки заказов; order_uid передаются json-массивом
+static LOAD_ITEMS_QUERY: &str = r#"
+        SELECT order_uid, chrt_id, track_number, price, rid, name, sale, size,
+               total_price, nm_id, brand, status
+        FROM item
+        WHERE order_uid IN (SELECT value FROM json_each(?1))
+        ORDER BY id
+    "#;
+
+// Товары для пачки строк заказов одним запросом
+async fn with_items<'c>(
+    executor: impl SqliteExecutor<'c>,
+    rows: Vec<OrderRow>,
+) -> Result<Vec<Order>, Error> {
+    let uids: Vec<&str> = rows.iter().map(|row| row.order_uid.as_str()).collect();
+    let item_rows: Vec<ItemRow> = sqlx::query_as(LOAD_ITEMS_QUERY)
+        .bind(sqlx::types::Json(&uids))
+        .fetch_all(executor)
+        .await?;
+    let mut items: HashMap<String, Vec<Item>> = HashMap::new();
+    for row in item_rows {
+        items.entry(row.order_uid).or_default().push(row.item);
+    }
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let order_items = items.remove(&row.order_uid).unwrap_or_default();
+            row.into_order(order_items)
+        })
+        .collect())
+}
+
+async fn check_order_exists(conn: &mut SqliteConnection, uid: &str) -> Result<bool, Error> {
+    let found: Option<i64> = sqlx::query_scalar("SELECT 1 FROM orders WHERE order_uid = ?1")
+        .bind(uid)
+        .fetch_optional(conn)
+        .await?;
+    Ok(found.is_some())
+}
+
+// Запись ключа в транзакции вызывающего, если ключ свободен или его заказ удален;
+// Some — ключ занят другим заказом, он возвращается без изменений
+async fn reserve_key(
+    conn: &mut SqliteConnection,
+    key: &str,
+    order_uid: &str,
+    content_hash: &str,
+) -> Result<Option<SavedKey>, Error> {
+    let saved: Option<(String, String)> =
+        sqlx::query_as("SELECT order_uid, content_hash FROM idempotency_keys WHERE key = ?1")
+            .bind(key)
+            .fetch_optional(&mut *conn)
+            .await?;
+    if let Some((saved_uid, saved_hash)) = saved {
+        if check_order_exists(conn, &saved_uid).await? {
+            return Ok(Some(SavedKey {
+                order_uid: saved_uid,
+                content_hash: saved_hash,
+            }));
+        }
+    }
+    sqlx::query(
+        r#"
+        INSERT INTO idempotency_keys (key, order_uid, content_hash)
+        VALUES (?1, ?2, ?3)
+        ON CONFLICT (key) DO UPDATE SET order_uid = excluded.order_uid,
+            content_hash = excluded.content_hash, created_at = CURRENT_TIMESTAMP
+        "#,
+    )
+    .bind(key)
+    .bind(order_uid)
+    .bind(content_hash)
+    .execute(conn)
+    .await?;
+    Ok(None)
+}
+
+// Вставка заказа в delivery, payment, orders и item в рамках транзакции вызывающего;
+// raw — исходный документ заказа. Возвращает порядковый номер заказа
+async fn insert_order_rows(
+    conn: &mut SqliteConnection,
+    order: &Order,
+    raw: &str,
+) -> Result<i64, Error> {
+    let delivery_id: i64 = sqlx::query_scalar(
+        r#"
+        INSERT INTO delivery (name, phone, zip, city, address, region, email)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) RETURNING id
+        "#,
+    )
+    .bind(&order.delivery.name)
+    .bind(&order.delivery.phone)
+    .bind(&order.delivery.zip)
+    .bind(&order.delivery.city)
+    .bind(&order.delivery.address)
+    .bind(&order.delivery.region)
+    .bind(&order.delivery.email)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let payment_id: i64 = sqlx::query_scalar(
+        r#"
+        INSERT INTO payment ("transaction", request_id, currency, provider, amount, payment_dt,
+            bank, delivery_cost, goods_total, custom_fee)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10) RETURNING id
+        "#,
+    )
+    .bind(&order.payment.transaction)
+    .bind(&order.payment.request_id)
+    .bind(&order.payment.currency)
+    .bind(&order.payment.provider)
+    .bind(order.payment.amount)
+    .bind(order.payment.payment_dt)
+    .bind(&order.payment.bank)
+    .bind(order.payment.delivery_cost)
+    .bind(order.payment.goods_total)
+    .bind(order.payment.custom_fee)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let seq: i64 = sqlx::query_scalar(
+        r#"
+        INSERT INTO orders (order_uid, track_number, entry, delivery_id, payment_id, locale,
+            internal_signature, customer_id, delivery_service, shardkey, sm_id, date_created,
+            oof_shard, content_hash, raw_document, extras)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)
+        RETURNING seq
+        "#,
+    )
+    .bind(&order.order_uid)
+    .bind(&order.track_number)
+    .bind(&order.entry)
+    .bind(delivery_id)
+    .bind(payment_id)
+    .bind(&order.locale)
+    .bind(&order.internal_signature)
+    .bind(&order.customer_id)
+    .bind(&order.delivery_service)
+    .bind(&order.shardkey)
+    .bind(order.sm_id)
+    .bind(&order.date_created)
+    .bind(&order.oof_shard)
+    .bind(content_hash(order))
+    .bind(raw)
+    .bind(sqlx::types::Json(&order.extras))
+    .fetch_one(&mut *conn)
+    .await?;
+
+    insert_items(conn, order).await?;
+    Ok(seq)
+}
+
+// Вставка товаров заказа
+async fn insert_items(conn: &mut SqliteConnection, order: &Order) -> Result<(), Error> {
+    for item in &order.items {
+        sqlx::query(
+            r#"
+            INSERT INTO item (chrt_id, track_number, price, rid, name, sale, size, total_price,
+                nm_id, brand, status, order_uid)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+            "#,
+        )
+        .bind(item.chrt_id)
+        .bind(&item.track_number)
+        .bind(item.price)
+        .bind(&item.rid)
+        .bind(&item.name)
+        .bind(item.sale)
+        .bind(&item.size)
+        .bind(item.total_price)
+        .bind(item.nm_id)
+        .bind(&item.brand)
+        .bind(item.status)
+        .bind(&order.order_uid)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+// Тесты
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode};
+    use std::path::PathBuf;
+
+    // Отдельный файл БД во временном каталоге теста; каталог удаляется вызывающим
+    pub(crate) async fn temp_database(name: &str) -> (SqlitePool, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("orders-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let options = SqliteConnectOptions::new()
+            .filename(dir.join("orders.db"))
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal);
+        let pool = SqlitePool::connect_with(options).await.unwrap();
+        (pool, dir)
+    }
+
+    async fn tables(pool: &SqlitePool) -> Vec<String> {
+        sqlx::query_scalar(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE '\\_%' ESCAPE '\\' AND name != 'sqlite_sequence' ORDER BY name",
+        )
+        .fetch_all(pool)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_migrations_up_and_down() {
+        let (pool, dir) = temp_database("migrations").await;
+
+        db_module::run_migrations(&pool).await.unwrap();
+        assert_eq!(
+            tables(&pool).await,
+            ["delivery", "idempotency_keys", "item", "orders", "payment"]
+        );
+        assert!(db_module::pending_migrations(&pool)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Повторный запуск ничего не меняет, откат удаляет все таблицы
+        db_module::run_migrations(&pool).await.unwrap();
+        db_module::revert_migrations(&pool, 0).await.unwrap();
+        assert!(tables(&pool).await.is_empty());
+        assert!(db_module::applied_migrations(&pool)
+            .await
+            .unwrap()
+            .is_empty());
+
+        pool.close().await;
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    async fn count(pool: &SqlitePool, query: &str, value: &str) -> i64 {
+        sqlx::query_scalar(query)
+            .bind(value)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    // Ограничения длины строк, как у VARCHAR(n) в PostgreSQL, откатывают запись заказа целиком
+    #[tokio::test]
+    async fn test_insert_order_rollback() {
+        let (pool, dir) = temp_database("rollback").await;
+        db_module::run_migrations(&pool).await.unwrap();
+        let repo = SqliteRepository::new(pool.clone());
+
+        let raw = std::fs::read_to_string("models/model_extended.json").unwrap();
+        let mut order: Order = serde_json::from_str(&raw).unwrap();
+        order.order_uid = "rollback_test_uid".to_string();
+        order.delivery.name = "Rollback Testov".to_string();
+        order.payment.transaction = "rollback_test_transaction".to_string();
+        // второй товар не помещается в size (не более 50 символов),
+        // поэтому вставка падает уже после записи delivery, payment и orders
+        order.items[1].size = "x".repeat(51);
+
+        let raw = serde_json::to_string(&order).unwrap();
+        assert!(repo.insert(&order, &raw).await.is_err());
+
+        let uid = order.order_uid.as_str();
+        let counts = [
+            count(
+                &pool,
+                "SELECT COUNT(*) FROM orders WHERE order_uid = ?1",
+                uid,
+            )
+            .await,
+            count(&pool, "SELECT COUNT(*) FROM item WHERE order_uid = ?1", uid).await,
+            count(
+                &pool,
+                "SELECT COUNT(*) FROM delivery WHERE name = ?1",
+                &order.delivery.name,
+            )
+            .await,
+            count(
+                &pool,
+                r#"SELECT COUNT(*) FROM payment WHERE "transaction" = ?1"#,
+                &order.payment.transaction,
+            )
+            .await,
+        ];
+        assert_eq!(counts, [0, 0, 0, 0]);
+
+        // после отката тот же заказ с корректными данными успешно записывается
+        order.items[1].size = "0".to_string();
+        let raw = serde_json::to_string(&order).unwrap();
+        assert!(repo.insert(&order, &raw).await.unwrap().is_some());
+
+        pool.close().await;
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,348 @@
+use crate::db_module::{AppState, IndexField, Order, OrderCache};
+use axum::extract::Query;
+use axum::response::Html;
+use chrono::{TimeZone, Utc};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::sync::Arc;
+
+// Сколько найденных заказов выводится списком
+const MAX_RESULTS: usize = 50;
+
+// Параметры GET /ui
+#[derive(Deserialize, Debug, Default)]
+pub struct SearchParams {
+    #[serde(default)]
+    pub q: String, // order_uid, track_number или customer_id
+}
+
+// GET /ui?q=... — страница поиска заказа для операторов. Заказ ищется в кэше по order_uid,
+// затем по track_number и customer_id; один найденный заказ выводится целиком, несколько —
+// списком со ссылками. Стили встроены в страницу, внешние ресурсы не загружаются
+pub async fn search_page(
+    state: Arc<OrderCache>,
+    params: Option<Query<SearchParams>>,
+) -> Html<String> {
+    let Query(params) = params.unwrap_or_default();
+    let query = params.q.trim();
+    let mut body = search_form(query);
+    if !query.is_empty() {
+        let snapshot = state.read();
+        let (found, total) = search(&snapshot, query);
+        match found.as_slice() {
+            [] => {
+                let _ = write!(
+                    body,
+                    "<p class=\"empty\">No orders found for <b>{}</b></p>",
+                    escape(query)
+                );
+            }
+            [order] => render_order(&mut body, order),
+            orders => render_list(&mut body, orders, total),
+        }
+    }
+    let title = if query.is_empty() {
+        "Order lookup".to_string()
+    } else {
+        format!("{} — Order lookup", escape(query))
+    };
+    Html(page(&title, &body))
+}
+
+// Заказы, найденные в кэше: точное совпадение order_uid, иначе заказы с таким track_number,
+// затем с таким customer_id (по индексам кэша), не больше MAX_RESULTS. Второе значение —
+// сколько заказов найдено всего
+fn search(state: &AppState, query: &str) -> (Vec<Arc<Order>>, usize) {
+    if let Some(order) = state.get_order(query) {
+        return (vec![order], 1);
+    }
+    let mut seen = HashSet::new();
+    let mut found = Vec::new();
+    let matches = state
+        .find(IndexField::TrackNumber, query)
+        .chain(state.find(IndexField::CustomerId, query));
+    for order in matches {
+        if seen.insert(order.order_uid.as_str()) && found.len() < MAX_RESULTS {
+            found.push(order.clone());
+        }
+    }
+    (found, seen.len())
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<style>
+body {{ font-family: system-ui, sans-serif; margin: 2rem auto; max-width: 1100px; padding: 0 1rem; color: #222; }}
+form {{ display: flex; gap: .5rem; margin-bottom: 1.5rem; }}
+input[type=search] {{ flex: 1; padding: .5rem; font-size: 1rem; }}
+button {{ padding: .5rem 1rem; font-size: 1rem; }}
+table {{ border-collapse: collapse; width: 100%; margin-bottom: 1.5rem; }}
+th, td {{ border: 1px solid #ddd; padding: .35rem .6rem; text-align: left; vertical-align: top; }}
+th {{ background: #f4f4f4; font-weight: 600; }}
+table.fields th {{ width: 14rem; }}
+td.num {{ text-align: right; font-variant-numeric: tabular-nums; }}
+h2 {{ margin-top: 2rem; font-size: 1.2rem; }}
+pre {{ background: #f4f4f4; padding: .75rem; overflow-x: auto; }}
+.empty, .hint {{ color: #666; }}
+</style>
+</head>
+<body>
+<h1>Order lookup</h1>
+{body}
+</body>
+</html>
+"#
+    )
+}
+
+fn search_form(query: &str) -> String {
+    format!(
+        "<form method=\"get\" action=\"/ui\">\
+         <input type=\"search\" name=\"q\" value=\"{}\" \
+         placeholder=\"order_uid, track number or customer id\" autofocus>\
+         <button type=\"submit\">Search</button></form>",
+        escape(query)
+    )
+}
+
+// Заказ целиком: основные поля, доставка, оплата и товары
+fn render_order(out: &mut String, order: &Order) {
+    let _ = write!(out, "<h2>Order {}</h2>", escape(&order.order_uid));
+    fields(
+        out,
+        &[
+            ("order_uid", order.order_uid.clone()),
+            ("track_number", order.track_number.clone()),
+            ("entry", order.entry.clone()),
+            ("customer_id", order.customer_id.clone()),
+            ("date_created", order.date_created.clone()),
+            ("locale", order.locale.clone()),
+            ("delivery_service", order.delivery_service.clone()),
+            ("shardkey", order.shardkey.clone()),
+            ("sm_id", order.sm_id.to_string()),
+            ("oof_shard", order.oof_shard.clone()),
+            ("internal_signature", order.internal_signature.clone()),
+            ("version", order.version.to_string()),
+        ],
+    );
+
+    let delivery = &order.delivery;
+    out.push_str("<h2>Delivery</h2>");
+    fields(
+        out,
+        &[
+            ("name", delivery.name.clone()),
+            ("phone", delivery.phone.clone()),
+            ("email", delivery.email.clone()),
+            ("zip", delivery.zip.clone()),
+            ("region", delivery.region.clone()),
+            ("city", delivery.city.clone()),
+            ("address", delivery.address.clone()),
+        ],
+    );
+
+    let payment = &order.payment;
+    // payment_dt — unix-время в секундах
+    let payment_dt = match Utc.timestamp_opt(payment.payment_dt, 0).single() {
+        Some(time) => format!("{} ({})", payment.payment_dt, time.to_rfc3339()),
+        None => payment.payment_dt.to_string(),
+    };
+    out.push_str("<h2>Payment</h2>");
+    fields(
+        out,
+        &[
+            ("transaction", payment.transaction.clone()),
+            ("request_id", payment.request_id.clone()),
+            ("provider", payment.provider.clone()),
+            ("bank", payment.bank.clone()),
+            ("currency", payment.currency.clone()),
+            ("amount", payment.amount.to_string()),
+            ("goods_total", payment.goods_total.to_string()),
+            ("delivery_cost", payment.delivery_cost.to_string()),
+            ("custom_fee", payment.custom_fee.to_string()),
+            ("payment_dt", payment_dt),
+        ],
+    );
+
+    let _ = write!(out, "<h2>Items ({})</h2>", order.items.len());
+    out.push_str(
+        "<table><tr><th>chrt_id</th><th>nm_id</th><th>name</th><th>brand</th><th>size</th>\
+         <th>price</th><th>sale, %</th><th>total_price</th><th>rid</th><th>track_number</th>\
+         <th>status</th></tr>",
+    );
+    for item in &order.items {
+        let _ = write!(
+            out,
+            "<tr><td class=\"num\">{}</td><td class=\"num\">{}</td><td>{}</td><td>{}</td>\
+             <td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td>\
+             <td class=\"num\">{}</td><td>{}</td><td>{}</td><td class=\"num\">{}</td></tr>",
+            item.chrt_id,
+            item.nm_id,
+            escape(&item.name),
+            escape(&item.brand),
+            escape(&item.size),
+            item.price,
+            item.sale,
+            item.total_price,
+            escape(&item.rid),
+            escape(&item.track_number),
+            item.status,
+        );
+    }
+    out.push_str("</table>");
+
+    // поля, неизвестные схеме, выводятся как есть
+    if !order.extras.is_empty() {
+        let extras = serde_json::to_string_pretty(&order.extras).expect("extras are json");
+        let _ = write!(out, "<h2>Other fields</h2><pre>{}</pre>", escape(&extras));
+    }
+}
+
+// Таблица "поле — значение"
+fn fields(out: &mut String, rows: &[(&str, String)]) {
+    out.push_str("<table class=\"fields\">");
+    for (name, value) in rows {
+        let _ = write!(out, "<tr><th>{}</th><td>{}</td></tr>", name, escape(value));
+    }
+    out.push_str("</table>");
+}
+
+// Список найденных заказов со ссылками на каждый
+fn render_list(out: &mut String, orders: &[Arc<Order>], total: usize) {
+    if total > orders.len() {
+        let _ = write!(
+            out,
+            "<p class=\"hint\">Found {} orders, showing the first {}</p>",
+            total,
+            orders.len()
+        );
+    } else {
+        let _ = write!(out, "<p class=\"hint\">Found {} orders</p>", total);
+    }
+    out.push_str(
+        "<table><tr><th>order_uid</th><th>track_number</th><th>customer_id</th>\
+         <th>date_created</th><th>amount</th><th>items</th></tr>",
+    );
+    for order in orders {
+        let _ = write!(
+            out,
+            "<tr><td><a href=\"/ui?q={}\">{}</a></td><td>{}</td><td>{}</td><td>{}</td>\
+             <td class=\"num\">{} {}</td><td class=\"num\">{}</td></tr>",
+            encode_query(&order.order_uid),
+            escape(&order.order_uid),
+            escape(&order.track_number),
+            escape(&order.customer_id),
+            escape(&order.date_created),
+            order.payment.amount,
+            escape(&order.payment.currency),
+            order.items.len(),
+        );
+    }
+    out.push_str("</table>");
+}
+
+// Экранирование текста для вставки в HTML, в том числе в значения атрибутов
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Кодирование значения параметра запроса в ссылке
+fn encode_query(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            byte => {
+                let _ = write!(encoded, "%{:02X}", byte);
+            }
+        }
+    }
+    encoded
+}
+
+// Тесты
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_module::tests::load_order;
+
+    async fn lookup(state: &Arc<OrderCache>, q: &str) -> String {
+        let params = SearchParams { q: q.to_string() };
+        search_page(state.clone(), Some(Query(params))).await.0
+    }
+
+    #[tokio::test]
+    async fn test_search_page() {
+        let mut state = AppState::new();
+        state.add_order(load_order("models/model1.json"));
+        state.add_order(load_order("models/model_extended.json"));
+        state.add_order(load_order("models/model2.json"));
+        let state = Arc::new(OrderCache::new(state));
+
+        // пустой запрос — только форма поиска
+        let page = search_page(state.clone(), None).await.0;
+        assert!(page.contains("<form"));
+        assert!(!page.contains("<h2>"));
+        // внешние ресурсы не подключаются
+        assert!(!page.contains("http://") && !page.contains("https://"));
+
+        // заказ по order_uid выводится целиком
+        let page = lookup(&state, "b563feb7b2b84b6test1").await;
+        assert!(page.contains("<h2>Order b563feb7b2b84b6test1</h2>"));
+        assert!(page.contains("<h2>Delivery</h2>"));
+        assert!(page.contains("<h2>Payment</h2>"));
+        assert!(page.contains("<h2>Items (1)</h2>"));
+
+        // по track_number найдены два заказа, выводится список со ссылками
+        let page = lookup(&state, " WBILMTESTTRACK ").await;
+        assert!(page.contains("Found 2 orders"));
+        assert!(page.contains("<a href=\"/ui?q=b563feb7b2b84b6test\">"));
+        assert!(page.contains("<a href=\"/ui?q=b563feb7b2b84b6ext\">"));
+
+        let page = lookup(&state, "missing").await;
+        assert!(page.contains("No orders found for <b>missing</b>"));
+    }
+
+    #[tokio::test]
+    async fn test_values_are_escaped() {
+        let mut order = load_order("models/model1.json");
+        order.order_uid = "a&b c".to_string();
+        order.customer_id = "<script>".to_string();
+        order.delivery.name = "\"Name\"".to_string();
+        let mut state = AppState::new();
+        state.add_order(order);
+        let state = Arc::new(OrderCache::new(state));
+
+        let page = lookup(&state, "a&b c").await;
+        assert!(page.contains("<h2>Order a&amp;b c</h2>"));
+        assert!(page.contains("&quot;Name&quot;"));
+        assert!(page.contains("value=\"a&amp;b c\""));
+        assert!(!page.contains("<script>"));
+
+        let page = lookup(&state, "<script>").await;
+        assert!(page.contains("&lt;script&gt;"));
+        assert!(!page.contains("<script>"));
+
+        assert_eq!(encode_query("a&b c/é"), "a%26b%20c%2F%C3%A9");
+    }
+}
@@ -0,0 +1,429 @@
+use crate::db_module::{self, Order, OrderCache};
+use crate::error_module::AppError;
+use crate::metrics_module::{METRICS, SOURCE_HTTP};
+use crate::raw_module::RawJson;
+use crate::repository_module::{OrderRepository, Removal, Repository, Saved};
+use crate::shutdown_module::Shutdown;
+use crate::{logging_module, validation_module};
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{Json, Path};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde_json::Value;
+use std::sync::Arc;
+
+// Условие заголовка If-Match
+#[derive(Debug, PartialEq)]
+pub enum IfMatch {
+    Any,               // "*" — заказ должен существовать
+    Tags(Vec<String>), // текущий ETag заказа должен совпасть с одним из перечисленных
+}
+
+impl IfMatch {
+    pub fn matches(&self, version: i64) -> bool {
+        match self {
+            IfMatch::Any => true,
+            IfMatch::Tags(tags) => tags.contains(&etag(version)),
+        }
+    }
+}
+
+// ETag заказа — его версия в кавычках
+pub fn etag(version: i64) -> String {
+    format!("\"{}\"", version)
+}
+
+// Разбор If-Match: "*" или список ETag через запятую; слабые ETag (W/) не совпадают никогда
+pub fn if_match(headers: &HeaderMap) -> Option<IfMatch> {
+    let value = headers.get(header::IF_MATCH)?.to_str().unwrap_or_default();
+    if value.trim() == "*" {
+        return Some(IfMatch::Any);
+    }
+    Some(IfMatch::Tags(
+        value.split(',').map(|tag| tag.trim().to_string()).collect(),
+    ))
+}
+
+// Применение JSON Merge Patch (RFC 7386): объекты объединяются рекурсивно, null удаляет
+// поле, любое другое значение (в том числе массив) заменяет исходное целиком
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(serde_json::Map::new());
+    }
+    let target = target.as_object_mut().expect("target is an object");
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+// PUT /order/:order_uid — замена заказа целиком; если заказа нет, он создается (201).
+// С If-Match заказ изменяется, только если его версия совпадает
+pub async fn put_order(
+    state: Arc<OrderCache>,
+    Path(order_uid): Path<String>,
+    headers: HeaderMap,
+    payload: Result<RawJson<Order>, JsonRejection>,
+    repo: Repository,
+    shutdown: Shutdown,
+) -> Result<Response, AppError> {
+    let _write = shutdown.track();
+    logging_module::record_order_uid(&order_uid);
+    let RawJson { value: order, raw } = payload?;
+    if order.order_uid != order_uid {
+        return Err(AppError::InvalidOrder(format!(
+            "order_uid '{}' in the body does not match the path",
+            order.order_uid
+        )));
+    }
+    save(&state, &*repo, order, raw, if_match(&headers)).await
+}
+
+// PATCH /order/:order_uid — частичное изменение заказа в формате JSON Merge Patch.
+// Без If-Match изменение применяется к прочитанной версии: параллельная запись не теряется.
+// Патч применяется к исходному документу, поэтому поля, неизвестные схеме, сохраняются
+pub async fn patch_order(
+    state: Arc<OrderCache>,
+    Path(order_uid): Path<String>,
+    headers: HeaderMap,
+    patch: Result<Json<Value>, JsonRejection>,
+    repo: Repository,
+    shutdown: Shutdown,
+) -> Result<Response, AppError> {
+    let _write = shutdown.track();
+    logging_module::record_order_uid(&order_uid);
+    let Json(patch) = patch?;
+
+    // исходный документ читается из хранилища: в кэше может быть устаревшая копия
+    let (raw, version) = repo
+        .raw_document(&order_uid)
+        .await?
+        .ok_or_else(|| AppError::NotFound(order_uid.clone()))?;
+    let mut document = match raw {
+        Some(raw) => {
+            serde_json::from_str(&raw).map_err(|err| AppError::Internal(err.to_string()))?
+        }
+        // заказ записан до появления исходных документов
+        None => {
+            let current = repo
+                .get(&order_uid)
+                .await?
+                .ok_or_else(|| AppError::NotFound(order_uid.clone()))?;
+            serde_json::to_value(&current).expect("order is always serializable")
+        }
+    };
+    merge_patch(&mut document, &patch);
+    let raw = document.to_string();
+    let order: Order =
+        serde_json::from_value(document).map_err(|err| AppError::InvalidOrder(err.to_string()))?;
+    if order.order_uid != order_uid {
+        return Err(AppError::InvalidOrder(
+            "order_uid cannot be changed".to_string(),
+        ));
+    }
+
+    let condition = if_match(&headers).unwrap_or_else(|| IfMatch::Tags(vec![etag(version)]));
+    save(&state, &*repo, order, raw, Some(condition)).await
+}
+
+// DELETE /order/:order_uid — удаление заказа вместе с delivery, payment, товарами
+// и ключами идемпотентности; с If-Match заказ удаляется, только если его версия совпадает
+pub async fn delete_order(
+    state: Arc<OrderCache>,
+    Path(order_uid): Path<String>,
+    headers: HeaderMap,
+    repo: Repository,
+    shutdown: Shutdown,
+) -> Result<StatusCode, AppError> {
+    let _write = shutdown.track();
+    logging_module::record_order_uid(&order_uid);
+    let seq = match repo.delete(&order_uid, if_match(&headers).as_ref()).await? {
+        Removal::Removed(seq) => seq,
+        Removal::Missing => return Err(AppError::NotFound(order_uid)),
+        Removal::Mismatch(version) => {
+            return Err(AppError::VersionMismatch(order_uid, Some(version)));
+        }
+    };
+    tracing::info!("order deleted");
+    state.write(|cache| cache.remove_order(&order_uid, seq));
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Проверка, запись в хранилище и, после коммита, обновление кэша
+async fn save(
+    state: &Arc<OrderCache>,
+    repo: &dyn OrderRepository,
+    mut order: Order,
+    raw: String,
+    condition: Option<IfMatch>,
+) -> Result<Response, AppError> {
+    validation_module::validate_order(&order).map_err(AppError::Validation)?;
+
+    let (status, version, seq) = match repo.update(&order, &raw, condition.as_ref()).await? {
+        Saved::Created(seq) => (StatusCode::CREATED, db_module::initial_version(), seq),
+        Saved::Updated(version, seq) => (StatusCode::OK, version, seq),
+        Saved::Mismatch(current) => {
+            return Err(AppError::VersionMismatch(order.order_uid, current));
+        }
+    };
+    tracing::info!(version, "order saved");
+    if status == StatusCode::CREATED {
+        METRICS.order_accepted(SOURCE_HTTP);
+        state.record_ingestion();
+    }
+
+    order.version = version;
+    order.seq = seq;
+    // ответ содержит записанный заказ, даже если в кэше его уже сменила более новая версия
+    let order = Arc::new(order);
+    state.write(|cache| cache.add_order(order.clone()));
+    Ok((status, [(header::ETAG, etag(version))], Json(order)).into_response())
+}
+
+// Тесты
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_module::AppState;
+    use crate::memory_module::MemoryRepository;
+    use crate::postgres_module::PgRepository;
+    use crate::tests::setup_database;
+    use axum::http::HeaderValue;
+    use serde_json::json;
+
+    const UID: &str = "b563feb7b2b84b6test";
+
+    async fn body_json(response: Response) -> (StatusCode, Option<String>, Value) {
+        let status = response.status();
+        let etag = response
+            .headers()
+            .get(header::ETAG)
+            .map(|value| value.to_str().unwrap().to_string());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, etag, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn headers(if_match: Option<&'static str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(value) = if_match {
+            headers.insert(header::IF_MATCH, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    fn model1() -> Value {
+        serde_json::from_str(&std::fs::read_to_string("models/model1.json").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        // примеры из RFC 7386
+        let mut target = json!({"a": "b", "c": {"d": "e", "f": "g"}, "list": [1, 2]});
+        merge_patch(
+            &mut target,
+            &json!({"a": "z", "c": {"f": null}, "list": [3], "new": {"x": 1}}),
+        );
+        assert_eq!(
+            target,
+            json!({"a": "z", "c": {"d": "e"}, "list": [3], "new": {"x": 1}})
+        );
+
+        let mut target = json!({"a": "b"});
+        merge_patch(&mut target, &json!(["c"]));
+        assert_eq!(target, json!(["c"]));
+    }
+
+    #[test]
+    fn test_if_match() {
+        assert_eq!(if_match(&headers(None)), None);
+        assert_eq!(if_match(&headers(Some("*"))), Some(IfMatch::Any));
+        let condition = if_match(&headers(Some("\"1\", \"3\""))).unwrap();
+        assert!(condition.matches(3));
+        assert!(!condition.matches(2));
+        assert!(!if_match(&headers(Some("W/\"1\""))).unwrap().matches(1));
+    }
+
+    #[tokio::test]
+    async fn test_put_and_patch() {
+        // HTTP слой проверяется без БД
+        let repo: Repository = Arc::new(MemoryRepository::new());
+        let state = Arc::new(OrderCache::new(AppState::new()));
+        let shutdown = Shutdown::new();
+        let put = |body: Value, if_match: Option<&'static str>| {
+            put_order(
+                state.clone(),
+                Path(UID.to_string()),
+                headers(if_match),
+                Ok(RawJson {
+                    value: serde_json::from_value(body.clone()).unwrap(),
+                    raw: body.to_string(),
+                }),
+                repo.clone(),
+                shutdown.clone(),
+            )
+        };
+        let patch = |uid: &str, body: Value, if_match: Option<&'static str>| {
+            patch_order(
+                state.clone(),
+                Path(uid.to_string()),
+                headers(if_match),
+                Ok(Json(body)),
+                repo.clone(),
+                shutdown.clone(),
+            )
+        };
+
+        // С If-Match несуществующий заказ не создается, без него — создается
+        let error = put(model1(), Some("\"1\"")).await.unwrap_err();
+        assert_eq!(error.code(), "version_mismatch");
+        let (status, etag, body) = body_json(put(model1(), None).await.unwrap()).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(etag.as_deref(), Some("\"1\""));
+        assert_eq!(body, model1());
+
+        // Замена с совпадающей версией
+        let mut replaced = model1();
+        replaced["delivery"]["city"] = "Moscow".into();
+        replaced["items"][0]["status"] = 203.into();
+        let (status, etag, _) =
+            body_json(put(replaced.clone(), Some("\"1\"")).await.unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(etag.as_deref(), Some("\"2\""));
+        let stored = repo.get(UID).await.unwrap().unwrap();
+        assert_eq!(serde_json::to_value(&stored).unwrap(), replaced);
+        assert_eq!(stored.version, 2);
+
+        // Устаревшая версия отклоняется
+        let response = put(model1(), Some("\"1\""))
+            .await
+            .unwrap_err()
+            .into_response();
+        let (status, _, body) = body_json(response).await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+        assert_eq!(body["etag"], "\"2\"");
+
+        // order_uid в теле должен совпадать с путем
+        let mut other = model1();
+        other["order_uid"] = "other".into();
+        assert_eq!(put(other, None).await.unwrap_err().code(), "invalid_order");
+
+        // Merge patch меняет адрес и статусы товаров; кэш и БД обновляются вместе
+        let (status, etag, body) = body_json(
+            patch(
+                UID,
+                json!({"delivery": {"address": "Tverskaya 1"}, "items": [replaced["items"][0].clone()]}),
+                None,
+            )
+            .await
+            .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(etag.as_deref(), Some("\"3\""));
+        assert_eq!(body["delivery"]["address"], "Tverskaya 1");
+        assert_eq!(body["delivery"]["city"], "Moscow");
+        let cached = state.read().get_order(UID).unwrap();
+        assert_eq!(cached.delivery.address, "Tverskaya 1");
+        assert_eq!(cached.version, 3);
+        let stored = repo.get(UID).await.unwrap().unwrap();
+        assert_eq!(serde_json::to_value(&stored).unwrap(), body);
+
+        // Хэш содержимого обновлен: повтор нового содержимого распознается
+        let resent: Order = serde_json::from_value(body.clone()).unwrap();
+        assert!(matches!(
+            crate::ingest_order(&*repo, &state, resent, &body.to_string())
+                .await
+                .unwrap(),
+            crate::Ingestion::Replayed
+        ));
+
+        // Ошибки PATCH
+        let error = patch(UID, json!({"sm_id": 1}), Some("\"2\""))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), "version_mismatch");
+        let error = patch(UID, json!({"order_uid": "other"}), None)
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), "invalid_order");
+        let error = patch(UID, json!({"payment": {"amount": 1}}), None)
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), "validation_failed");
+        let error = patch(UID, json!({"delivery": null}), None)
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), "invalid_order");
+        let error = patch("missing", json!({}), None).await.unwrap_err();
+        assert_eq!(error.code(), "order_not_found");
+        let (status, etag, _) =
+            body_json(patch(UID, json!({"sm_id": 1}), Some("*")).await.unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(etag.as_deref(), Some("\"4\""));
+    }
+
+    #[tokio::test]
+    async fn test_delete_order() {
+        let (pool, _schema) = setup_database().await;
+        let repo: Repository = Arc::new(PgRepository::new(pool.clone()));
+        let state = Arc::new(OrderCache::new(AppState::new()));
+        let shutdown = Shutdown::new();
+        for file_path in ["models/model1.json", "models/model2.json"] {
+            let raw = std::fs::read_to_string(file_path).unwrap();
+            let order = serde_json::from_str(&raw).unwrap();
+            crate::ingest_order(&*repo, &state, order, &raw)
+                .await
+                .unwrap();
+        }
+        repo.save_key("key-1", UID, "hash").await.unwrap();
+        let delete = |uid: &str, if_match: Option<&'static str>| {
+            delete_order(
+                state.clone(),
+                Path(uid.to_string()),
+                headers(if_match),
+                repo.clone(),
+                shutdown.clone(),
+            )
+        };
+        let count = |table: &'static str| {
+            let pool = pool.clone();
+            async move {
+                let count: (i64,) = sqlx::query_as(&format!("SELECT COUNT(*) FROM {}", table))
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap();
+                count.0
+            }
+        };
+
+        let error = delete(UID, Some("\"2\"")).await.unwrap_err();
+        assert_eq!(error.code(), "version_mismatch");
+        assert_eq!(
+            delete(UID, Some("\"1\"")).await.unwrap(),
+            StatusCode::NO_CONTENT
+        );
+
+        // удалены все строки заказа, второй заказ не затронут
+        assert!(state.read().get_order(UID).is_none());
+        assert_eq!(state.read().len(), 1);
+        assert!(repo.get(UID).await.unwrap().is_none());
+        for table in ["orders", "delivery", "payment"] {
+            assert_eq!(count(table).await, 1);
+        }
+        let model2_items = state.read().orders().next().unwrap().items.len() as i64;
+        assert_eq!(count("item").await, model2_items);
+        assert_eq!(count("idempotency_keys").await, 0);
+
+        assert_eq!(
+            delete(UID, None).await.unwrap_err().code(),
+            "order_not_found"
+        );
+    }
+}
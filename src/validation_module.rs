@@ -0,0 +1,258 @@
+use crate::db_module::Order;
+use serde::Serialize;
+
+// Коды валют ISO 4217
+static ISO_4217_CURRENCIES: &[&str] = &[
+    "AED", "AFN", "ALL", "AMD", "ANG", "AOA", "ARS", "AUD", "AWG", "AZN", "BAM", "BBD", "BDT",
+    "BGN", "BHD", "BIF", "BMD", "BND", "BOB", "BOV", "BRL", "BSD", "BTN", "BWP", "BYN", "BZD",
+    "CAD", "CDF", "CHE", "CHF", "CHW", "CLF", "CLP", "CNY", "COP", "COU", "CRC", "CUC", "CUP",
+    "CVE", "CZK", "DJF", "DKK", "DOP", "DZD", "EGP", "ERN", "ETB", "EUR", "FJD", "FKP", "GBP",
+    "GEL", "GHS", "GIP", "GMD", "GNF", "GTQ", "GYD", "HKD", "HNL", "HTG", "HUF", "IDR", "ILS",
+    "INR", "IQD", "IRR", "ISK", "JMD", "JOD", "JPY", "KES", "KGS", "KHR", "KMF", "KPW", "KRW",
+    "KWD", "KYD", "KZT", "LAK", "LBP", "LKR", "LRD", "LSL", "LYD", "MAD", "MDL", "MGA", "MKD",
+    "MMK", "MNT", "MOP", "MRU", "MUR", "MVR", "MWK", "MXN", "MXV", "MYR", "MZN", "NAD", "NGN",
+    "NIO", "NOK", "NPR", "NZD", "OMR", "PAB", "PEN", "PGK", "PHP", "PKR", "PLN", "PYG", "QAR",
+    "RON", "RSD", "RUB", "RWF", "SAR", "SBD", "SCR", "SDG", "SEK", "SGD", "SHP", "SLE", "SLL",
+    "SOS", "SRD", "SSP", "STN", "SVC", "SYP", "SZL", "THB", "TJS", "TMT", "TND", "TOP", "TRY",
+    "TTD", "TWD", "TZS", "UAH", "UGX", "USD", "USN", "UYI", "UYU", "UYW", "UZS", "VED", "VES",
+    "VND", "VUV", "WST", "XAF", "XAG", "XAU", "XBA", "XBB", "XBC", "XBD", "XCD", "XDR", "XOF",
+    "XPD", "XPF", "XPT", "XSU", "XTS", "XUA", "XXX", "YER", "ZAR", "ZMW", "ZWL",
+];
+
+// Нарушенное правило: путь к полю, идентификатор правила и описание
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub field: String,
+    pub rule: &'static str,
+    pub message: String,
+}
+
+impl Violation {
+    fn new(field: impl Into<String>, rule: &'static str, message: String) -> Self {
+        Violation {
+            field: field.into(),
+            rule,
+            message,
+        }
+    }
+}
+
+// Проверка бизнес-правил заказа, возвращает список всех нарушений
+pub fn validate_order(order: &Order) -> Result<(), Vec<Violation>> {
+    let mut violations = Vec::new();
+    let payment = &order.payment;
+
+    if order.items.is_empty() {
+        violations.push(Violation::new(
+            "items",
+            "items_not_empty",
+            "order must contain at least one item".to_string(),
+        ));
+    }
+
+    // суммы считаются в i64, чтобы исключить переполнение
+    let items_total: i64 = order.items.iter().map(|item| item.total_price as i64).sum();
+    if payment.goods_total as i64 != items_total {
+        violations.push(Violation::new(
+            "payment.goods_total",
+            "goods_total_matches_items",
+            format!(
+                "goods_total {} does not equal the sum of item total_price {}",
+                payment.goods_total, items_total
+            ),
+        ));
+    }
+
+    let expected_amount =
+        payment.goods_total as i64 + payment.delivery_cost as i64 + payment.custom_fee as i64;
+    if payment.amount as i64 != expected_amount {
+        violations.push(Violation::new(
+            "payment.amount",
+            "amount_matches_total",
+            format!(
+                "amount {} does not equal goods_total + delivery_cost + custom_fee = {}",
+                payment.amount, expected_amount
+            ),
+        ));
+    }
+
+    for (index, item) in order.items.iter().enumerate() {
+        if item.track_number != order.track_number {
+            violations.push(Violation::new(
+                format!("items[{}].track_number", index),
+                "item_track_number_matches_order",
+                format!(
+                    "item track_number '{}' does not match order track_number '{}'",
+                    item.track_number, order.track_number
+                ),
+            ));
+        }
+    }
+
+    if !ISO_4217_CURRENCIES.contains(&payment.currency.as_str()) {
+        violations.push(Violation::new(
+            "payment.currency",
+            "currency_iso_4217",
+            format!("'{}' is not an ISO 4217 currency code", payment.currency),
+        ));
+    }
+
+    if chrono::DateTime::parse_from_rfc3339(&order.date_created).is_err() {
+        violations.push(Violation::new(
+            "date_created",
+            "date_created_rfc_3339",
+            format!("'{}' is not an RFC 3339 date", order.date_created),
+        ));
+    }
+
+    // длина строк ограничена размером колонок VARCHAR(n) в БД
+    let delivery = &order.delivery;
+    let mut lengths: Vec<(String, &str, usize)> = [
+        ("order_uid", &order.order_uid, 255),
+        ("track_number", &order.track_number, 255),
+        ("entry", &order.entry, 255),
+        ("locale", &order.locale, 10),
+        ("internal_signature", &order.internal_signature, 255),
+        ("customer_id", &order.customer_id, 255),
+        ("delivery_service", &order.delivery_service, 100),
+        ("shardkey", &order.shardkey, 50),
+        ("date_created", &order.date_created, 50),
+        ("oof_shard", &order.oof_shard, 50),
+        ("delivery.name", &delivery.name, 255),
+        ("delivery.phone", &delivery.phone, 50),
+        ("delivery.zip", &delivery.zip, 20),
+        ("delivery.city", &delivery.city, 100),
+        ("delivery.address", &delivery.address, 255),
+        ("delivery.region", &delivery.region, 100),
+        ("delivery.email", &delivery.email, 100),
+        ("payment.transaction", &payment.transaction, 255),
+        ("payment.request_id", &payment.request_id, 255),
+        ("payment.currency", &payment.currency, 10),
+        ("payment.provider", &payment.provider, 100),
+        ("payment.bank", &payment.bank, 100),
+    ]
+    .into_iter()
+    .map(|(field, value, limit)| (field.to_string(), value.as_str(), limit))
+    .collect();
+    for (index, item) in order.items.iter().enumerate() {
+        for (field, value, limit) in [
+            ("track_number", &item.track_number, 255),
+            ("rid", &item.rid, 255),
+            ("name", &item.name, 255),
+            ("size", &item.size, 50),
+            ("brand", &item.brand, 100),
+        ] {
+            lengths.push((format!("items[{}].{}", index, field), value, limit));
+        }
+    }
+    for (field, value, limit) in lengths {
+        let length = value.chars().count();
+        if length > limit {
+            violations.push(Violation::new(
+                field,
+                "max_length",
+                format!(
+                    "length {} exceeds the limit of {} characters",
+                    length, limit
+                ),
+            ));
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+// Тесты
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_order(file_path: &str) -> Order {
+        let content = std::fs::read_to_string(file_path).expect("Unable to read file");
+        serde_json::from_str(&content).expect("JSON was not well-formatted")
+    }
+
+    fn rules(violations: &[Violation]) -> Vec<&'static str> {
+        violations.iter().map(|violation| violation.rule).collect()
+    }
+
+    #[test]
+    fn test_valid_models() {
+        for file_path in [
+            "models/model1.json",
+            "models/model2.json",
+            "models/model3.json",
+            "models/model4.json",
+            "models/model5.json",
+            "models/model_extended.json",
+        ] {
+            assert_eq!(
+                validate_order(&load_order(file_path)),
+                Ok(()),
+                "{}",
+                file_path
+            );
+        }
+    }
+
+    #[test]
+    fn test_all_violations_are_reported() {
+        let mut order = load_order("models/model_extended.json");
+        order.payment.goods_total += 1;
+        order.payment.currency = "usd".to_string();
+        order.items[1].track_number = "OTHER".to_string();
+        order.date_created = "26.11.2021".to_string();
+
+        let violations = validate_order(&order).unwrap_err();
+        assert_eq!(
+            rules(&violations),
+            vec![
+                "goods_total_matches_items",
+                "amount_matches_total",
+                "item_track_number_matches_order",
+                "currency_iso_4217",
+                "date_created_rfc_3339",
+            ]
+        );
+        assert_eq!(violations[2].field, "items[1].track_number");
+    }
+
+    #[test]
+    fn test_empty_items() {
+        let mut order = load_order("models/model1.json");
+        order.items.clear();
+        order.payment.goods_total = 0;
+        order.payment.amount = order.payment.delivery_cost + order.payment.custom_fee;
+
+        let violations = validate_order(&order).unwrap_err();
+        assert_eq!(rules(&violations), vec!["items_not_empty"]);
+        assert_eq!(violations[0].field, "items");
+    }
+
+    // Строки не длиннее колонок БД; длина считается в символах, как у VARCHAR(n)
+    #[test]
+    fn test_max_length() {
+        let mut order = load_order("models/model_extended.json");
+        order.delivery.zip = "я".repeat(20);
+        order.items[1].size = "x".repeat(50);
+        assert_eq!(validate_order(&order), Ok(()));
+
+        order.delivery.zip.push('я');
+        order.items[1].size.push('x');
+        order.payment.bank = "b".repeat(101);
+        let violations = validate_order(&order).unwrap_err();
+        assert_eq!(rules(&violations), vec!["max_length"; 3]);
+        let fields: Vec<&str> = violations
+            .iter()
+            .map(|violation| violation.field.as_str())
+            .collect();
+        assert_eq!(
+            fields,
+            vec!["delivery.zip", "payment.bank", "items[1].size"]
+        );
+    }
+}